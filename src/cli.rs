@@ -1,4 +1,10 @@
-use clap::{Parser, Subcommand};
+use crate::{
+    diff::Algorithm,
+    objects::{DiffFormat, DiffOptions, GraphStyle, LogFormat, LogOptions},
+    rename::RenameOptions,
+};
+use clap::{Args, Parser, Subcommand};
+use regex::Regex;
 
 // CLI commands structure represented as a data structure
 
@@ -21,23 +27,261 @@ pub enum Subcommands {
     Commit {
         message: String,
     },
-    Status,
+    /// Shows the changes between HEAD, the index and the working directory
+    Status {
+        /// Machine-readable output in git's porcelain format, v1 or v2
+        #[arg(long, value_name = "VERSION", num_args = 0..=1, default_missing_value = "v1", value_parser = ["v1", "v2"])]
+        porcelain: Option<String>,
+        /// Machine-readable output as JSON
+        #[arg(long, conflicts_with = "porcelain")]
+        json: bool,
+        /// Shows the branch and its upstream in porcelain formats
+        #[arg(short, long)]
+        branch: bool,
+        /// Terminates porcelain lines with null bytes instead of newlines and doesn't quote paths
+        #[arg(short = 'z')]
+        nul_terminated: bool,
+    },
+    /// Shows unstaged changes, staged changes with --cached, or the changes between commits
+    Diff {
+        /// Up to two commits or trees to compare, followed by the files and directories to limit
+        /// the diff to
+        args: Vec<String>,
+        /// Files and directories to limit the diff to, for paths which look like commits
+        #[arg(last = true)]
+        paths: Vec<String>,
+        /// Compares the index with HEAD or the given commit instead of the working directory
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+        #[command(flatten)]
+        options: DiffArgs,
+    },
+    /// Switches to a branch, or restores a commit into a new directory if a path is given
     Checkout {
-        sha: String,
-        path: String,
+        target: String,
+        path: Option<String>,
+    },
+    /// Switches to a branch, updating the working directory and the index
+    Switch {
+        target: String,
+        /// Switches to a commit instead of a branch, detaching HEAD
+        #[arg(short, long)]
+        detach: bool,
+    },
+    /// Lists, creates, deletes or renames branches
+    Branch {
+        /// Branch to create or delete, the new name when renaming the current branch
+        name: Option<String>,
+        /// Commit the new branch starts at, or the new name when renaming name
+        start: Option<String>,
+        /// Deletes the branch if it is merged into HEAD
+        #[arg(short, long, conflicts_with_all = ["force_delete", "rename"])]
+        delete: bool,
+        /// Deletes the branch even if it isn't merged
+        #[arg(short = 'D', conflicts_with = "rename")]
+        force_delete: bool,
+        /// Renames a branch
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+    },
+    /// Merges a branch or commit into the current branch
+    Merge {
+        target: String,
     },
-    Branch,
-    Log,
+    /// Prints which paths are ignored by .gitignore files or exclude files
+    CheckIgnore {
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Prints the file, line number and pattern deciding whether each path is ignored
+        #[arg(short, long)]
+        verbose: bool,
+        /// Also prints paths not matching any pattern, requires verbose
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+    },
+    /// Shows the history of HEAD, optionally with the changes of each commit
+    Log {
+        #[command(flatten)]
+        options: LogArgs,
+    },
+    /// Moves objects of a flat objects directory into the two-level fan-out layout
+    Migrate,
+    /// Packs all reachable objects into a packfile and removes the redundant loose objects
+    Repack,
+    /// Like repack, but also removes unreachable loose objects
+    Gc,
     Dev {
         #[command(subcommand)]
         command: DevSubcommands,
     },
 }
 
+/// Options shared by all commands showing diffs
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Number of unchanged lines shown around each change
+    #[arg(
+        short = 'U',
+        long = "unified",
+        value_name = "LINES",
+        default_value_t = 3
+    )]
+    pub context: usize,
+    /// Algorithm computing the diff, defaults to the diff.algorithm config or myers
+    #[arg(long, value_name = "ALGORITHM", value_parser = ["myers", "minimal", "patience", "histogram"])]
+    pub diff_algorithm: Option<String>,
+    /// Shows the number of changed lines of each file with a histogram instead of a patch
+    #[arg(long, conflicts_with_all = ["numstat", "name_status", "word_diff"])]
+    pub stat: bool,
+    /// Shows the numbers of added and deleted lines of each file instead of a patch
+    #[arg(long, conflicts_with_all = ["name_status", "word_diff"])]
+    pub numstat: bool,
+    /// Shows the paths of the changed files with whether they were added, modified or deleted
+    #[arg(long, conflicts_with = "word_diff")]
+    pub name_status: bool,
+    /// Shows which words of the changed lines were deleted and inserted
+    #[arg(long)]
+    pub word_diff: bool,
+    /// Detects renamed files which are at least this similar, 50% by default
+    #[arg(short = 'M', long, value_name = "N", num_args = 0..=1, default_missing_value = "50%", value_parser = RenameOptions::parse_threshold)]
+    pub find_renames: Option<usize>,
+    /// Also detects files copied from modified files, with the same threshold as renames
+    #[arg(short = 'C', long, value_name = "N", num_args = 0..=1, default_missing_value = "50%", value_parser = RenameOptions::parse_threshold)]
+    pub find_copies: Option<usize>,
+    /// Turns off rename detection, which is on by default unless the diff.renames config is false
+    #[arg(long, conflicts_with_all = ["find_renames", "find_copies"])]
+    pub no_renames: bool,
+}
+
+impl DiffArgs {
+    /// Diff options with the defaults of the configs for everything not given on the command line
+    pub fn options(&self) -> DiffOptions {
+        let format = match (self.stat, self.numstat, self.name_status, self.word_diff) {
+            (true, ..) => DiffFormat::Stat,
+            (_, true, ..) => DiffFormat::Numstat,
+            (_, _, true, _) => DiffFormat::NameStatus,
+            (.., true) => DiffFormat::WordDiff,
+            _ => DiffFormat::Patch,
+        };
+        let renames = match (self.no_renames, self.find_renames, self.find_copies) {
+            (true, ..) => None,
+            (_, threshold, Some(copies)) => {
+                Some(RenameOptions::new(threshold.or(Some(copies)), true))
+            }
+            (_, Some(threshold), None) => Some(RenameOptions::new(Some(threshold), false)),
+            _ => RenameOptions::configured(&["diff.renames"]),
+        };
+
+        DiffOptions {
+            context: self.context,
+            algorithm: self
+                .diff_algorithm
+                .as_deref()
+                .and_then(Algorithm::from_name)
+                .unwrap_or_else(Algorithm::configured),
+            format,
+            renames,
+        }
+    }
+}
+
+/// Options selecting and formatting the commits of a log
+#[derive(Args)]
+pub struct LogArgs {
+    /// Only shows commits changing these files or directories
+    paths: Vec<String>,
+    /// Shows the changes of each commit as a diff to its first parent
+    #[arg(short, long)]
+    patch: bool,
+    /// Only shows commits changing a file, following it across renames
+    #[arg(long, value_name = "PATH", conflicts_with = "paths")]
+    follow: Option<String>,
+    /// Shows at most this many commits
+    #[arg(short = 'n', long, value_name = "NUMBER")]
+    max_count: Option<usize>,
+    /// Shows each commit on a single line with its abbreviated sha and subject
+    #[arg(long, conflicts_with = "format")]
+    oneline: bool,
+    /// Format of each commit, medium, oneline or a template of placeholders: %H and %h for the
+    /// sha, %an, %ae and %ad for the author's name, email and date, %s and %b for the subject
+    /// and body of the message
+    #[arg(long, visible_alias = "pretty", value_name = "FORMAT", value_parser = LogFormat::parse)]
+    format: Option<LogFormat>,
+    /// Shows the oldest commits first
+    #[arg(long, conflicts_with = "graph")]
+    reverse: bool,
+    /// Draws the history as a graph next to the commits, with ascii or unicode characters
+    #[arg(long, value_name = "STYLE", num_args = 0..=1, require_equals = true, default_missing_value = "ascii", value_parser = ["ascii", "unicode"], conflicts_with = "follow")]
+    graph: Option<String>,
+    /// Shows the history of all branches and tags, not only of HEAD
+    #[arg(long)]
+    all: bool,
+    /// Shows the branches and tags pointing to each commit, by default only on a terminal
+    /// unless the log.decorate config says otherwise
+    #[arg(long, overrides_with = "no_decorate")]
+    decorate: bool,
+    /// Doesn't show the branches and tags pointing to each commit
+    #[arg(long, overrides_with = "decorate")]
+    no_decorate: bool,
+    /// Only shows commits made at or after a date, like 2024-01-31 or "2 weeks ago"
+    #[arg(long, visible_alias = "after", value_name = "DATE", value_parser = LogOptions::parse_time)]
+    since: Option<i64>,
+    /// Only shows commits made at or before a date
+    #[arg(long, visible_alias = "before", value_name = "DATE", value_parser = LogOptions::parse_time)]
+    until: Option<i64>,
+    /// Only shows commits whose author's name or email matches the pattern, a basic regular
+    /// expression like in git, repeat it to allow several authors
+    #[arg(long, value_name = "PATTERN", value_parser = LogOptions::parse_pattern)]
+    author: Vec<Regex>,
+    /// Only shows commits whose message matches the pattern, repeat it to allow several ones
+    #[arg(long, value_name = "PATTERN", value_parser = LogOptions::parse_pattern)]
+    grep: Vec<Regex>,
+    #[command(flatten)]
+    diff: DiffArgs,
+}
+
+impl LogArgs {
+    pub fn options(&self) -> LogOptions {
+        let diff = self.diff.options();
+        let format = match (self.oneline, &self.format) {
+            (true, _) => LogFormat::Oneline { abbrev: true },
+            (_, Some(format)) => format.to_owned(),
+            _ => LogFormat::Medium,
+        };
+
+        LogOptions {
+            diff,
+            show_changes: self.patch || diff.format != DiffFormat::Patch,
+            format,
+            decorate: match (self.decorate, self.no_decorate) {
+                (true, _) => true,
+                (_, true) => false,
+                _ => LogOptions::decorate_by_default(),
+            },
+            graph: self.graph.as_deref().and_then(GraphStyle::from_name),
+            all: self.all,
+            follow: self.follow.to_owned(),
+            paths: self.paths.to_owned(),
+            max_count: self.max_count,
+            reverse: self.reverse,
+            since: self.since,
+            until: self.until,
+            authors: self.author.to_owned(),
+            greps: self.grep.to_owned(),
+        }
+    }
+}
+
 #[derive(Subcommand)]
 pub enum DevSubcommands {
     Clean,
     BuildTree,
-    DbgTree { sha: String },
-    DbgCommit { sha: String },
+    DbgTree {
+        sha: String,
+    },
+    DbgCommit {
+        sha: String,
+    },
+    /// Prints the index as JSON
+    DumpIndex,
 }
@@ -1,7 +1,10 @@
 use crate::{
-    objects::{Commit, Head, Index, Tree},
+    objects::{
+        Checkout, Commit, Diff, DiffOptions, FanOutMigration, Head, Index, LogOptions, Merge,
+        Repack, Signature, Status, Tag, Tree,
+    },
     storing::{Object, Storable},
-    DirBuilder,
+    DirBuilder, IgnoreFilter,
 };
 
 pub enum CommandReturnType {
@@ -23,44 +26,390 @@ impl Commands {
         CommandReturnType::Storable(Box::new(index))
     }
 
-    pub fn status() -> CommandReturnType {
+    pub fn status(
+        porcelain: Option<String>,
+        json: bool,
+        branch: bool,
+        nul_terminated: bool,
+    ) -> CommandReturnType {
+        // Like git, status refreshes the stat data of the index while comparing it
+        let mut index = Index::new_from_index_file();
+        index.refresh();
+        let status = Status::new(&index);
+
+        match porcelain.as_deref() {
+            Some("v2") => print!("{}", status.porcelain_v2(branch, nul_terminated)),
+            Some(_) => print!("{}", status.porcelain_v1(branch, nul_terminated)),
+            None if json => println!("{}", status.to_json()),
+            None => print!("{}", status),
+        }
+
+        CommandReturnType::Storable(Box::new(index))
+    }
+
+    /// Leading arguments which resolve to commits or trees are compared, the others select the
+    /// paths to compare like git does
+    pub fn diff(
+        args: Vec<String>,
+        mut paths: Vec<String>,
+        cached: bool,
+        options: DiffOptions,
+    ) -> CommandReturnType {
+        let mut trees = Vec::new();
+        let mut args = args.into_iter().peekable();
+        while let Some(tree) = args.peek().and_then(|rev| Tree::from_rev(rev)) {
+            trees.push(tree);
+            args.next();
+        }
+        paths.splice(0..0, args);
+
         let index = Index::new_from_index_file();
-        index.status();
+        let head_tree = || match Head::read_HEAD().commit() {
+            Some(commit) => commit.tree().to_owned(),
+            None => Tree::new("ROOT", Vec::new()),
+        };
+
+        let diff = match (trees.len(), cached) {
+            (0, false) => Diff::wd(&index, &paths, options),
+            (0, true) => Diff::cached(&head_tree(), &index, &paths, options),
+            (1, false) => Diff::tree_to_wd(&trees[0], &index, &paths, options),
+            (1, true) => Diff::cached(&trees[0], &index, &paths, options),
+            (2, false) => Diff::trees(&trees[0], &trees[1], &paths, options),
+            _ => {
+                eprintln!(
+                    "Too many commits to compare, at most two can be compared without --cached"
+                );
+                return CommandReturnType::NonStorable;
+            }
+        };
+        print!("{}", diff);
 
         CommandReturnType::NonStorable
     }
 
     pub fn commit(message: String) -> CommandReturnType {
         let index = Index::new_from_index_file();
+        if index.has_conflicts() {
+            eprintln!("Committing is not possible because you have unmerged files:");
+            for path in index.conflicts() {
+                eprintln!("\t{}", path);
+            }
+            eprintln!("Fix them up in the working tree, and then use 'rgit add <file>'.");
+            return CommandReturnType::NonStorable;
+        }
         let tree = Tree::from(index);
 
         let head = Head::read_HEAD();
-        let parent = head.commit().to_owned();
+        // Concluding a merge with conflicts records the merged commit as second parent
+        let mut parents: Vec<String> = head.commit().iter().map(|x| x.sha()).collect();
+        parents.extend(Merge::in_progress());
+
+        let Some((author, committer)) = Commands::signatures() else {
+            return CommandReturnType::NonStorable;
+        };
 
-        let commit = Commit::new(tree, parent, message);
+        let commit = Commit::new(tree, parents, author, committer, message);
 
         head.update(&commit);
+        Merge::finish();
 
         CommandReturnType::Storable(Box::new(commit))
     }
 
-    pub fn checkout(sha: String, path: String) -> CommandReturnType {
-        let commit = Commit::new_from_object_file(&sha, None);
+    /// Returns the author and committer signatures for a new commit
+    fn signatures() -> Option<(Signature, Signature)> {
+        let (Some(author), Some(committer)) = (Signature::author(), Signature::committer()) else {
+            eprintln!("Author identity unknown, set user.name and user.email in .rgit/config or");
+            eprintln!(
+                "~/.rgitconfig, or the RGIT_AUTHOR_* and RGIT_COMMITTER_* environment variables"
+            );
+            return None;
+        };
+
+        Some((author, committer))
+    }
+
+    pub fn checkout(target: String, path: Option<String>) -> CommandReturnType {
+        let Some(path) = path else {
+            // Anything which isn't a branch is checked out as a detached HEAD
+            let detach = !Head::exists(&target);
+            return Commands::switch(target, detach);
+        };
+
+        let Some(commit) = Commit::from_rev(&target) else {
+            eprintln!("Not a valid commit: '{}'", target);
+            return CommandReturnType::NonStorable;
+        };
         commit.restore(path);
 
         CommandReturnType::NonStorable
     }
 
-    pub fn log() -> CommandReturnType {
+    /// Switches to a branch, or to a detached HEAD at any commit if detach is set
+    pub fn switch(target: String, detach: bool) -> CommandReturnType {
+        let target = if detach {
+            Commit::from_rev(&target).map(Head::detached)
+        } else {
+            Head::try_read(&target)
+        };
+
+        let Some(target) = target else {
+            eprintln!("Not a valid branch or commit");
+            return CommandReturnType::NonStorable;
+        };
+
+        if Index::new_from_index_file().has_conflicts() {
+            eprintln!("You need to resolve your current index first");
+            return CommandReturnType::NonStorable;
+        }
+
+        if target.is_current() {
+            println!("Already on '{}'", target.name().unwrap());
+            return CommandReturnType::NonStorable;
+        }
+
+        match Checkout::new(target) {
+            Ok(checkout) => CommandReturnType::Storable(Box::new(checkout)),
+            Err(conflicts) => {
+                eprintln!("Your local changes to the following files would be overwritten:");
+                for path in conflicts {
+                    eprintln!("\t{}", path);
+                }
+                eprintln!("Please commit your changes before you switch branches.");
+
+                CommandReturnType::NonStorable
+            }
+        }
+    }
+
+    /// Merges a branch or commit into the current head, fast-forwarding if possible
+    pub fn merge(target: String) -> CommandReturnType {
+        if Merge::in_progress().is_some() {
+            eprintln!("You have not concluded your merge (MERGE_HEAD exists).");
+            eprintln!("Please, commit your changes before you merge.");
+            return CommandReturnType::NonStorable;
+        }
+
+        let Some(theirs) = Commit::from_rev(&target) else {
+            eprintln!("merge: {} - not something we can merge", target);
+            return CommandReturnType::NonStorable;
+        };
+
         let head = Head::read_HEAD();
-        let commit = head.commit().to_owned();
+        let ours = match head.commit() {
+            Some(ours) if theirs.is_ancestor_of(ours) => {
+                println!("Already up to date.");
+                return CommandReturnType::NonStorable;
+            }
+            Some(ours) if !ours.is_ancestor_of(&theirs) => ours,
+            // Without diverging history the head only has to move forward
+            ours => {
+                if let Some(ours) = ours {
+                    println!("Updating {}..{}", &ours.sha()[..7], &theirs.sha()[..7]);
+                }
+
+                return match Checkout::fast_forward(theirs) {
+                    Ok(checkout) => CommandReturnType::Storable(Box::new(checkout)),
+                    Err(conflicts) => Commands::print_merge_conflicts(conflicts),
+                };
+            }
+        };
+
+        let Some((author, committer)) = Commands::signatures() else {
+            return CommandReturnType::NonStorable;
+        };
+
+        let base = ours.merge_base(&theirs);
+        let mut merge = match Merge::new(theirs, base, &target) {
+            Ok(merge) => merge,
+            Err(conflicts) => return Commands::print_merge_conflicts(conflicts),
+        };
+
+        let kind = if Head::exists(&target) {
+            "branch"
+        } else {
+            "commit"
+        };
+        merge.commit(author, committer, format!("Merge {} '{}'", kind, target));
+
+        CommandReturnType::Storable(Box::new(merge))
+    }
+
+    fn print_merge_conflicts(conflicts: Vec<String>) -> CommandReturnType {
+        eprintln!("Your local changes to the following files would be overwritten by merge:");
+        for path in conflicts {
+            eprintln!("\t{}", path);
+        }
+        eprintln!("Please commit your changes before you merge.");
+
+        CommandReturnType::NonStorable
+    }
+
+    /// Prints the given paths which are ignored, verbose also prints the pattern deciding it,
+    /// even if it's a negated one re-including the path
+    pub fn check_ignore(
+        paths: Vec<String>,
+        verbose: bool,
+        non_matching: bool,
+    ) -> CommandReturnType {
+        let ignore_filter = IgnoreFilter::new();
+
+        for path in paths {
+            match ignore_filter.matching_pattern(&path) {
+                Some(pattern) if verbose => println!("{}\t{}", pattern, path),
+                Some(pattern) if !pattern.is_negated() => println!("{}", path),
+                None if non_matching => println!("::\t{}", path),
+                _ => (),
+            }
+        }
+
+        CommandReturnType::NonStorable
+    }
+
+    /// Shows the history of HEAD, with the changes of each commit if a patch or another diff
+    /// format is requested
+    pub fn log(options: LogOptions) -> CommandReturnType {
+        let mut commits = Vec::new();
+        if options.all {
+            commits.extend(Head::all().iter().filter_map(|x| x.commit().to_owned()));
+            commits.extend(Tag::all().iter().filter_map(|x| x.commit()));
+        }
+        commits.extend(Head::read_HEAD().commit().to_owned());
+
+        Commit::log(commits, &options);
+
+        CommandReturnType::NonStorable
+    }
+
+    /// Dispatches the branch subcommand depending on the given flags
+    pub fn branch(
+        name: Option<String>,
+        start: Option<String>,
+        delete: bool,
+        force_delete: bool,
+        rename: bool,
+    ) -> CommandReturnType {
+        match (name, start) {
+            (Some(_), Some(_)) if delete || force_delete => {
+                eprintln!("Only one branch can be deleted at once");
+                CommandReturnType::NonStorable
+            }
+            (Some(name), None) if delete || force_delete => {
+                Commands::delete_branch(name, force_delete)
+            }
+            (Some(name), Some(new_name)) if rename => Commands::rename_branch(Some(name), new_name),
+            (Some(new_name), None) if rename => Commands::rename_branch(None, new_name),
+            (Some(name), start) => Commands::create_branch(name, start),
+            (None, _) if delete || force_delete || rename => {
+                eprintln!("Branch name required");
+                CommandReturnType::NonStorable
+            }
+            (None, _) => Commands::list_branches(),
+        }
+    }
+
+    fn list_branches() -> CommandReturnType {
+        let current = Head::read_HEAD();
+        if current.is_detached() {
+            println!("* ({})", current.describe());
+        }
+
+        for head in Head::all() {
+            let marker = if head.is_current() { "*" } else { " " };
+            println!("{} {}", marker, head.name().unwrap());
+        }
+
+        CommandReturnType::NonStorable
+    }
+
+    fn create_branch(name: String, start: Option<String>) -> CommandReturnType {
+        if !Head::is_valid_name(&name) {
+            eprintln!("'{}' is not a valid branch name", name);
+            return CommandReturnType::NonStorable;
+        }
+        if Head::exists(&name) {
+            eprintln!("A branch named '{}' already exists", name);
+            return CommandReturnType::NonStorable;
+        }
+
+        let start = start.unwrap_or(String::from("HEAD"));
+        let Some(commit) = Commit::from_rev(&start) else {
+            eprintln!("Not a valid commit: '{}'", start);
+            return CommandReturnType::NonStorable;
+        };
+
+        CommandReturnType::Storable(Box::new(Head::new(name, commit)))
+    }
 
-        if let Some(commit) = commit {
-            commit.log();
+    fn delete_branch(name: String, force: bool) -> CommandReturnType {
+        let Some(head) = Head::try_read(&name) else {
+            eprintln!("Branch '{}' not found", name);
+            return CommandReturnType::NonStorable;
+        };
+
+        if head.is_current() {
+            eprintln!(
+                "Cannot delete branch '{}', it is currently checked out",
+                name
+            );
+            return CommandReturnType::NonStorable;
+        }
+
+        let is_merged = match (head.commit(), Head::read_HEAD().commit()) {
+            (None, _) => true,
+            (Some(commit), Some(current)) => commit.is_ancestor_of(current),
+            (Some(_), None) => false,
+        };
+
+        if !is_merged && !force {
+            eprintln!(
+                "The branch '{}' is not fully merged.\nIf you are sure you want to delete it, run 'rgit branch -D {}'",
+                name, name
+            );
+            return CommandReturnType::NonStorable;
+        }
+
+        head.delete();
+        match head.commit() {
+            Some(commit) => println!("Deleted branch {} (was {})", name, &commit.sha()[..7]),
+            None => println!("Deleted branch {}", name),
         }
 
         CommandReturnType::NonStorable
     }
+
+    /// Renames a branch, or the current one if no old name is given
+    fn rename_branch(name: Option<String>, new_name: String) -> CommandReturnType {
+        let head = match name {
+            Some(name) => Head::try_read(&name),
+            None => Some(Head::read_HEAD()),
+        };
+        let Some(head) = head.filter(|x| !x.is_detached()) else {
+            eprintln!("Branch not found");
+            return CommandReturnType::NonStorable;
+        };
+
+        if !Head::is_valid_name(&new_name) {
+            eprintln!("'{}' is not a valid branch name", new_name);
+            return CommandReturnType::NonStorable;
+        }
+        if Head::exists(&new_name) {
+            eprintln!("A branch named '{}' already exists", new_name);
+            return CommandReturnType::NonStorable;
+        }
+
+        head.rename(&new_name);
+
+        CommandReturnType::NonStorable
+    }
+
+    pub fn migrate() -> CommandReturnType {
+        CommandReturnType::Storable(Box::new(FanOutMigration::new()))
+    }
+
+    pub fn repack(prune: bool) -> CommandReturnType {
+        CommandReturnType::Storable(Box::new(Repack::new(prune)))
+    }
 }
 
 pub struct DevCommands;
@@ -91,4 +440,11 @@ impl DevCommands {
 
         CommandReturnType::NonStorable
     }
+
+    pub fn dump_index() -> CommandReturnType {
+        let index = Index::new_from_index_file();
+        println!("{}", index.to_json_string());
+
+        CommandReturnType::NonStorable
+    }
 }
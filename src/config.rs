@@ -0,0 +1,76 @@
+use crate::Paths;
+use std::{collections::HashMap, fs};
+
+/// Values of git style config files, keys are written as `section.key` or
+/// `section.subsection.key`
+pub struct Config {
+    values: HashMap<String, String>,
+}
+
+impl Config {
+    /// Loads the global config file followed by the repository's, so that repository values win
+    pub fn load() -> Config {
+        let mut config = Config {
+            values: HashMap::new(),
+        };
+
+        for path in [Paths::global_config(), Some(Paths::config())]
+            .into_iter()
+            .flatten()
+        {
+            if let Ok(content) = fs::read_to_string(path) {
+                config.parse(&content);
+            }
+        }
+
+        config
+    }
+
+    /// Section and key names are case insensitive, subsection names aren't
+    fn normalize_key(key: &str) -> String {
+        match (key.find('.'), key.rfind('.')) {
+            (Some(first), Some(last)) if first != last => format!(
+                "{}.{}.{}",
+                key[..first].to_lowercase(),
+                &key[first + 1..last],
+                key[last + 1..].to_lowercase()
+            ),
+            _ => key.to_lowercase(),
+        }
+    }
+
+    fn parse(&mut self, content: &str) {
+        let mut section = String::new();
+
+        for line in content.lines().map(|x| x.trim()) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+                section = match header.split_once(' ') {
+                    Some((name, subsection)) => {
+                        format!("{}.{}", name.trim(), subsection.trim().trim_matches('"'))
+                    }
+                    None => header.trim().to_owned(),
+                };
+                continue;
+            }
+
+            // A key without a value is a boolean set to true
+            let (key, value) = line.split_once('=').unwrap_or((line, "true"));
+            let value = value.trim().trim_matches('"');
+
+            self.values.insert(
+                Config::normalize_key(&format!("{}.{}", section, key.trim())),
+                value.to_owned(),
+            );
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .get(&Config::normalize_key(key))
+            .map(|x| x.as_str())
+    }
+}
@@ -0,0 +1,995 @@
+use crate::Config;
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    ops::Range,
+};
+
+// Git only looks at the start of a file to decide whether it is binary
+const BINARY_CHECK_LEN: usize = 8000;
+// Histogram diff doesn't use lines occurring more often than this as anchors, like git
+const MAX_CHAIN_LEN: usize = 64;
+// Myers searches which aren't minimal always get at least this many steps before git cuts
+// them short
+const MIN_MAX_COST: usize = 256;
+
+// Weights of git's indent heuristic, which places ambiguous changes by the indentation and blank
+// lines around them. The values are the ones git tuned on a corpus of real world diffs
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+const MAX_SLIDING: usize = 100;
+const START_OF_FILE_PENALTY: isize = 1;
+const END_OF_FILE_PENALTY: isize = 21;
+const TOTAL_BLANK_WEIGHT: isize = -30;
+const POST_BLANK_WEIGHT: isize = 6;
+const RELATIVE_INDENT_PENALTY: isize = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: isize = 10;
+const RELATIVE_OUTDENT_PENALTY: isize = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: isize = 17;
+const RELATIVE_DEDENT_PENALTY: isize = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: isize = 17;
+const INDENT_WEIGHT: isize = 60;
+
+/// Single step of an edit script turning one sequence into another, holding the indices of the
+/// elements in the old and new sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Splits content into lines, each keeping its line terminator
+pub fn lines(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes.split_inclusive(|x| *x == b'\n').collect()
+}
+
+/// Splits text into words, i.e. runs of non-whitespace characters, returning their byte ranges
+pub fn words(bytes: &[u8]) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+
+    for (i, byte) in bytes.iter().enumerate() {
+        match (byte.is_ascii_whitespace(), start) {
+            (true, Some(word_start)) => {
+                words.push(word_start..i);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => (),
+        }
+    }
+    words.extend(start.map(|word_start| word_start..bytes.len()));
+
+    words
+}
+
+/// Computes the edit script between two sequences of words the way git's word diff does, with
+/// Myers and without the indent heuristic, which is only meaningful for lines
+pub fn word_edits(old: &[&[u8]], new: &[&[u8]]) -> Vec<Edit> {
+    compact(old, new, &myers(old, new), false)
+}
+
+/// Treats content as binary if it contains a null byte, like git does
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// Computes the shortest edit script between two sequences with Myers' O(ND) algorithm
+pub fn myers<T: Eq + Hash>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    myers_range(old, new, 0..old.len(), 0..new.len(), true, &mut edits);
+    edits
+}
+
+/// Git's cost limit of a Myers search, roughly the square root of the number of diagonals
+fn max_cost(len: usize) -> usize {
+    let mut cost = 1;
+    let mut rest = len + 3;
+    while rest > 0 {
+        rest >>= 2;
+        cost <<= 1;
+    }
+
+    cost.max(MIN_MAX_COST)
+}
+
+/// Myers' algorithm on two ranges. Like in git, elements which don't occur in the other range
+/// at all are set aside as deleted or inserted before searching, which keeps diffs between
+/// unrelated files cheap. Unless the script has to be minimal, searches taking more than git's
+/// cost limit are cut short
+fn myers_range<T: Eq + Hash>(
+    old: &[T],
+    new: &[T],
+    a: Range<usize>,
+    b: Range<usize>,
+    minimal: bool,
+    edits: &mut Vec<Edit>,
+) {
+    let in_old: HashSet<&T> = old[a.clone()].iter().collect();
+    let in_new: HashSet<&T> = new[b.clone()].iter().collect();
+    let kept_old: Vec<usize> = a.clone().filter(|i| in_new.contains(&old[*i])).collect();
+    let kept_new: Vec<usize> = b.clone().filter(|j| in_old.contains(&new[*j])).collect();
+    let old_kept: Vec<&T> = kept_old.iter().map(|i| &old[*i]).collect();
+    let new_kept: Vec<&T> = kept_new.iter().map(|j| &new[*j]).collect();
+
+    let mut kept_edits = Vec::new();
+    let cost = (!minimal).then(|| max_cost(old_kept.len() + new_kept.len()));
+    bisect(
+        &old_kept,
+        &new_kept,
+        0..old_kept.len(),
+        0..new_kept.len(),
+        cost,
+        &mut kept_edits,
+    );
+
+    // Elements set aside are deleted or inserted right before the next kept element
+    let (mut i, mut j) = (a.start, b.start);
+    for edit in kept_edits {
+        match edit {
+            Edit::Equal(x, y) => {
+                let (x, y) = (kept_old[x], kept_new[y]);
+                edits.extend((i..x).map(Edit::Delete));
+                edits.extend((j..y).map(Edit::Insert));
+                edits.push(Edit::Equal(x, y));
+                (i, j) = (x + 1, y + 1);
+            }
+            Edit::Delete(x) => {
+                edits.extend((i..=kept_old[x]).map(Edit::Delete));
+                i = kept_old[x] + 1;
+            }
+            Edit::Insert(y) => {
+                edits.extend((j..=kept_new[y]).map(Edit::Insert));
+                j = kept_new[y] + 1;
+            }
+        }
+    }
+    edits.extend((i..a.end).map(Edit::Delete));
+    edits.extend((j..b.end).map(Edit::Insert));
+}
+
+/// Linear space variant of Myers' algorithm, like git's: splits the ranges at a point the
+/// shortest edit script passes through and recurses on both halves
+fn bisect<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    a: Range<usize>,
+    b: Range<usize>,
+    max_cost: Option<usize>,
+    edits: &mut Vec<Edit>,
+) {
+    trimmed(old, new, a, b, edits, |a, b, edits| {
+        let (x, y) = middle_snake(old, new, a.clone(), b.clone(), max_cost);
+        bisect(old, new, a.start..x, b.start..y, max_cost, edits);
+        bisect(old, new, x..a.end, y..b.end, max_cost, edits);
+    });
+}
+
+/// Finds the point where the shortest edit script between two trimmed, non-empty ranges is
+/// halfway done, by extending the furthest reaching paths of every diagonal k = x - y from the
+/// start and from the end at once until they meet. After max_cost steps the search gives up and
+/// returns the end of the path which got furthest, the script isn't minimal then
+fn middle_snake<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    a: Range<usize>,
+    b: Range<usize>,
+    max_cost: Option<usize>,
+) -> (usize, usize) {
+    let (off1, lim1) = (a.start as isize, a.end as isize);
+    let (off2, lim2) = (b.start as isize, b.end as isize);
+    let (dmin, dmax) = (off1 - lim2, lim1 - off2);
+    let (fmid, bmid) = (off1 - off2, lim1 - lim2);
+    let odd = (fmid - bmid) & 1 != 0;
+    let (mut fmin, mut fmax, mut bmin, mut bmax) = (fmid, fmid, bmid, bmid);
+
+    // Furthest x reached on every diagonal, with room for a sentinel on both ends which makes
+    // paths never come from outside of the ranges
+    let index = |k: isize| (k - dmin + 1) as usize;
+    let len = index(dmax) + 2;
+    let (mut forward, mut backward) = (vec![-1; len], vec![isize::MAX; len]);
+    forward[index(fmid)] = off1;
+    backward[index(bmid)] = lim1;
+    let same = |x: isize, y: isize| old[x as usize] == new[y as usize];
+
+    for cost in 1.. {
+        if fmin > dmin {
+            fmin -= 1;
+            forward[index(fmin - 1)] = -1;
+        } else {
+            fmin += 1;
+        }
+        if fmax < dmax {
+            fmax += 1;
+            forward[index(fmax + 1)] = -1;
+        } else {
+            fmax -= 1;
+        }
+
+        for k in (fmin..=fmax).rev().step_by(2) {
+            let mut x = if forward[index(k - 1)] >= forward[index(k + 1)] {
+                forward[index(k - 1)] + 1
+            } else {
+                forward[index(k + 1)]
+            };
+            let mut y = x - k;
+            while x < lim1 && y < lim2 && same(x, y) {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+
+            if odd && (bmin..=bmax).contains(&k) && backward[index(k)] <= x {
+                return (x as usize, y as usize);
+            }
+        }
+
+        if bmin > dmin {
+            bmin -= 1;
+            backward[index(bmin - 1)] = isize::MAX;
+        } else {
+            bmin += 1;
+        }
+        if bmax < dmax {
+            bmax += 1;
+            backward[index(bmax + 1)] = isize::MAX;
+        } else {
+            bmax -= 1;
+        }
+
+        for k in (bmin..=bmax).rev().step_by(2) {
+            let mut x = if backward[index(k - 1)] < backward[index(k + 1)] {
+                backward[index(k - 1)]
+            } else {
+                backward[index(k + 1)] - 1
+            };
+            let mut y = x - k;
+            while x > off1 && y > off2 && same(x - 1, y - 1) {
+                x -= 1;
+                y -= 1;
+            }
+            backward[index(k)] = x;
+
+            if !odd && (fmin..=fmax).contains(&k) && x <= forward[index(k)] {
+                return (x as usize, y as usize);
+            }
+        }
+
+        if max_cost.is_some_and(|max_cost| cost >= max_cost) {
+            // Ends of the forward and backward paths which got furthest, clipped to the ranges
+            let (mut fbest, mut fbest_x) = (-1, -1);
+            for k in (fmin..=fmax).rev().step_by(2) {
+                let mut x = forward[index(k)].min(lim1);
+                if x - k > lim2 {
+                    x = lim2 + k;
+                }
+                if fbest < 2 * x - k {
+                    (fbest, fbest_x) = (2 * x - k, x);
+                }
+            }
+            let (mut bbest, mut bbest_x) = (isize::MAX, isize::MAX);
+            for k in (bmin..=bmax).rev().step_by(2) {
+                let mut x = backward[index(k)].max(off1);
+                if x - k < off2 {
+                    x = off2 + k;
+                }
+                if 2 * x - k < bbest {
+                    (bbest, bbest_x) = (2 * x - k, x);
+                }
+            }
+
+            return if (lim1 + lim2) - bbest < fbest - (off1 + off2) {
+                (fbest_x as usize, (fbest - fbest_x) as usize)
+            } else {
+                (bbest_x as usize, (bbest - bbest_x) as usize)
+            };
+        }
+    }
+
+    unreachable!("Paths from both ends meet after at most as many steps as there are elements")
+}
+
+/// Algorithm computing the edit scripts of diffs and merges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Algorithm {
+    #[default]
+    Myers,
+    Minimal,
+    Patience,
+    Histogram,
+}
+
+impl Algorithm {
+    pub fn from_name(name: &str) -> Option<Algorithm> {
+        match name.to_lowercase().as_str() {
+            "myers" | "default" => Some(Algorithm::Myers),
+            "minimal" => Some(Algorithm::Minimal),
+            "patience" => Some(Algorithm::Patience),
+            "histogram" => Some(Algorithm::Histogram),
+            _ => None,
+        }
+    }
+
+    /// Reads the algorithm from the diff.algorithm config, defaults to Myers
+    pub fn configured() -> Algorithm {
+        match Config::load().get("diff.algorithm") {
+            Some(name) => Algorithm::from_name(name).unwrap_or_else(|| {
+                eprintln!("Unknown diff.algorithm {}, using myers", name);
+                Algorithm::Myers
+            }),
+            None => Algorithm::Myers,
+        }
+    }
+
+    /// Computes the edit script turning one sequence into another
+    pub fn diff<T: Eq + Hash + AsRef<[u8]>>(&self, old: &[T], new: &[T]) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        let (a, b) = (0..old.len(), 0..new.len());
+
+        match self {
+            Algorithm::Myers => myers_range(old, new, a, b, false, &mut edits),
+            Algorithm::Minimal => myers_range(old, new, a, b, true, &mut edits),
+            Algorithm::Patience => patience(old, new, a, b, &mut edits),
+            Algorithm::Histogram => histogram(old, new, a, b, &mut edits),
+        }
+
+        compact(old, new, &edits, true)
+    }
+}
+
+/// Matches the common prefix and suffix of two ranges and calls split for what remains in
+/// between, unless one of the remaining ranges is empty
+fn trimmed<T: PartialEq>(
+    old: &[T],
+    new: &[T],
+    mut a: Range<usize>,
+    mut b: Range<usize>,
+    edits: &mut Vec<Edit>,
+    split: impl FnOnce(Range<usize>, Range<usize>, &mut Vec<Edit>),
+) {
+    while !a.is_empty() && !b.is_empty() && old[a.start] == new[b.start] {
+        edits.push(Edit::Equal(a.start, b.start));
+        a.start += 1;
+        b.start += 1;
+    }
+
+    let mut suffix = Vec::new();
+    while !a.is_empty() && !b.is_empty() && old[a.end - 1] == new[b.end - 1] {
+        a.end -= 1;
+        b.end -= 1;
+        suffix.push(Edit::Equal(a.end, b.end));
+    }
+
+    if a.is_empty() || b.is_empty() {
+        edits.extend(a.map(Edit::Delete));
+        edits.extend(b.map(Edit::Insert));
+    } else {
+        split(a, b, edits);
+    }
+
+    edits.extend(suffix.into_iter().rev());
+}
+
+/// Patience diff, anchors the script at the longest increasing sequence of lines occurring
+/// exactly once in both ranges and diffs the gaps between them recursively
+fn patience<T: Eq + Hash>(
+    old: &[T],
+    new: &[T],
+    a: Range<usize>,
+    b: Range<usize>,
+    edits: &mut Vec<Edit>,
+) {
+    trimmed(old, new, a, b, edits, |a, b, edits| {
+        // Occurrences and last index of each line in the old and the new range
+        let mut counts: HashMap<&T, (usize, usize, usize, usize)> = HashMap::new();
+        for i in a.clone() {
+            let count = counts.entry(&old[i]).or_default();
+            (count.0, count.1) = (count.0 + 1, i);
+        }
+        for j in b.clone() {
+            let count = counts.entry(&new[j]).or_default();
+            (count.2, count.3) = (count.2 + 1, j);
+        }
+
+        let mut unique: Vec<(usize, usize)> = counts
+            .into_values()
+            .filter(|count| count.0 == 1 && count.2 == 1)
+            .map(|count| (count.1, count.3))
+            .collect();
+        if unique.is_empty() {
+            return myers_range(old, new, a, b, false, edits);
+        }
+        unique.sort();
+
+        let (mut i, mut j) = (a.start, b.start);
+        for (x, y) in longest_increasing(&unique) {
+            patience(old, new, i..x, j..y, edits);
+            edits.push(Edit::Equal(x, y));
+            (i, j) = (x + 1, y + 1);
+        }
+        patience(old, new, i..a.end, j..b.end, edits);
+    });
+}
+
+/// Finds the longest sequence of pairs, sorted by their first element, whose second elements
+/// are increasing too
+fn longest_increasing(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // Index of the pair ending the best sequence of each length, and each pair's predecessor
+    let mut tails: Vec<usize> = Vec::new();
+    let mut predecessors = vec![None; pairs.len()];
+
+    for (i, (_, y)) in pairs.iter().enumerate() {
+        let len = tails.partition_point(|tail| pairs[*tail].1 < *y);
+        predecessors[i] = len.checked_sub(1).map(|x| tails[x]);
+        match tails.get_mut(len) {
+            Some(tail) => *tail = i,
+            None => tails.push(i),
+        }
+    }
+
+    let mut sequence = Vec::new();
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        sequence.push(pairs[i]);
+        current = predecessors[i];
+    }
+
+    sequence.reverse();
+    sequence
+}
+
+/// Histogram diff, anchors the script at the common region whose rarest line occurs least often
+/// in the old range and diffs the ranges around it recursively
+fn histogram<T: Eq + Hash>(
+    old: &[T],
+    new: &[T],
+    a: Range<usize>,
+    b: Range<usize>,
+    edits: &mut Vec<Edit>,
+) {
+    trimmed(old, new, a, b, edits, |a, b, edits| {
+        let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+        for i in a.clone() {
+            occurrences.entry(&old[i]).or_default().push(i);
+        }
+
+        // Rarity, start in the old and new range and length of the best common region
+        let mut best: Option<(usize, usize, usize, usize)> = None;
+        let mut j = b.start;
+        while j < b.end {
+            let mut next_j = j + 1;
+
+            for &i in occurrences
+                .get(&new[j])
+                .filter(|x| x.len() <= MAX_CHAIN_LEN)
+                .into_iter()
+                .flatten()
+            {
+                let (mut start_i, mut start_j) = (i, j);
+                while start_i > a.start && start_j > b.start && old[start_i - 1] == new[start_j - 1]
+                {
+                    (start_i, start_j) = (start_i - 1, start_j - 1);
+                }
+                let (mut end_i, mut end_j) = (i + 1, j + 1);
+                while end_i < a.end && end_j < b.end && old[end_i] == new[end_j] {
+                    (end_i, end_j) = (end_i + 1, end_j + 1);
+                }
+
+                let rarity = (start_i..end_i)
+                    .map(|x| occurrences[&old[x]].len())
+                    .min()
+                    .unwrap();
+                let len = end_i - start_i;
+                if best.is_none_or(|x| rarity < x.0 || (rarity == x.0 && len > x.3)) {
+                    best = Some((rarity, start_i, start_j, len));
+                }
+                next_j = next_j.max(end_j);
+            }
+
+            j = next_j;
+        }
+
+        match best {
+            Some((_, i, j, len)) => {
+                histogram(old, new, a.start..i, b.start..j, edits);
+                edits.extend((0..len).map(|x| Edit::Equal(i + x, j + x)));
+                histogram(old, new, i + len..a.end, j + len..b.end, edits);
+            }
+            None => myers_range(old, new, a, b, false, edits),
+        }
+    });
+}
+
+/// Slides each group of changed lines as far down as it can go while staying a valid script,
+/// or to the last position where it lines up with changes of the other sequence, like git does.
+/// Ambiguous changes so end up in the same place for every algorithm
+fn compact<T: PartialEq + AsRef<[u8]>>(
+    old: &[T],
+    new: &[T],
+    edits: &[Edit],
+    indent_heuristic: bool,
+) -> Vec<Edit> {
+    let mut deleted = vec![false; old.len()];
+    let mut inserted = vec![false; new.len()];
+    for edit in edits {
+        match *edit {
+            Edit::Delete(i) => deleted[i] = true,
+            Edit::Insert(j) => inserted[j] = true,
+            Edit::Equal(..) => (),
+        }
+    }
+
+    slide(old, &mut deleted, &gaps(&inserted), indent_heuristic);
+    slide(new, &mut inserted, &gaps(&deleted), indent_heuristic);
+
+    let mut edits = Vec::with_capacity(edits.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && deleted[i] {
+            edits.push(Edit::Delete(i));
+            i += 1;
+        } else if j < new.len() && inserted[j] {
+            edits.push(Edit::Insert(j));
+            j += 1;
+        } else {
+            edits.push(Edit::Equal(i, j));
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+
+    edits
+}
+
+/// Tells for every number of unchanged lines whether changed lines follow right after them
+fn gaps(changed: &[bool]) -> Vec<bool> {
+    let mut gaps = vec![false; changed.iter().filter(|x| !**x).count() + 1];
+    let mut unchanged = 0;
+    for is_changed in changed {
+        match is_changed {
+            true => gaps[unchanged] = true,
+            false => unchanged += 1,
+        }
+    }
+
+    gaps
+}
+
+/// Moves runs of changed lines, a run can move down by one line if the line after it equals
+/// its first line and up by one if the line before it equals its last, which keeps the
+/// unchanged lines the same
+fn slide<T: PartialEq + AsRef<[u8]>>(
+    lines: &[T],
+    changed: &mut [bool],
+    other_gaps: &[bool],
+    indent_heuristic: bool,
+) {
+    let (mut start, mut unchanged) = (0, 0);
+
+    while start < lines.len() {
+        if !changed[start] {
+            (start, unchanged) = (start + 1, unchanged + 1);
+            continue;
+        }
+
+        let mut end = start;
+        let mut earliest_end;
+        let mut aligned_end;
+        loop {
+            while end < lines.len() && changed[end] {
+                end += 1;
+            }
+            let len = end - start;
+
+            // Runs reaching another run while moving merge with it
+            while start > 0 && !changed[start - 1] && lines[start - 1] == lines[end - 1] {
+                (changed[start - 1], changed[end - 1]) = (true, false);
+                (start, end, unchanged) = (start - 1, end - 1, unchanged - 1);
+                while start > 0 && changed[start - 1] {
+                    start -= 1;
+                }
+            }
+            earliest_end = end;
+            aligned_end = other_gaps[unchanged].then_some(end);
+
+            while end < lines.len() && lines[start] == lines[end] {
+                (changed[start], changed[end]) = (false, true);
+                (start, end, unchanged) = (start + 1, end + 1, unchanged + 1);
+                while end < lines.len() && changed[end] {
+                    end += 1;
+                }
+                if other_gaps[unchanged] {
+                    aligned_end = Some(end);
+                }
+            }
+
+            if end - start == len {
+                break;
+            }
+        }
+
+        let target_end = match aligned_end {
+            _ if end == earliest_end => end,
+            Some(aligned_end) => aligned_end,
+            None if indent_heuristic => best_split(lines, earliest_end, end, end - start),
+            None => end,
+        };
+        while end > target_end {
+            (changed[start - 1], changed[end - 1]) = (true, false);
+            (start, end, unchanged) = (start - 1, end - 1, unchanged - 1);
+        }
+
+        start = end;
+    }
+}
+
+/// Width of a line's leading whitespace with tabs expanded, Option::None for blank lines
+fn indent(line: &[u8]) -> Option<isize> {
+    let mut indent = 0;
+    for byte in line {
+        match byte {
+            b' ' => indent += 1,
+            b'\t' => indent += 8 - indent % 8,
+            x if x.is_ascii_whitespace() => (),
+            _ => return Some(indent),
+        }
+        if indent >= MAX_INDENT {
+            return Some(MAX_INDENT);
+        }
+    }
+
+    None
+}
+
+/// Scores splitting lines before an index by the indentation and blank lines around the split,
+/// returning the indentation and a penalty, lower is better for both
+fn split_score<T: AsRef<[u8]>>(lines: &[T], split: usize) -> (isize, isize) {
+    let at = lines.get(split).and_then(|x| indent(x.as_ref()));
+
+    // Number of blank lines and indentation of the first non-blank line before and after
+    let surrounding = |lines: &mut dyn Iterator<Item = &T>| {
+        let mut blanks = 0;
+        for line in lines {
+            if let Some(indent) = indent(line.as_ref()) {
+                return (blanks, Some(indent));
+            }
+            blanks += 1;
+            if blanks == MAX_BLANKS {
+                return (blanks, Some(0));
+            }
+        }
+        (blanks, None)
+    };
+    let (pre_blank, pre_indent) = surrounding(&mut lines[..split].iter().rev());
+    let (post_blank, post_indent) = surrounding(&mut lines.iter().skip(split + 1));
+
+    let mut penalty = 0;
+    if pre_indent.is_none() && pre_blank == 0 {
+        penalty += START_OF_FILE_PENALTY;
+    }
+    if split >= lines.len() {
+        penalty += END_OF_FILE_PENALTY;
+    }
+
+    let post_blank = if at.is_none() { 1 + post_blank } else { 0 };
+    let total_blank = pre_blank + post_blank;
+    penalty += TOTAL_BLANK_WEIGHT * total_blank + POST_BLANK_WEIGHT * post_blank;
+
+    let indent = if split < lines.len() {
+        at.or(post_indent)
+    } else {
+        None
+    };
+    let with_blank = total_blank != 0;
+    match (indent, pre_indent) {
+        (Some(indent), Some(pre_indent)) if indent > pre_indent => {
+            penalty += match with_blank {
+                true => RELATIVE_INDENT_WITH_BLANK_PENALTY,
+                false => RELATIVE_INDENT_PENALTY,
+            }
+        }
+        (Some(indent), Some(pre_indent)) if indent < pre_indent => {
+            penalty += match (post_indent.is_some_and(|x| x > indent), with_blank) {
+                (true, true) => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+                (true, false) => RELATIVE_OUTDENT_PENALTY,
+                (false, true) => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+                (false, false) => RELATIVE_DEDENT_PENALTY,
+            }
+        }
+        _ => (),
+    }
+
+    (indent.unwrap_or(-1), penalty)
+}
+
+/// Picks the end of a run of changed lines which can slide between two ends with git's indent
+/// heuristic, preferring the lowest position among equally good ones
+fn best_split<T: AsRef<[u8]>>(lines: &[T], earliest_end: usize, end: usize, len: usize) -> usize {
+    let first = earliest_end
+        .max((end - len).saturating_sub(1))
+        .max(end.saturating_sub(MAX_SLIDING));
+
+    let mut best: Option<(usize, (isize, isize))> = None;
+    for split in first..=end {
+        let (end_indent, end_penalty) = split_score(lines, split);
+        let (start_indent, start_penalty) = split_score(lines, split - len);
+        let score = (end_indent + start_indent, end_penalty + start_penalty);
+
+        let better = best.is_none_or(|(_, best)| {
+            INDENT_WEIGHT * (score.0 - best.0).signum() + (score.1 - best.1) <= 0
+        });
+        if better {
+            best = Some((split, score));
+        }
+    }
+
+    best.map_or(end, |(split, _)| split)
+}
+
+/// Part of an edit script holding a group of nearby changes with their surrounding context,
+/// starting at the given line indices of the old and new sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub edits: Vec<Edit>,
+}
+
+/// Groups the changes of an edit script into hunks with up to context unchanged lines around
+/// them, changes separated by at most twice the context are kept in the same hunk like git does
+pub fn hunks(edits: &[Edit], context: usize) -> Vec<Hunk> {
+    let changes: Vec<usize> = (0..edits.len())
+        .filter(|i| !matches!(edits[*i], Edit::Equal(..)))
+        .collect();
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for i in changes {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last - 1 <= 2 * context => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    // Positions in the old and new sequence before each edit
+    let mut positions = Vec::with_capacity(edits.len());
+    let (mut old, mut new) = (0, 0);
+    for edit in edits {
+        positions.push((old, new));
+        match edit {
+            Edit::Equal(..) => (old, new) = (old + 1, new + 1),
+            Edit::Delete(_) => old += 1,
+            Edit::Insert(_) => new += 1,
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(first, last)| {
+            let edits =
+                &edits[first.saturating_sub(context)..(last + context + 1).min(edits.len())];
+            let (old_start, new_start) = positions[first.saturating_sub(context)];
+
+            Hunk {
+                old_start,
+                old_len: edits
+                    .iter()
+                    .filter(|x| !matches!(x, Edit::Insert(_)))
+                    .count(),
+                new_start,
+                new_len: edits
+                    .iter()
+                    .filter(|x| !matches!(x, Edit::Delete(_)))
+                    .count(),
+                edits: edits.to_vec(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALGORITHMS: [Algorithm; 4] = [
+        Algorithm::Myers,
+        Algorithm::Minimal,
+        Algorithm::Patience,
+        Algorithm::Histogram,
+    ];
+
+    /// Pairs of short sequences over a small alphabet, so that they share many elements
+    fn sequences() -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut state: u32 = 1;
+        let mut next = |len: u32| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) % len
+        };
+
+        (0..200)
+            .map(|_| {
+                let old = (0..next(12)).map(|_| b'a' + next(3) as u8).collect();
+                let new = (0..next(12)).map(|_| b'a' + next(3) as u8).collect();
+                (old, new)
+            })
+            .collect()
+    }
+
+    /// Checks that an edit script turns old into new, returning the number of changes
+    fn check<T: PartialEq + std::fmt::Debug>(old: &[T], new: &[T], edits: &[Edit]) -> usize {
+        let (mut o, mut n) = (0, 0);
+        for edit in edits {
+            match *edit {
+                Edit::Equal(i, j) => {
+                    assert_eq!((i, j), (o, n));
+                    assert_eq!(old[i], new[j]);
+                    (o, n) = (o + 1, n + 1);
+                }
+                Edit::Delete(i) => {
+                    assert_eq!(i, o);
+                    o += 1;
+                }
+                Edit::Insert(j) => {
+                    assert_eq!(j, n);
+                    n += 1;
+                }
+            }
+        }
+        assert_eq!((o, n), (old.len(), new.len()));
+
+        edits
+            .iter()
+            .filter(|x| !matches!(x, Edit::Equal(..)))
+            .count()
+    }
+
+    /// Number of changes of the shortest edit script, from the longest common subsequence
+    fn shortest<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+        let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in 0..old.len() {
+            for j in 0..new.len() {
+                lengths[i + 1][j + 1] = match old[i] == new[j] {
+                    true => lengths[i][j] + 1,
+                    false => lengths[i][j + 1].max(lengths[i + 1][j]),
+                };
+            }
+        }
+
+        old.len() + new.len() - 2 * lengths[old.len()][new.len()]
+    }
+
+    fn diff_lines(algorithm: Algorithm, old: &str, new: &str) -> Vec<Edit> {
+        algorithm.diff(&lines(old.as_bytes()), &lines(new.as_bytes()))
+    }
+
+    #[test]
+    fn myers_finds_shortest_edit_script() {
+        for (old, new) in sequences() {
+            assert_eq!(check(&old, &new, &myers(&old, &new)), shortest(&old, &new));
+        }
+    }
+
+    #[test]
+    fn diffs_large_files_without_common_lines() {
+        let old: Vec<String> = (0..100_000).map(|i| format!("old {}\n", i)).collect();
+        let new: Vec<String> = (0..100_000).map(|i| format!("new {}\n", i)).collect();
+
+        for algorithm in [Algorithm::Myers, Algorithm::Minimal] {
+            assert_eq!(check(&old, &new, &algorithm.diff(&old, &new)), 200_000);
+        }
+    }
+
+    #[test]
+    fn cuts_expensive_myers_searches_short() {
+        // Every line is common, but only one of them can be kept in order
+        let old: Vec<String> = (0..5_000).map(|i| format!("{}\n", i)).collect();
+        let new: Vec<String> = old.iter().rev().cloned().collect();
+
+        check(&old, &new, &Algorithm::Myers.diff(&old, &new));
+    }
+
+    #[test]
+    fn algorithms_produce_valid_edit_scripts() {
+        for (old, new) in sequences() {
+            let (old, new): (Vec<[u8; 1]>, Vec<[u8; 1]>) = (
+                old.iter().map(|x| [*x]).collect(),
+                new.iter().map(|x| [*x]).collect(),
+            );
+            for algorithm in ALGORITHMS {
+                let changes = check(&old, &new, &algorithm.diff(&old, &new));
+                if matches!(algorithm, Algorithm::Myers | Algorithm::Minimal) {
+                    assert_eq!(changes, shortest(&old, &new));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_inserted_function_together() {
+        let old = "void func1() {\n    x += 1\n}\n\nvoid func2() {\n    x += 2\n}\n";
+        let new = "void func1() {\n    x += 1\n}\n\nvoid functhreehalves() {\n    x += 1.5\n}\n\nvoid func2() {\n    x += 2\n}\n";
+
+        for algorithm in ALGORITHMS {
+            let edits = diff_lines(algorithm, old, new);
+            let inserted: Vec<Edit> = (4..8).map(Edit::Insert).collect();
+            assert_eq!(edits[4..8], inserted, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn histogram_matches_git() {
+        let edits = diff_lines(
+            Algorithm::Histogram,
+            "a\nb\nc\na\nb\nb\na\n",
+            "c\nb\na\nb\na\nc\n",
+        );
+
+        assert_eq!(
+            edits,
+            [
+                Edit::Delete(0),
+                Edit::Delete(1),
+                Edit::Equal(2, 0),
+                Edit::Delete(3),
+                Edit::Delete(4),
+                Edit::Equal(5, 1),
+                Edit::Equal(6, 2),
+                Edit::Insert(3),
+                Edit::Insert(4),
+                Edit::Insert(5),
+            ]
+        );
+    }
+
+    #[test]
+    fn slides_changes_like_git() {
+        // Each way to place the inserted lines ends up where git shows them
+        let cases: [(&str, &str, &[usize]); 2] = [
+            ("a\n\nb\n", "a\n\nc\n\nb\n", &[2, 3]),
+            (
+                "  if x:\n    y\n\n  z\n",
+                "  if x:\n    y\n\n  if w:\n    y\n\n  z\n",
+                &[3, 4, 5],
+            ),
+        ];
+
+        for (old, new, expected) in cases {
+            let (old, new) = (lines(old.as_bytes()), lines(new.as_bytes()));
+            let inserted = expected.len();
+
+            for start in 1..=expected[0] {
+                let mut edits: Vec<Edit> = (0..start).map(|x| Edit::Equal(x, x)).collect();
+                edits.extend((start..start + inserted).map(Edit::Insert));
+                edits.extend((start..old.len()).map(|x| Edit::Equal(x, x + inserted)));
+                check(&old, &new, &edits);
+
+                let edits = compact(&old, &new, &edits, true);
+                let inserts: Vec<usize> = edits
+                    .iter()
+                    .filter_map(|x| match x {
+                        Edit::Insert(j) => Some(*j),
+                        _ => None,
+                    })
+                    .collect();
+                assert_eq!(inserts, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn groups_nearby_changes_into_hunks() {
+        let old: Vec<u8> = (0..20).collect();
+        let mut new = old.clone();
+        new[2] = 100;
+        new[8] = 100;
+        new[17] = 100;
+
+        let hunks = hunks(&myers(&old, &new), 3);
+        let ranges: Vec<_> = hunks
+            .iter()
+            .map(|x| (x.old_start, x.old_len, x.new_start, x.new_len))
+            .collect();
+        assert_eq!(ranges, [(0, 12, 0, 12), (14, 6, 14, 6)]);
+    }
+}
@@ -14,7 +14,7 @@ impl DirBuilder {
 
 impl Storable for DirBuilder {
     fn store(&self) {
-        if path::Path::new(&Paths::root()).exists() == true {
+        if path::Path::new(&Paths::root()).exists() {
             println!("Working directory is already a repository");
             return;
         }
@@ -22,13 +22,11 @@ impl Storable for DirBuilder {
         println!("Initlizing repository...");
 
         for dir in Paths::dirs() {
-            fs::create_dir_all(&dir).expect(&format!("Failed to create directory {}", dir));
+            fs::create_dir_all(&dir)
+                .unwrap_or_else(|_| panic!("Failed to create directory {}", dir));
         }
 
-        fs::write(Paths::HEAD(), "main").unwrap();
-        fs::File::create(Paths::heads() + "/main").unwrap();
-
-        fs::File::create(Paths::index())
-            .expect(&format!("Failed to create directory {}", Paths::index()));
+        // The main branch is unborn until the first commit creates its ref file
+        fs::write(Paths::HEAD(), "ref: refs/heads/main\n").unwrap();
     }
 }
@@ -0,0 +1,14 @@
+/// Encodes raw bytes, e.g. a binary sha, as a lowercase hex string
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+/// Decodes a hex string, e.g. a sha, into its raw bytes
+pub fn decode(hex: &str) -> Vec<u8> {
+    assert_eq!(hex.len() % 2, 0, "{} has an odd length", hex);
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("Invalid hex string"))
+        .collect()
+}
@@ -1,4 +1,8 @@
 pub const BLOB: &str = "blob";
 pub const TREE: &str = "tree";
 pub const COMMIT: &str = "commit";
+pub const TAG: &str = "tag";
 pub const PARENT: &str = "parent";
+pub const AUTHOR: &str = "author";
+pub const COMMITTER: &str = "committer";
+pub const ENCODING: &str = "encoding";
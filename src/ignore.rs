@@ -1,22 +1,525 @@
-use std::{convert::AsRef, fs, path};
+use crate::{Config, Paths};
+use std::{cell::RefCell, collections::HashMap, env, fmt, fs, path};
 
+// Repository directories are never part of the working directory
+const REPOSITORY_DIRS: [&str; 2] = [".git", ".rgit"];
+
+/// Part of a compiled gitignore pattern
+#[derive(Debug, Clone)]
+enum Token {
+    Literal(u8),
+    // `?`, any character except a slash
+    Any,
+    // `[...]`, a character of a set of ranges, or of its complement if negated
+    Class(Vec<(u8, u8)>, bool),
+    // `*`, any number of characters except slashes
+    Star,
+    // `**/`, any number of leading directories, including none
+    Directories,
+    // Trailing `/**`, everything inside a directory
+    Everything,
+}
+
+/// Compiles a gitignore pattern, see gitignore(5) for the meaning of `*`, `**`, `?` and `[...]`
+fn compile(pattern: &[u8]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < pattern.len() {
+        match pattern[i] {
+            b'\\' if i + 1 < pattern.len() => {
+                tokens.push(Token::Literal(pattern[i + 1]));
+                i += 2;
+            }
+            b'*' => {
+                let start = i;
+                while i < pattern.len() && pattern[i] == b'*' {
+                    i += 1;
+                }
+
+                // Only two asterisks forming a whole path segment are special
+                let segment_start = start == 0 || pattern[start - 1] == b'/';
+                if i - start < 2 || !segment_start {
+                    tokens.push(Token::Star);
+                } else if i == pattern.len() {
+                    tokens.push(Token::Everything);
+                } else if pattern[i] == b'/' {
+                    tokens.push(Token::Directories);
+                    i += 1;
+                } else {
+                    tokens.push(Token::Star);
+                }
+            }
+            b'?' => {
+                tokens.push(Token::Any);
+                i += 1;
+            }
+            b'[' => match compile_class(&pattern[i + 1..]) {
+                Some((class, len)) => {
+                    tokens.push(class);
+                    i += len + 1;
+                }
+                None => {
+                    tokens.push(Token::Literal(b'['));
+                    i += 1;
+                }
+            },
+            byte => {
+                tokens.push(Token::Literal(byte));
+                i += 1;
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Compiles the rest of a bracket expression after its `[`, returning the class and the number
+/// of bytes it took up, or Option::None if it isn't terminated
+fn compile_class(pattern: &[u8]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(pattern.first(), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let mut byte = *pattern.get(i)?;
+        // A closing bracket right at the start is part of the set
+        if byte == b']' && !first {
+            return Some((Token::Class(ranges, negated), i + 1));
+        }
+        if byte == b'\\' {
+            i += 1;
+            byte = *pattern.get(i)?;
+        }
+        first = false;
+
+        match (pattern.get(i + 1), pattern.get(i + 2)) {
+            (Some(b'-'), Some(end)) if *end != b']' => {
+                ranges.push((byte, *end));
+                i += 3;
+            }
+            _ => {
+                ranges.push((byte, byte));
+                i += 1;
+            }
+        }
+    }
+}
+
+impl Token {
+    /// Whether a token standing for a single character matches a byte, none of them match slashes
+    fn matches_byte(&self, byte: u8) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == byte,
+            Token::Any => byte != b'/',
+            Token::Class(ranges, negated) => {
+                byte != b'/'
+                    && ranges
+                        .iter()
+                        .any(|(start, end)| (start..=end).contains(&&byte))
+                        != *negated
+            }
+            Token::Star | Token::Directories | Token::Everything => false,
+        }
+    }
+}
+
+/// Matches compiled tokens against a path like git's wildmatch. Instead of trying every way to
+/// split the text, only the last `*` and the last `**/` are retried on a mismatch, as later ones
+/// can take over whatever earlier ones would have matched
+fn matches(tokens: &[Token], text: &[u8]) -> bool {
+    let (mut t, mut i) = (0, 0);
+    // Positions in the tokens and the text right after the last `*` and `**/`
+    let mut star: Option<(usize, usize)> = None;
+    let mut directories: Option<(usize, usize)> = None;
+
+    loop {
+        let matched = match tokens.get(t) {
+            None => i == text.len(),
+            Some(Token::Everything) => return true,
+            Some(Token::Star) => {
+                star = Some((t + 1, i));
+                t += 1;
+                continue;
+            }
+            Some(Token::Directories) => {
+                directories = Some((t + 1, i));
+                star = None;
+                t += 1;
+                continue;
+            }
+            Some(token) => text.get(i).is_some_and(|x| token.matches_byte(*x)),
+        };
+
+        if matched && t == tokens.len() {
+            return true;
+        }
+        if matched {
+            (t, i) = (t + 1, i + 1);
+            continue;
+        }
+
+        // A `*` takes one more character unless that is a slash, then `**/` skips one more
+        // directory
+        if let Some((star_t, star_i)) =
+            star.filter(|(_, x)| text.get(*x).is_some_and(|x| *x != b'/'))
+        {
+            star = Some((star_t, star_i + 1));
+            (t, i) = (star_t, star_i + 1);
+        } else if let Some((directories_t, directories_i)) = directories {
+            let Some(slash) = text[directories_i..].iter().position(|x| *x == b'/') else {
+                return false;
+            };
+            directories = Some((directories_t, directories_i + slash + 1));
+            star = None;
+            (t, i) = (directories_t, directories_i + slash + 1);
+        } else {
+            return false;
+        }
+    }
+}
+
+/// A single line of an ignore file
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    // File the pattern comes from and its line number, for reporting which rule matched
+    source: String,
+    line: usize,
+    text: String,
+    // Directory of the .gitignore file, patterns only apply to paths inside of it
+    base: String,
+    tokens: Vec<Token>,
+    negated: bool,
+    dir_only: bool,
+    // Patterns containing a slash match the whole path relative to base, others only file names
+    anchored: bool,
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file, returns Option::None for blank lines and comments
+    fn parse(line: &str, source: &str, line_number: usize, base: &str) -> Option<Pattern> {
+        if line.starts_with('#') {
+            return None;
+        }
+
+        // Trailing spaces are ignored unless they are escaped
+        let mut pattern = line.trim_end_matches(['\n', '\r']);
+        while pattern.ends_with(' ') && !pattern.ends_with("\\ ") {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        if pattern.is_empty() {
+            return None;
+        }
+
+        let negated = pattern.starts_with('!');
+        if negated {
+            pattern = &pattern[1..];
+        }
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+
+        if pattern.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            source: String::from(source),
+            line: line_number,
+            text: String::from(line.trim_end()),
+            base: String::from(base),
+            tokens: compile(pattern.as_bytes()),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let path = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(&self.base)
+                .and_then(|x| x.strip_prefix('/'))
+            {
+                Some(path) => path,
+                None => return false,
+            }
+        };
+
+        let text = if self.anchored {
+            path
+        } else {
+            path.rsplit('/').next().unwrap()
+        };
+
+        matches(&self.tokens, text.as_bytes())
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The pattern as it is written in its file
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_negated(&self) -> bool {
+        self.negated
+    }
+}
+
+// Provenance of the pattern in the format of git check-ignore -v
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source, self.line, self.text)
+    }
+}
+
+/// Reads the patterns of an ignore file, missing files don't hold any patterns
+fn read_patterns(path: &str, base: &str) -> Vec<Pattern> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| Pattern::parse(line, path, i + 1, base))
+        .collect()
+}
+
+/// Decides which paths of the working directory are ignored, following the rules of the global
+/// excludes file, .rgit/info/exclude and the .gitignore files of all directories
 pub struct IgnoreFilter {
-    ignore_paths: Vec<String>,
+    // Patterns of the global excludes file followed by those of .rgit/info/exclude
+    excludes: Vec<Pattern>,
+    // Patterns of the .gitignore file of each directory, loaded when first needed
+    gitignores: RefCell<HashMap<String, Vec<Pattern>>>,
 }
 
 impl IgnoreFilter {
-    /// Creates a new ignore filter loaded with the paths mentioned in .gitignore
-    pub fn new(ignore_path: impl AsRef<path::Path>) -> IgnoreFilter {
-        let ignore_files = fs::read_to_string(ignore_path).unwrap() + "\n.git\n.rgit";
-        let ignore_paths = ignore_files.lines().map(|x| x.to_string()).collect();
+    pub fn new() -> IgnoreFilter {
+        let global = Config::load()
+            .get("core.excludesFile")
+            .map(|path| match (path.strip_prefix("~/"), env::var("HOME")) {
+                (Some(path), Ok(home)) => format!("{}/{}", home, path),
+                _ => String::from(path),
+            })
+            .or_else(Paths::global_excludes);
+
+        let mut excludes = global
+            .map(|path| read_patterns(&path, ""))
+            .unwrap_or_default();
+        excludes.extend(read_patterns(&Paths::info_exclude(), ""));
 
-        IgnoreFilter { ignore_paths }
+        IgnoreFilter {
+            excludes,
+            gitignores: RefCell::new(HashMap::new()),
+        }
     }
 
-    pub fn is_valid(&self, path: &str) -> bool {
-        !self
-            .ignore_paths
+    /// Returns the last pattern matching a path, which decides whether the path is ignored,
+    /// without looking at the path's parent directories
+    fn last_match(&self, path: &str, is_dir: bool) -> Option<Pattern> {
+        // Deeper .gitignore files take precedence over those closer to the root
+        let dirs = std::iter::once("").chain(path.match_indices('/').map(|(i, _)| &path[..i]));
+
+        let mut gitignores = self.gitignores.borrow_mut();
+        let mut last_match = self
+            .excludes
             .iter()
-            .any(|x| path.starts_with(&(x.to_owned() + "/")) || path.eq(x))
+            .rfind(|x| x.matches(path, is_dir))
+            .cloned();
+
+        for dir in dirs {
+            let patterns = gitignores.entry(String::from(dir)).or_insert_with(|| {
+                let file = match dir {
+                    "" => Paths::ignore(),
+                    dir => format!("{}/{}", dir, Paths::ignore()),
+                };
+                read_patterns(&file, dir)
+            });
+
+            if let Some(pattern) = patterns.iter().rfind(|x| x.matches(path, is_dir)) {
+                last_match = Some(pattern.to_owned());
+            }
+        }
+
+        last_match
+    }
+
+    /// Returns the pattern deciding whether a path is ignored, a pattern ignoring one of the
+    /// path's parent directories can't be overridden, as git doesn't look into ignored directories
+    pub fn matching_pattern(&self, path: &str) -> Option<Pattern> {
+        let path = path.trim_start_matches("./").trim_end_matches('/');
+
+        for (i, _) in path.match_indices('/') {
+            let pattern = self.last_match(&path[..i], true);
+            if pattern.as_ref().is_some_and(|x| !x.is_negated()) {
+                return pattern;
+            }
+        }
+
+        self.last_match(path, path::Path::new(path).is_dir())
+    }
+
+    pub fn is_ignored(&self, path: &str) -> bool {
+        let path = path.trim_start_matches("./");
+        // The working directory itself is never ignored
+        if path.is_empty() || path == "." {
+            return false;
+        }
+        if path
+            .split('/')
+            .any(|component| REPOSITORY_DIRS.contains(&component))
+        {
+            return true;
+        }
+
+        self.matching_pattern(path)
+            .is_some_and(|pattern| !pattern.is_negated())
+    }
+
+    /// Recursively lists all files of a directory which aren't ignored, without descending into
+    /// ignored directories, including the directory itself
+    pub fn files(&self, dir: &str) -> Vec<String> {
+        let mut files = Vec::new();
+        let dir = dir.trim_start_matches("./").trim_end_matches('/');
+        if self.is_ignored(dir) {
+            return files;
+        }
+        let mut dirs = vec![String::from(if dir == "." { "" } else { dir })];
+
+        while let Some(dir) = dirs.pop() {
+            let read_dir = fs::read_dir(if dir.is_empty() { "." } else { dir.as_str() })
+                .unwrap_or_else(|_| panic!("Failed to read directory {}", dir));
+
+            for entry in read_dir {
+                let entry = entry.unwrap();
+                let name = entry.file_name().to_string_lossy().to_string();
+                let path = match dir.as_str() {
+                    "" => name,
+                    dir => format!("{}/{}", dir, name),
+                };
+
+                if self.is_ignored(&path) {
+                    continue;
+                }
+
+                if entry.file_type().unwrap().is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+
+        files.sort();
+        files
+    }
+}
+
+impl Default for IgnoreFilter {
+    fn default() -> Self {
+        IgnoreFilter::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wildmatch(pattern: &str, text: &str) -> bool {
+        matches(&compile(pattern.as_bytes()), text.as_bytes())
+    }
+
+    fn pattern(line: &str, base: &str) -> Pattern {
+        Pattern::parse(line, ".gitignore", 1, base).unwrap()
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildmatch("*.rs", "main.rs"));
+        assert!(!wildmatch("*.rs", "src/main.rs"));
+        assert!(wildmatch("a?c", "abc"));
+        assert!(!wildmatch("a?c", "a/c"));
+        assert!(wildmatch("foo*bar*", "foobazbarqux"));
+        assert!(!wildmatch("foo*bar", "foo/bar"));
+        assert!(wildmatch("\\*", "*"));
+        assert!(!wildmatch("\\*", "a"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(wildmatch("[a-c]x", "bx"));
+        assert!(!wildmatch("[a-c]x", "dx"));
+        assert!(wildmatch("[!a-c]x", "dx"));
+        assert!(!wildmatch("[!a-c]x", "ax"));
+        assert!(!wildmatch("[!a-c]x", "/x"));
+    }
+
+    #[test]
+    fn matches_double_asterisks() {
+        assert!(wildmatch("**/foo", "foo"));
+        assert!(wildmatch("**/foo", "a/b/foo"));
+        assert!(!wildmatch("**/foo", "afoo"));
+        assert!(wildmatch("a/**/b", "a/b"));
+        assert!(wildmatch("a/**/b", "a/x/y/b"));
+        assert!(!wildmatch("a/**/b", "ab"));
+        assert!(wildmatch("a/**", "a/x/y"));
+        assert!(!wildmatch("a/**", "a"));
+        assert!(wildmatch("**/*.o", "x/y/z.o"));
+        assert!(!wildmatch("**/*.o", "x/y.o/z"));
+    }
+
+    #[test]
+    fn fails_quickly_on_many_asterisks() {
+        let text = "a".repeat(100);
+        assert!(!wildmatch("*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b", &text));
+        assert!(!wildmatch("**/**/**/**/**/**/**/**/b", &"a/".repeat(50)));
+    }
+
+    #[test]
+    fn parses_lines() {
+        assert!(Pattern::parse("# comment", ".gitignore", 1, "").is_none());
+        assert!(Pattern::parse("   ", ".gitignore", 1, "").is_none());
+        assert!(pattern("trailing\\ ", "").matches("trailing ", false));
+        assert!(pattern("!keep.log", "").is_negated());
+    }
+
+    #[test]
+    fn matches_paths() {
+        let dir = pattern("build/", "");
+        assert!(dir.matches("src/build", true));
+        assert!(!dir.matches("src/build", false));
+
+        let anchored = pattern("/root.txt", "");
+        assert!(anchored.matches("root.txt", false));
+        assert!(!anchored.matches("sub/root.txt", false));
+
+        let nested = pattern("doc/*.md", "");
+        assert!(nested.matches("doc/index.md", false));
+        assert!(!nested.matches("src/doc/index.md", false));
+
+        let based = pattern("*.log", "sub");
+        assert!(based.matches("sub/dir/debug.log", false));
+        assert!(!based.matches("debug.log", false));
+        assert!(!based.matches("subdir/debug.log", false));
     }
 }
@@ -1,12 +1,17 @@
 pub mod cli;
 pub mod commands;
+mod config;
+mod diff;
 mod dir_builder;
+mod hex;
 pub mod identifiers;
 mod ignore;
 pub mod objects;
 mod paths;
+mod rename;
 pub mod storing;
 
+pub use config::Config;
 pub use dir_builder::DirBuilder;
 pub use ignore::IgnoreFilter;
 pub use paths::Paths;
@@ -2,7 +2,7 @@ use clap::Parser;
 use rgit::{
     cli::{Cli, DevSubcommands, Subcommands},
     commands::{
-        CommandReturnType::{self, NonStorable, Storable},
+        CommandReturnType::{self, Storable},
         Commands, DevCommands,
     },
 };
@@ -15,15 +15,43 @@ fn main() {
         Subcommands::Init => Commands::init(),
         Subcommands::Add { file } => Commands::add(file),
         Subcommands::Commit { message } => Commands::commit(message),
-        Subcommands::Status => Commands::status(),
-        Subcommands::Checkout { sha, path } => Commands::checkout(sha, path),
-        Subcommands::Branch => NonStorable,
-        Subcommands::Log => Commands::log(),
+        Subcommands::Status {
+            porcelain,
+            json,
+            branch,
+            nul_terminated,
+        } => Commands::status(porcelain, json, branch, nul_terminated),
+        Subcommands::Diff {
+            args,
+            paths,
+            cached,
+            options,
+        } => Commands::diff(args, paths, cached, options.options()),
+        Subcommands::Checkout { target, path } => Commands::checkout(target, path),
+        Subcommands::Switch { target, detach } => Commands::switch(target, detach),
+        Subcommands::Branch {
+            name,
+            start,
+            delete,
+            force_delete,
+            rename,
+        } => Commands::branch(name, start, delete, force_delete, rename),
+        Subcommands::Merge { target } => Commands::merge(target),
+        Subcommands::CheckIgnore {
+            paths,
+            verbose,
+            non_matching,
+        } => Commands::check_ignore(paths, verbose, non_matching),
+        Subcommands::Log { options } => Commands::log(options.options()),
+        Subcommands::Migrate => Commands::migrate(),
+        Subcommands::Repack => Commands::repack(false),
+        Subcommands::Gc => Commands::repack(true),
         Subcommands::Dev { command } => match command {
             DevSubcommands::Clean => DevCommands::clean(),
             DevSubcommands::BuildTree => DevCommands::build_tree(),
             DevSubcommands::DbgTree { sha } => DevCommands::dbg_tree(sha),
             DevSubcommands::DbgCommit { sha } => DevCommands::dbg_commit(sha),
+            DevSubcommands::DumpIndex => DevCommands::dump_index(),
         },
     };
 
@@ -1,11 +1,36 @@
 mod blob;
+mod checkout;
 mod commit;
+mod database;
+mod delta;
+mod diff;
+mod graph;
 mod head;
 pub mod index;
+mod log;
+mod loose;
+mod merge;
+mod pack;
+mod rev_walk;
+mod signature;
+mod status;
+mod tag;
 mod tree;
 
 pub use blob::Blob;
+pub use checkout::Checkout;
 pub use commit::Commit;
+pub use database::{FsDatabase, MemoryDatabase, ObjectDatabase};
+pub use diff::{Diff, DiffFormat, DiffOptions};
+pub use graph::GraphStyle;
 pub use head::Head;
 pub use index::Index;
+pub use log::{LogFormat, LogOptions, LogWriter};
+pub use loose::{FanOutMigration, Loose};
+pub use merge::Merge;
+pub use pack::{Pack, Repack};
+pub use rev_walk::RevWalk;
+pub use signature::Signature;
+pub use status::Status;
+pub use tag::Tag;
 pub use tree::Tree;
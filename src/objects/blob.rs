@@ -1,34 +1,40 @@
 use crate::{
     identifiers,
-    storing::{Object, Storable},
-    Paths,
+    objects::{FsDatabase, ObjectDatabase},
+    storing::{self, Object, Storable},
 };
-use sha1_smol;
-use std::{fmt, fs, io::Write, path};
+use std::{fmt, fs, os::unix::ffi::OsStringExt, path};
 
 pub struct Blob {
     bytes: Vec<u8>,
 }
 
 impl Blob {
-    fn new_from_bytes(bytes: Vec<u8>) -> Blob {
+    pub fn new(bytes: Vec<u8>) -> Blob {
         Blob { bytes }
     }
 
     /// Creates a new blob from the contents of a file found in the working directory
     pub fn new_from_wd_file(path: impl AsRef<path::Path> + fmt::Display) -> Blob {
-        let bytes = fs::read(&path).expect(&format!("Failed to read {} to create blob", path));
-        let blob = Blob::new_from_bytes(bytes);
+        let bytes =
+            fs::read(&path).unwrap_or_else(|_| panic!("Failed to read {} to create blob", path));
 
-        blob
+        Blob::new(bytes)
     }
 
-    pub fn sha(&self) -> String {
-        let mut hasher = sha1_smol::Sha1::new();
-        hasher.update(&self.bytes);
-        let sha = hasher.digest().to_string();
-
-        sha
+    /// Creates a blob the way git stores a path of the working directory, a symlink is stored
+    /// as the path it points to. Returns Option::None if there is neither a file nor a symlink
+    pub fn try_new_from_wd(path: impl AsRef<path::Path> + fmt::Display) -> Option<Blob> {
+        let metadata = fs::symlink_metadata(&path).ok()?;
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(&path).ok()?;
+            Some(Blob::new(target.into_os_string().into_vec()))
+        } else if metadata.is_file() {
+            Some(Blob::new_from_wd_file(path))
+        } else {
+            None
+        }
     }
 
     pub fn bytes(&self) -> &[u8] {
@@ -38,41 +44,23 @@ impl Blob {
 
 impl Storable for Blob {
     fn store(&self) {
-        let mut file = fs::File::create(Paths::objects() + "/" + &self.sha())
-            .expect("Failed to create file to store blob");
-
-        let buf = self.serialize();
-
-        file.write_all(&buf).unwrap();
+        self.write_to(&FsDatabase);
     }
 }
 
 impl Object for Blob {
-    fn new_from_object_file(sha: &str, _: Option<String>) -> Self {
-        let buf = fs::read(Paths::objects() + "/" + sha).unwrap();
-        let null_i = buf.iter().position(|x| *x == b'\0').unwrap();
+    fn read_from(db: &dyn ObjectDatabase, sha: &str, _: Option<String>) -> Self {
+        let (kind, payload) = db.read_existing(sha);
+        assert_eq!(kind, identifiers::BLOB, "Object {} is not a blob", sha);
 
-        let bytes = Vec::from(&buf[null_i + 1..]);
+        Blob::new(payload)
+    }
 
-        Blob { bytes }
+    fn write_to(&self, db: &dyn ObjectDatabase) {
+        db.write(&self.serialize());
     }
 
     fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-
-        buf.extend(identifiers::BLOB.bytes());
-        buf.push(b' ');
-        buf.extend(
-            // self.bytes
-            //     .len()
-            //     .to_be_bytes()
-            //     .into_iter()
-            //     .filter(|x| *x != b'\0'),
-            self.bytes.len().to_string().bytes(),
-        );
-        buf.push(b'\0');
-        buf.extend_from_slice(&self.bytes);
-
-        buf
+        storing::add_header(identifiers::BLOB, &self.bytes)
     }
 }
@@ -0,0 +1,218 @@
+use crate::{
+    objects::{index, Blob, Commit, Head, Index},
+    storing::{Object, Storable},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs,
+    os::unix::{ffi::OsStrExt, fs::symlink, prelude::PermissionsExt},
+    path,
+};
+
+/// Collects the blob entries of the tree of a head's commit by their paths
+pub(super) fn head_entries(head: &Head) -> BTreeMap<String, index::Entry> {
+    match head.commit() {
+        Some(commit) => commit
+            .tree()
+            .to_index_entries()
+            .into_iter()
+            .map(|x| (x.path().to_owned(), x))
+            .collect(),
+        None => BTreeMap::new(),
+    }
+}
+
+/// Checks whether the working directory file of an entry matches its blob, missing files never
+/// match. Submodules aren't checked out by rgit, so their directories always match
+pub(super) fn wd_file_matches(entry: &index::Entry) -> bool {
+    if entry.git_mode() == index::GITLINK_MODE {
+        return path::Path::new(entry.path()).is_dir();
+    }
+
+    Blob::try_new_from_wd(entry.path()).is_some_and(|x| x.sha() == entry.sha())
+}
+
+/// Removes a file and all of its parent directories which become empty, the directory of a
+/// submodule is only removed if it is empty
+pub(super) fn remove_wd_file(path: &str) {
+    match path::Path::new(path).symlink_metadata() {
+        Ok(metadata) if metadata.is_dir() => {
+            let _ = fs::remove_dir(path);
+        }
+        Ok(_) => fs::remove_file(path).unwrap_or_else(|_| panic!("Failed to remove {}", path)),
+        Err(_) => (),
+    }
+
+    let mut dir = path::Path::new(path).parent();
+    while let Some(parent) = dir.filter(|x| !x.as_os_str().is_empty()) {
+        if fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+/// Writes a file of the working directory like git checks it out, a symlink is created pointing
+/// to the path its blob holds and a submodule is left as an empty directory
+pub(super) fn write_wd_file(path: &str, mode: u32, bytes: &[u8]) {
+    let path = path::Path::new(path);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).unwrap();
+    }
+
+    // Writing through an existing symlink would change the file it points to instead
+    if path.symlink_metadata().is_ok_and(|x| !x.is_dir()) {
+        fs::remove_file(path).unwrap_or_else(|_| panic!("Failed to remove {}", path.display()));
+    }
+
+    match mode & index::TYPE_MASK {
+        index::SYMLINK_MODE => symlink(OsStr::from_bytes(bytes), path)
+            .unwrap_or_else(|_| panic!("Failed to create symlink {}", path.display())),
+        index::GITLINK_MODE => fs::create_dir_all(path).unwrap(),
+        _ => {
+            fs::write(path, bytes).unwrap_or_else(|_| panic!("Failed to write {}", path.display()));
+            fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).unwrap();
+        }
+    }
+}
+
+/// Switches the working directory, the index and HEAD from the current head to another one
+pub struct Checkout {
+    target: Head,
+    // Files which have to be written from the target tree and files which have to be removed
+    writes: Vec<index::Entry>,
+    removals: Vec<String>,
+    index: Index,
+    // Moves the current head to the target's commit instead of switching HEAD to the target
+    fast_forward: bool,
+}
+
+impl Checkout {
+    /// Plans switching to a head, returns the paths whose local changes would be overwritten
+    /// if switching isn't possible
+    pub fn new(target: Head) -> Result<Checkout, Vec<String>> {
+        Checkout::plan(target, false)
+    }
+
+    /// Plans moving the current head forward to a commit, returns the paths whose local changes
+    /// would be overwritten if it isn't possible
+    pub fn fast_forward(commit: Commit) -> Result<Checkout, Vec<String>> {
+        let target = match Head::read_HEAD().name() {
+            Some(name) => Head::new(name.to_owned(), commit),
+            None => Head::detached(commit),
+        };
+
+        Checkout::plan(target, true)
+    }
+
+    fn plan(target: Head, fast_forward: bool) -> Result<Checkout, Vec<String>> {
+        let current = Head::read_HEAD();
+        let old = head_entries(&current);
+        let new = head_entries(&target);
+        let index = Index::new_from_index_file();
+
+        let paths: BTreeSet<&str> = old.keys().chain(new.keys()).map(|x| x.as_str()).collect();
+        let changed: BTreeSet<&str> = paths
+            .into_iter()
+            .filter(|path| old.get(*path).map(|x| x.sha()) != new.get(*path).map(|x| x.sha()))
+            .collect();
+
+        let mut conflicts = Vec::new();
+        let mut writes = Vec::new();
+        let mut removals = Vec::new();
+
+        for path in changed.iter() {
+            let old_entry = old.get(*path);
+            let new_entry = new.get(*path);
+            let index_entry = index.entry(path);
+
+            let is_staged = index_entry.map(|x| x.sha()) != old_entry.map(|x| x.sha());
+            let is_modified = match index_entry {
+                Some(entry) => !wd_file_matches(entry),
+                // Untracked files are only in the way if the target tree contains them
+                None => new_entry.is_some() && path::Path::new(path).symlink_metadata().is_ok(),
+            };
+            let matches_target = match new_entry {
+                Some(entry) => wd_file_matches(entry),
+                None => path::Path::new(path).symlink_metadata().is_err(),
+            };
+
+            if (is_staged || is_modified) && !matches_target {
+                conflicts.push(path.to_string());
+                continue;
+            }
+
+            match new_entry {
+                Some(entry) => writes.push(entry.to_owned()),
+                None => removals.push(path.to_string()),
+            }
+        }
+
+        if !conflicts.is_empty() {
+            return Err(conflicts);
+        }
+
+        // Paths which don't differ between both trees keep their index entries, including
+        // staged changes
+        let mut entries: Vec<index::Entry> = index
+            .entries()
+            .iter()
+            .filter(|x| !changed.contains(x.path()))
+            .cloned()
+            .collect();
+        entries.extend(writes.iter().cloned());
+
+        Ok(Checkout {
+            target,
+            writes,
+            removals,
+            index: Index::new(entries),
+            fast_forward,
+        })
+    }
+
+    fn write_file(entry: &index::Entry) {
+        // The commit of a submodule isn't in the database, there is nothing to write
+        if entry.git_mode() == index::GITLINK_MODE {
+            return write_wd_file(entry.path(), entry.mode(), &[]);
+        }
+
+        let blob = Blob::new_from_object_file(entry.sha(), None);
+        write_wd_file(entry.path(), entry.mode(), blob.bytes());
+    }
+}
+
+impl Storable for Checkout {
+    fn store(&self) {
+        for path in self.removals.iter() {
+            remove_wd_file(path);
+        }
+
+        for entry in self.writes.iter() {
+            Checkout::write_file(entry);
+        }
+
+        self.index.store();
+
+        if self.fast_forward {
+            if let Some(commit) = self.target.commit() {
+                self.target.update(commit);
+            }
+            println!("Fast-forward");
+            return;
+        }
+
+        self.target.write_HEAD();
+
+        match (self.target.name(), self.target.commit()) {
+            (Some(name), _) => println!("Switched to branch '{}'", name),
+            (None, Some(commit)) => println!(
+                "HEAD is now at {} {}",
+                &commit.sha()[..7],
+                commit.message().lines().next().unwrap_or_default()
+            ),
+            (None, None) => (),
+        }
+    }
+}
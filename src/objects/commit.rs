@@ -1,124 +1,395 @@
 use crate::{
     identifiers,
-    objects::Tree,
-    storing::{Object, Storable},
-    Paths,
+    objects::{
+        Diff, DiffOptions, FsDatabase, Head, LogOptions, LogWriter, ObjectDatabase, RevWalk,
+        Signature, Tree,
+    },
+    rename::RenameOptions,
+    storing::{self, Object, Storable},
 };
-use sha1_smol::Sha1;
-use std::{fs, io::Write};
+use std::{
+    cell::OnceCell,
+    collections::{HashSet, VecDeque},
+};
+
+/// Decodes the text of a commit for showing it. Git writes messages in the encoding named by the
+/// encoding header, of which rgit knows ISO-8859-1, anything else is read as utf-8 with invalid
+/// bytes replaced
+fn decode(payload: &[u8]) -> String {
+    let text = String::from_utf8_lossy(payload);
+    let headers = text.split_once("\n\n").map_or(text.as_ref(), |x| x.0);
+    let encoding = headers
+        .lines()
+        .find_map(|x| x.strip_prefix(identifiers::ENCODING)?.strip_prefix(' '));
+
+    match encoding.map(|x| x.to_lowercase()).as_deref() {
+        Some("iso-8859-1" | "iso8859-1" | "latin1" | "latin-1") => {
+            payload.iter().map(|x| *x as char).collect()
+        }
+        _ => text.into_owned(),
+    }
+}
 
-// TODO: make parents lazy loaded
+/// A commit only holds the shas of its tree and parents, both are loaded on demand
 #[derive(Debug, Clone)]
 pub struct Commit {
-    tree: Tree,
-    parent: Option<Box<Commit>>,
+    tree: String,
+    // Root commits have no parents, merge commits have more than one
+    parents: Vec<String>,
+    // Commits created before signatures were recorded have neither author nor committer. They
+    // are kept as written and only parsed when shown, so that the sha stays the same even if
+    // rgit can't parse them
+    author: Option<String>,
+    committer: Option<String>,
     message: String,
+    // Payload of commits read from the database, written back as it is so that the sha stays the
+    // same even with headers rgit doesn't interpret, e.g. gpgsig, or text which isn't utf-8
+    payload: Option<Vec<u8>>,
+    // Set on first access, new commits already hold the tree they were created from
+    loaded_tree: OnceCell<Tree>,
 }
 
 impl Commit {
-    pub fn new(tree: Tree, parent: Option<Commit>, message: String) -> Commit {
-        let parent = if let Some(commit) = parent {
-            Some(Box::new(commit))
-        } else {
-            None
-        };
+    pub fn new(
+        tree: Tree,
+        parents: Vec<String>,
+        author: Signature,
+        committer: Signature,
+        mut message: String,
+    ) -> Commit {
+        // Git terminates commit messages with a newline
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
 
         Commit {
-            tree,
-            parent,
+            tree: tree.sha(),
+            parents,
+            author: Some(author.to_string()),
+            committer: Some(committer.to_string()),
             message,
+            payload: None,
+            loaded_tree: OnceCell::from(tree),
         }
     }
 
-    pub fn sha(&self) -> String {
-        let buf = self.serialize();
+    /// Resolves a revision, i.e. HEAD, a branch name or a full or abbreviated sha, optionally
+    /// followed by `~<n>` suffixes selecting first parent ancestors and `^<n>` suffixes selecting
+    /// the nth parent
+    pub fn from_rev(rev: &str) -> Option<Commit> {
+        let suffix_i = rev.find(['~', '^']).unwrap_or(rev.len());
+        let (name, mut suffixes) = rev.split_at(suffix_i);
+
+        let mut commit = if name == "HEAD" {
+            Head::read_HEAD().commit().to_owned()
+        } else if let Some(head) = Head::try_read(name) {
+            head.commit().to_owned()
+        } else {
+            Commit::from_sha_prefix(name)
+        }?;
+
+        while !suffixes.is_empty() {
+            let operator = &suffixes[..1];
+            let digits = suffixes[1..]
+                .find(|x: char| !x.is_ascii_digit())
+                .map_or(suffixes.len(), |i| i + 1);
+            let n = match &suffixes[1..digits] {
+                "" => 1,
+                n => n.parse().ok()?,
+            };
+            suffixes = &suffixes[digits..];
+
+            match (operator, n) {
+                ("~", n) => {
+                    for _ in 0..n {
+                        commit = commit.first_parent()?;
+                    }
+                }
+                ("^", 0) => (),
+                (_, n) => commit = commit.parents().into_iter().nth(n - 1)?,
+            }
+        }
+
+        Some(commit)
+    }
+
+    /// Finds the commit whose sha starts with a prefix of at least four characters
+    fn from_sha_prefix(prefix: &str) -> Option<Commit> {
+        FsDatabase
+            .find_prefix(prefix, identifiers::COMMIT)
+            .map(|sha| Commit::new_from_object_file(&sha, None))
+    }
+
+    /// Checks whether the commit is reachable by following the parents of another commit
+    pub fn is_ancestor_of(&self, descendant: &Commit) -> bool {
+        let sha = self.sha();
+        let mut seen = HashSet::new();
+        let mut queue = VecDeque::from([descendant.sha()]);
+
+        while let Some(current) = queue.pop_front() {
+            if current == sha {
+                return true;
+            }
+            if seen.insert(current.to_owned()) {
+                let commit = Commit::new_from_object_file(&current, None);
+                queue.extend(commit.parents);
+            }
+        }
+
+        false
+    }
+
+    /// Finds the best common ancestor to merge two commits with, a common ancestor which isn't an
+    /// ancestor of another common ancestor. If there are several, e.g. in criss-cross histories,
+    /// the most recent one is chosen like git merge-base does
+    pub fn merge_base(&self, other: &Commit) -> Option<Commit> {
+        let ancestors: HashSet<String> = RevWalk::new(vec![other.to_owned()])
+            .map(|x| x.sha())
+            .collect();
+        let common: Vec<Commit> = RevWalk::new(vec![self.to_owned()])
+            .filter(|x| ancestors.contains(&x.sha()))
+            .collect();
+
+        // Ancestors of common ancestors are common ancestors too, so the redundant ones are
+        // exactly those which are a parent of another one
+        let redundant: HashSet<&String> = common.iter().flat_map(|x| x.parent_shas()).collect();
+
+        common
+            .iter()
+            .find(|x| !redundant.contains(&x.sha()))
+            .cloned()
+    }
+
+    pub fn tree_sha(&self) -> &str {
+        &self.tree
+    }
+
+    pub fn parent_shas(&self) -> &[String] {
+        &self.parents
+    }
+
+    pub fn is_merge(&self) -> bool {
+        self.parents.len() > 1
+    }
+
+    pub fn author(&self) -> Option<Signature> {
+        self.author.as_deref().and_then(Signature::parse)
+    }
+
+    pub fn committer(&self) -> Option<Signature> {
+        self.committer.as_deref().and_then(Signature::parse)
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the tree of the commit, loading it from the .rgit/objects directory if necessary
+    pub fn tree(&self) -> &Tree {
+        self.tree_from(&FsDatabase)
+    }
+
+    /// Returns the tree of the commit, loading it from a database if necessary
+    pub fn tree_from(&self, db: &dyn ObjectDatabase) -> &Tree {
+        self.loaded_tree
+            .get_or_init(|| Tree::read_from(db, &self.tree, Some(String::from("ROOT"))))
+    }
+
+    /// Loads the parent commits from the .rgit/objects directory
+    pub fn parents(&self) -> Vec<Commit> {
+        self.parents_from(&FsDatabase)
+    }
 
-        let mut hasher = Sha1::new();
-        hasher.update(&buf);
-        let sha = hasher.digest().to_string();
+    /// Loads the parent commits from a database
+    pub fn parents_from(&self, db: &dyn ObjectDatabase) -> Vec<Commit> {
+        self.parents
+            .iter()
+            .map(|parent| Commit::read_from(db, parent, None))
+            .collect()
+    }
 
-        sha
+    /// Loads the first parent, i.e. the commit a merge was made on
+    pub fn first_parent(&self) -> Option<Commit> {
+        self.parents
+            .first()
+            .map(|parent| Commit::new_from_object_file(parent, None))
     }
 
     /// Recreates the tree which the commit is holding
     pub fn restore(&self, path: String) {
-        self.tree.restore(path);
+        self.tree().restore(path);
     }
 
-    /// Logs all parents of the tree recursively
-    pub fn log(&self) {
-        println!("commit {}\n{}\n", self.sha(), self.message);
-        if let Some(commit) = &self.parent {
-            commit.log();
+    /// Tree of the first parent, or an empty tree for root commits
+    fn parent_tree(&self) -> Tree {
+        match self.first_parent() {
+            Some(parent) => parent.tree().to_owned(),
+            None => Tree::new("ROOT", Vec::new()),
+        }
+    }
+
+    /// Diff of the commit limited to a followed file, updating the path to the one the file had
+    /// before the commit. Option::None if the commit doesn't change the file or is a merge
+    fn follow(&self, path: &mut String, options: DiffOptions) -> Option<Diff> {
+        let parent_tree = self.parent_tree();
+        let changed = parent_tree
+            .changes(self.tree())
+            .iter()
+            .any(|(_, new)| new.as_ref().is_some_and(|x| x.path() == path.as_str()));
+        if self.is_merge() || !changed {
+            return None;
+        }
+
+        // Renames are always detected, otherwise the file's history would end there
+        let options = DiffOptions {
+            renames: options.renames.or(Some(RenameOptions::new(None, false))),
+            ..options
+        };
+        let mut diff = Diff::trees(&parent_tree, self.tree(), &[], options);
+        *path = diff.follow(path)?;
+
+        Some(diff)
+    }
+
+    /// Prints the history leading to the commits, newest first or in graph order, selected and
+    /// formatted by the options. The changes of each commit are shown as a diff to its first
+    /// parent, like in git merge commits are shown without their changes. Following a path only
+    /// prints the commits changing the file, tracking its path across renames
+    pub fn log(commits: Vec<Commit>, options: &LogOptions) {
+        let walk = RevWalk::new(commits).limit_to(options.paths.clone());
+        let mut follow = options.follow.clone();
+
+        // Commits with their parents in the graph and the diff of a followed file
+        let commits: Box<dyn Iterator<Item = (Commit, Vec<String>, Option<Diff>)>> =
+            match options.graph {
+                Some(_) => {
+                    // Lines of the graph only lead to parents which are shown too
+                    let commits = walk.topo_order();
+                    let shown: HashSet<String> = commits
+                        .iter()
+                        .filter(|(x, _)| options.matches(x))
+                        .map(|(x, _)| x.sha())
+                        .collect();
+                    let commits: Vec<_> = commits
+                        .into_iter()
+                        .filter(|(x, _)| shown.contains(&x.sha()))
+                        .map(|(x, parents)| {
+                            let parents = parents.into_iter().filter(|x| shown.contains(x));
+                            (x, parents.collect(), None)
+                        })
+                        .collect();
+                    Box::new(commits.into_iter())
+                }
+                None => Box::new(
+                    walk.filter_map(|current| match &mut follow {
+                        Some(path) => {
+                            let diff = current.follow(path, options.diff)?;
+                            Some((current, Vec::new(), Some(diff)))
+                        }
+                        None => Some((current, Vec::new(), None)),
+                    })
+                    .filter(|(current, ..)| options.matches(current)),
+                ),
+            };
+        let commits = commits.take(options.max_count.unwrap_or(usize::MAX));
+
+        let commits: Box<dyn Iterator<Item = _>> = match options.reverse {
+            true => Box::new(commits.collect::<Vec<_>>().into_iter().rev()),
+            false => Box::new(commits),
+        };
+
+        let mut writer = LogWriter::new(options);
+        for (current, parents, diff) in commits {
+            let diff = match diff {
+                None if options.show_changes && !current.is_merge() => Some(Diff::trees(
+                    &current.parent_tree(),
+                    current.tree(),
+                    &options.paths,
+                    options.diff,
+                )),
+                diff => diff,
+            };
+            let diff = diff.filter(|x| options.show_changes && !x.is_empty());
+
+            print!("{}", writer.write(&current, parents, diff.as_ref()));
         }
     }
 }
 
 impl Storable for Commit {
     fn store(&self) {
-        self.tree.store();
-
-        let buf = self.serialize();
-
-        let mut file = fs::File::create(Paths::objects() + "/" + &self.sha()).unwrap();
-        file.write_all(&buf).unwrap();
+        self.write_to(&FsDatabase);
     }
 }
 
 impl Object for Commit {
-    fn new_from_object_file(sha: &str, _: Option<String>) -> Self {
-        let buf = fs::read(Paths::objects() + "/" + sha).unwrap();
+    fn read_from(db: &dyn ObjectDatabase, sha: &str, _: Option<String>) -> Self {
+        let (kind, payload) = db.read_existing(sha);
+        assert_eq!(kind, identifiers::COMMIT, "Object {} is not a commit", sha);
 
-        let mut parts = buf.split(|x| *x == b'\0');
+        let text = decode(&payload);
+        let (headers, message) = text.split_once("\n\n").unwrap_or((text.as_str(), ""));
 
-        parts.next();
-        let tree_sha = parts.next().unwrap();
-        let tree_sha = &tree_sha[identifiers::TREE.len() + 1..];
-        let tree_sha = String::from_utf8(tree_sha.into()).unwrap();
-        let tree = Tree::new_from_object_file(&tree_sha, Some(String::from("ROOT")));
+        let mut tree = None;
+        let mut parents = Vec::new();
+        let mut author = None;
+        let mut committer = None;
 
-        let parent_sha = parts.next().unwrap();
-        let parent_sha = if parent_sha.len() != 0 {
-            Some(&parent_sha[identifiers::PARENT.len() + 1..])
-        } else {
-            None
-        };
-        let parent_sha = if let Some(parent_sha) = parent_sha {
-            assert_eq!(parent_sha.len(), 40);
-            Some(String::from_utf8(parent_sha.into()).unwrap())
-        } else {
-            None
-        };
-        let parent = if let Some(parent_sha) = parent_sha {
-            Some(Commit::new_from_object_file(&parent_sha, None))
-        } else {
-            None
-        };
+        for line in headers.lines() {
+            let (key, value) = line.split_once(' ').expect("Malformed commit header");
+
+            match key {
+                identifiers::TREE => tree = Some(String::from(value)),
+                identifiers::PARENT => {
+                    assert_eq!(value.len(), 40);
+                    parents.push(String::from(value));
+                }
+                identifiers::AUTHOR => author = Some(String::from(value)),
+                identifiers::COMMITTER => committer = Some(String::from(value)),
+                _ => (),
+            }
+        }
+
+        Commit {
+            tree: tree.expect("Commit is missing its tree"),
+            parents,
+            author,
+            committer,
+            message: String::from(message),
+            payload: Some(payload),
+            loaded_tree: OnceCell::new(),
+        }
+    }
 
-        let message = parts.next().unwrap();
-        let message = String::from_utf8(message.into()).unwrap();
+    fn write_to(&self, db: &dyn ObjectDatabase) {
+        // Only trees of new commits have to be written, loaded ones are stored already
+        if !db.exists(&self.tree) {
+            self.tree_from(db).write_to(db);
+        }
 
-        Commit::new(tree, parent, message)
+        db.write(&self.serialize());
     }
 
     fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
-
-        buf.extend(identifiers::COMMIT.bytes());
-        buf.push(b' ');
-        buf.push(b'\0');
-        buf.extend(identifiers::TREE.bytes());
-        buf.push(b' ');
-        buf.extend(self.tree.sha().bytes());
-        buf.push(b'\0');
-        if let Some(parent) = &self.parent {
-            buf.extend(identifiers::PARENT.bytes());
-            buf.push(b' ');
-            buf.extend(parent.sha().bytes());
-        };
-        buf.push(b'\0');
-        buf.extend(self.message.bytes());
+        if let Some(payload) = &self.payload {
+            return storing::add_header(identifiers::COMMIT, payload);
+        }
+
+        let mut payload = String::new();
+
+        payload.push_str(&format!("{} {}\n", identifiers::TREE, self.tree));
+        for parent in self.parents.iter() {
+            payload.push_str(&format!("{} {}\n", identifiers::PARENT, parent));
+        }
+        if let Some(author) = &self.author {
+            payload.push_str(&format!("{} {}\n", identifiers::AUTHOR, author));
+        }
+        if let Some(committer) = &self.committer {
+            payload.push_str(&format!("{} {}\n", identifiers::COMMITTER, committer));
+        }
+        payload.push('\n');
+        payload.push_str(&self.message);
 
-        buf
+        storing::add_header(identifiers::COMMIT, payload.as_bytes())
     }
 }
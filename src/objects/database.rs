@@ -0,0 +1,273 @@
+use crate::{
+    objects::{Loose, Pack},
+    storing,
+};
+use sha1_smol::Sha1;
+use std::{cell::RefCell, collections::HashMap};
+
+/// Backend all objects are read from and written to
+pub trait ObjectDatabase {
+    /// Reads an object, returning its type and payload, or None if it doesn't exist
+    fn read(&self, sha: &str) -> Option<(String, Vec<u8>)>;
+
+    /// Stores a serialized object including its header unless it already exists, returning its sha
+    fn write(&self, buf: &[u8]) -> String;
+
+    fn exists(&self, sha: &str) -> bool;
+
+    /// Iterates over the shas of all objects in the database
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_>;
+
+    /// Reads an object which has to exist, returning its type and payload
+    fn read_existing(&self, sha: &str) -> (String, Vec<u8>) {
+        self.read(sha)
+            .unwrap_or_else(|| panic!("Object {} not found", sha))
+    }
+
+    /// Finds the object of a type whose sha starts with a prefix of at least four characters
+    fn find_prefix(&self, prefix: &str, kind: &str) -> Option<String> {
+        if prefix.len() < 4 || !prefix.chars().all(|x| x.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let prefix = prefix.to_lowercase();
+        let mut matches = self.iter().filter(|sha| sha.starts_with(&prefix));
+        let sha = matches.next()?;
+        if matches.next().is_some() {
+            eprintln!("Short sha {} is ambiguous", prefix);
+            return None;
+        }
+
+        match self.read(&sha) {
+            Some((found, _)) if found == kind => Some(sha),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    // Pack indices are only read once per run
+    static PACKS: Vec<Pack> = Pack::all();
+}
+
+/// Database backed by the .rgit/objects directory, looking objects up as loose objects first and in
+/// packfiles second
+pub struct FsDatabase;
+
+impl FsDatabase {
+    fn is_packed(sha: &str) -> bool {
+        PACKS.with(|packs| packs.iter().any(|pack| pack.contains(sha)))
+    }
+}
+
+impl ObjectDatabase for FsDatabase {
+    fn read(&self, sha: &str) -> Option<(String, Vec<u8>)> {
+        if Loose::exists(sha) {
+            let buf = Loose::read(sha);
+            let (kind, payload) = storing::split_header(&buf);
+            return Some((kind.to_owned(), payload.to_vec()));
+        }
+
+        PACKS.with(|packs| packs.iter().find_map(|pack| pack.read(sha)))
+    }
+
+    fn write(&self, buf: &[u8]) -> String {
+        let sha = Sha1::from(buf).digest().to_string();
+
+        if FsDatabase::is_packed(&sha) {
+            return sha;
+        }
+
+        Loose::write(buf)
+    }
+
+    fn exists(&self, sha: &str) -> bool {
+        Loose::exists(sha) || FsDatabase::is_packed(sha)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        let mut shas = Loose::all();
+        PACKS.with(|packs| packs.iter().for_each(|pack| shas.extend(pack.shas())));
+        shas.sort();
+        shas.dedup();
+
+        Box::new(shas.into_iter())
+    }
+}
+
+/// Database holding all objects in memory, nothing is ever written to disk
+#[derive(Default)]
+pub struct MemoryDatabase {
+    objects: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryDatabase {
+    pub fn new() -> MemoryDatabase {
+        MemoryDatabase::default()
+    }
+}
+
+impl ObjectDatabase for MemoryDatabase {
+    fn read(&self, sha: &str) -> Option<(String, Vec<u8>)> {
+        self.objects.borrow().get(sha).map(|buf| {
+            let (kind, payload) = storing::split_header(buf);
+            (kind.to_owned(), payload.to_vec())
+        })
+    }
+
+    fn write(&self, buf: &[u8]) -> String {
+        let sha = Sha1::from(buf).digest().to_string();
+
+        self.objects
+            .borrow_mut()
+            .entry(sha.to_owned())
+            .or_insert_with(|| buf.to_vec());
+
+        sha
+    }
+
+    fn exists(&self, sha: &str) -> bool {
+        self.objects.borrow().contains_key(sha)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = String> + '_> {
+        let mut shas: Vec<String> = self.objects.borrow().keys().cloned().collect();
+        shas.sort();
+
+        Box::new(shas.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        identifiers,
+        objects::{index, Blob, Commit, Tree},
+        storing::Object,
+    };
+    use std::collections::HashSet;
+
+    #[test]
+    fn stores_objects_in_memory() {
+        let db = MemoryDatabase::new();
+        let sha = db.write(&storing::add_header(identifiers::BLOB, b"hello\n"));
+
+        assert_eq!(sha, "ce013625030ba8dba906f756967f9e9ca394464a");
+        assert!(db.exists(&sha));
+        assert_eq!(
+            db.read(&sha),
+            Some((identifiers::BLOB.to_owned(), b"hello\n".to_vec()))
+        );
+        assert_eq!(db.read(&"0".repeat(40)), None);
+        assert_eq!(db.iter().collect::<Vec<_>>(), [sha.to_owned()]);
+    }
+
+    #[test]
+    fn finds_objects_by_prefix() {
+        let db = MemoryDatabase::new();
+        let sha = db.write(&storing::add_header(identifiers::BLOB, b"hello\n"));
+
+        assert_eq!(db.find_prefix("CE0136", identifiers::BLOB), Some(sha));
+        assert_eq!(db.find_prefix("ce0136", identifiers::TREE), None);
+        assert_eq!(db.find_prefix("ce0", identifiers::BLOB), None);
+        assert_eq!(db.find_prefix("ce01x", identifiers::BLOB), None);
+    }
+
+    #[test]
+    fn writes_trees_like_git() {
+        let db = MemoryDatabase::new();
+        let mut entries = Vec::new();
+        for (mode, path, bytes) in [
+            (0o100644, "a.txt", "hello\n"),
+            (0o100644, "dir/b.txt", "hello\n"),
+            (0o100755, "run.sh", "#!/bin/sh\n"),
+        ] {
+            let blob = Blob::new(bytes.as_bytes().to_vec());
+            blob.write_to(&db);
+            entries.push(index::Entry::new(mode, path.to_owned(), blob.sha()));
+        }
+
+        let tree = Tree::new("ROOT", entries);
+        tree.write_to(&db);
+
+        let sha = tree.sha();
+        assert_eq!(sha, "9b92697fcf7ea73a6544923b05750d6a322ccdb9");
+        let read = Tree::read_from(&db, &sha, Some("ROOT".to_owned()));
+        assert_eq!(read.serialize(), tree.serialize());
+        // The blobs and both trees
+        assert_eq!(db.iter().count(), 4);
+    }
+
+    #[test]
+    fn compares_trees_without_loading_unchanged_subtrees() {
+        let db = MemoryDatabase::new();
+        let sha = Blob::new(b"hello\n".to_vec()).sha();
+        let entry = |path: &str| index::Entry::new(0o100644, path.to_owned(), sha.to_owned());
+
+        let old = Tree::new("ROOT", vec![entry("a.txt"), entry("dir/b.txt")]);
+        old.write_to(&db);
+        // Only the root is loaded, reading the subtree would look for it in .rgit/objects
+        let old = Tree::read_from(&db, &old.sha(), Some("ROOT".to_owned()));
+        let new = Tree::new("ROOT", vec![entry("c.txt"), entry("dir/b.txt")]);
+
+        let paths: Vec<(Option<String>, Option<String>)> = old
+            .changes(&new)
+            .into_iter()
+            .map(|(old, new)| {
+                (
+                    old.map(|x| x.path().to_owned()),
+                    new.map(|x| x.path().to_owned()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                (Some("a.txt".to_owned()), None),
+                (None, Some("c.txt".to_owned()))
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_symlinks_and_submodules_in_trees() {
+        let db = MemoryDatabase::new();
+        let blob = Blob::new(b"file".to_vec());
+        blob.write_to(&db);
+        let submodule = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+
+        let tree = Tree::new(
+            "ROOT",
+            vec![
+                index::Entry::new(0o120777, "link".to_owned(), blob.sha()),
+                index::Entry::new(0o160000, "sub".to_owned(), submodule.to_owned()),
+            ],
+        );
+        tree.write_to(&db);
+
+        assert_eq!(tree.sha(), "f808dda56ed399d08f7a08bb8f7bfe6a35cfac0d");
+        let mut objects = Vec::new();
+        Tree::collect_objects(&db, &tree.sha(), "", &mut HashSet::new(), &mut objects);
+        assert_eq!(
+            objects,
+            [(tree.sha(), String::new()), (blob.sha(), "link".to_owned())]
+        );
+    }
+
+    #[test]
+    fn keeps_commits_which_arent_utf8() {
+        let db = MemoryDatabase::new();
+        let mut payload = format!(
+            "tree {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\nencoding ISO-8859-1\n\n",
+            "4b825dc642cb6eb9a060e54bf8d69288fbee4904"
+        )
+        .into_bytes();
+        payload.extend(b"Caf\xe9\n");
+        let sha = db.write(&storing::add_header(identifiers::COMMIT, &payload));
+
+        let commit = Commit::read_from(&db, &sha, None);
+        assert_eq!(commit.message(), "Caf\u{e9}\n");
+        assert_eq!(commit.sha(), sha);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+// Size of the blocks of the base which are indexed to find matches in the target
+const BLOCK_SIZE: usize = 16;
+// Largest amount of bytes a single copy or insert instruction can hold
+const MAX_COPY_SIZE: usize = 0x10000;
+const MAX_INSERT_SIZE: usize = 0x7f;
+
+/// Encodes a size as the little endian base 128 varint used in delta headers
+fn encode_size(mut size: usize, buf: &mut Vec<u8>) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+
+        if size == 0 {
+            buf.push(byte);
+            break;
+        }
+
+        buf.push(byte | 0x80);
+    }
+}
+
+fn flush_insert(insert: &mut Vec<u8>, buf: &mut Vec<u8>) {
+    for chunk in insert.chunks(MAX_INSERT_SIZE) {
+        buf.push(chunk.len() as u8);
+        buf.extend_from_slice(chunk);
+    }
+
+    insert.clear();
+}
+
+fn push_copy(mut offset: usize, mut size: usize, buf: &mut Vec<u8>) {
+    while size > 0 {
+        let chunk = size.min(MAX_COPY_SIZE);
+
+        let mut op = 0x80u8;
+        let mut args = Vec::new();
+
+        for i in 0..4 {
+            let byte = ((offset >> (8 * i)) & 0xff) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                args.push(byte);
+            }
+        }
+
+        // A size of 0x10000 is encoded as no size bytes at all
+        if chunk != MAX_COPY_SIZE {
+            for i in 0..3 {
+                let byte = ((chunk >> (8 * i)) & 0xff) as u8;
+                if byte != 0 {
+                    op |= 1 << (4 + i);
+                    args.push(byte);
+                }
+            }
+        }
+
+        buf.push(op);
+        buf.extend(args);
+
+        offset += chunk;
+        size -= chunk;
+    }
+}
+
+/// Creates a git delta which rebuilds target when applied to base
+pub fn create(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    encode_size(base.len(), &mut buf);
+    encode_size(target.len(), &mut buf);
+
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK_SIZE - 1)).step_by(BLOCK_SIZE) {
+        blocks
+            .entry(&base[offset..offset + BLOCK_SIZE])
+            .or_insert(offset);
+    }
+
+    let mut insert = Vec::new();
+    let mut i = 0;
+
+    while i < target.len() {
+        let found = target
+            .get(i..i + BLOCK_SIZE)
+            .and_then(|block| blocks.get(block));
+
+        let Some(&base_offset) = found else {
+            insert.push(target[i]);
+            i += 1;
+            continue;
+        };
+
+        let mut start = base_offset;
+        let mut len = BLOCK_SIZE;
+        while start + len < base.len()
+            && i + len < target.len()
+            && base[start + len] == target[i + len]
+        {
+            len += 1;
+        }
+
+        // Extend the match backwards into bytes which were about to be inserted
+        while !insert.is_empty() && start > 0 && base[start - 1] == *insert.last().unwrap() {
+            insert.pop();
+            start -= 1;
+            len += 1;
+        }
+
+        flush_insert(&mut insert, &mut buf);
+        push_copy(start, len, &mut buf);
+
+        i += len - (base_offset - start);
+    }
+
+    flush_insert(&mut insert, &mut buf);
+
+    buf
+}
+
+/// Decodes a size encoded as a little endian base 128 varint, advancing the position
+fn decode_size(buf: &[u8], i: &mut usize) -> usize {
+    let mut size = 0;
+    let mut shift = 0;
+
+    loop {
+        let byte = buf[*i];
+        *i += 1;
+        size |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return size;
+        }
+    }
+}
+
+/// Rebuilds the target of a git delta from its base
+pub fn apply(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut i = 0;
+    let base_size = decode_size(delta, &mut i);
+    let target_size = decode_size(delta, &mut i);
+    assert_eq!(base_size, base.len(), "Delta base has the wrong size");
+
+    let mut target = Vec::with_capacity(target_size);
+
+    while i < delta.len() {
+        let op = delta[i];
+        i += 1;
+
+        if op & 0x80 != 0 {
+            let mut offset = 0;
+            let mut size = 0;
+
+            for bit in 0..4 {
+                if op & (1 << bit) != 0 {
+                    offset |= (delta[i] as usize) << (8 * bit);
+                    i += 1;
+                }
+            }
+            for bit in 0..3 {
+                if op & (1 << (4 + bit)) != 0 {
+                    size |= (delta[i] as usize) << (8 * bit);
+                    i += 1;
+                }
+            }
+            if size == 0 {
+                size = MAX_COPY_SIZE;
+            }
+
+            target.extend_from_slice(&base[offset..offset + size]);
+        } else if op != 0 {
+            target.extend_from_slice(&delta[i..i + op as usize]);
+            i += op as usize;
+        } else {
+            panic!("Delta contains the reserved instruction 0");
+        }
+    }
+
+    assert_eq!(target_size, target.len(), "Delta produced the wrong size");
+
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = create(base, target);
+        assert_eq!(apply(base, &delta), target);
+        delta
+    }
+
+    #[test]
+    fn starts_with_both_sizes() {
+        let delta = create(&[b'a'; 300], b"target");
+
+        // 300 takes two bytes as a varint
+        assert_eq!(delta[..3], [0xac, 0x02, 6]);
+    }
+
+    #[test]
+    fn inserts_literal_bytes() {
+        assert_eq!(create(b"", b"new"), [0, 3, 3, b'n', b'e', b'w']);
+    }
+
+    #[test]
+    fn copies_unchanged_content() {
+        let base: Vec<u8> = (0..4096).map(|x| (x * 7 % 251) as u8).collect();
+        let mut target = base.clone();
+        target[2000] ^= 0xff;
+        target.extend(b"appended");
+
+        let delta = round_trip(&base, &target);
+        assert!(delta.len() < 64);
+    }
+
+    #[test]
+    fn inserts_unrelated_content() {
+        round_trip(b"", b"");
+        round_trip(b"", b"new content");
+        round_trip(b"old content", b"");
+        // Literal data longer than an insert instruction holds is split
+        round_trip(b"short", &[b'x'; 3 * MAX_INSERT_SIZE]);
+    }
+
+    #[test]
+    fn splits_large_copies() {
+        let base: Vec<u8> = (0..3 * MAX_COPY_SIZE).map(|x| (x % 253) as u8).collect();
+        let mut target = b"prefix".to_vec();
+        target.extend(&base);
+
+        round_trip(&base, &target);
+    }
+}
@@ -0,0 +1,824 @@
+use crate::{
+    diff::{self, Algorithm, Edit, Hunk},
+    objects::{
+        index,
+        merge::same_version,
+        status::{normalize_mode, quote_path},
+        tree::TreeChange,
+        Blob, Index, Tree,
+    },
+    rename::{Candidate, Rename, RenameOptions},
+    storing::Object,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
+    ops::Range,
+};
+
+const NULL_SHA: &str = "0000000000000000000000000000000000000000";
+// Git shortens the function names shown in hunk headers
+const FUNCNAME_LEN: usize = 80;
+// Width of the stat format if the COLUMNS variable doesn't tell the terminal's width
+const STAT_WIDTH: usize = 80;
+
+/// Checks whether a path is selected by the paths given on the command line, which select
+/// themselves and everything inside of them
+pub(super) fn matches_pathspec(path: &str, pathspecs: &[String]) -> bool {
+    pathspecs.is_empty()
+        || pathspecs.iter().any(|spec| {
+            let spec = spec.trim_start_matches("./").trim_end_matches('/');
+            spec.is_empty()
+                || spec == "."
+                || path == spec
+                || path.strip_prefix(spec).is_some_and(|x| x.starts_with('/'))
+        })
+}
+
+/// Finds the closest line before a hunk which looks like the start of a function with git's
+/// default rule, i.e. a line starting with a letter, `_` or `$`
+fn funcname(lines: &[&[u8]], before: usize) -> Option<String> {
+    let line = lines[..before].iter().rev().find(|x| {
+        x.first()
+            .is_some_and(|x| x.is_ascii_alphabetic() || *x == b'_' || *x == b'$')
+    })?;
+
+    let line = &line[..line.len().min(FUNCNAME_LEN)];
+    Some(String::from_utf8_lossy(line).trim_end().to_owned())
+}
+
+/// Formats the line range of one side of a hunk, ranges of a single line omit their length and
+/// empty ranges point at the line before them
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        len => format!("{},{}", start + 1, len),
+    }
+}
+
+/// Formats the paths of a renamed file for the stat formats, moving the parts both paths share
+/// out of braces like git does, e.g. "src/{a => b}/file"
+fn rename_path(from: &str, to: &str) -> String {
+    let (from, to) = (quote_path(from, false), quote_path(to, false));
+    if from.starts_with('"') || to.starts_with('"') {
+        return format!("{} => {}", from, to);
+    }
+    let (a, b) = (from.as_bytes(), to.as_bytes());
+
+    // The common prefix ends with a slash
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+
+    // The common suffix starts with a slash, which may be the last one of the prefix
+    let mut suffix = 0;
+    let (mut i, mut j) = (a.len(), b.len());
+    let limit = prefix.saturating_sub(1);
+    while i >= limit && j >= limit && a.get(i) == b.get(j) {
+        if a.get(i) == Some(&b'/') {
+            suffix = a.len() - i;
+        }
+        if i == 0 || j == 0 {
+            break;
+        }
+        (i, j) = (i - 1, j - 1);
+    }
+
+    if prefix + suffix == 0 {
+        return format!("{} => {}", from, to);
+    }
+    let middle = |x: &str| {
+        let len = x.len().saturating_sub(prefix + suffix);
+        x[prefix..prefix + len].to_owned()
+    };
+    format!(
+        "{}{{{} => {}}}{}",
+        &from[..prefix],
+        middle(&from),
+        middle(&to),
+        &from[from.len() - suffix..]
+    )
+}
+
+/// Version of a file on one side of a diff
+#[derive(Clone)]
+struct Side {
+    entry: index::Entry,
+    bytes: Vec<u8>,
+}
+
+impl Side {
+    fn from_blob(entry: &index::Entry) -> Side {
+        let bytes = match Side::submodule(entry) {
+            Some(bytes) => bytes,
+            None => Blob::new_from_object_file(entry.sha(), None)
+                .bytes()
+                .to_vec(),
+        };
+
+        Side {
+            entry: entry.to_owned(),
+            bytes,
+        }
+    }
+
+    fn from_wd_file(entry: index::Entry) -> Side {
+        let bytes = Side::submodule(&entry).unwrap_or_else(|| {
+            Blob::try_new_from_wd(entry.path())
+                .unwrap_or_else(|| panic!("Failed to read {} to diff it", entry.path()))
+                .bytes()
+                .to_vec()
+        });
+
+        Side { entry, bytes }
+    }
+
+    /// Git shows the commit a submodule is at instead of its files
+    fn submodule(entry: &index::Entry) -> Option<Vec<u8>> {
+        (entry.git_mode() == index::GITLINK_MODE)
+            .then(|| format!("Subproject commit {}\n", entry.sha()).into_bytes())
+    }
+}
+
+/// Changes of a single file, a missing side means the file was added or deleted
+struct FileDiff {
+    old: Option<Side>,
+    new: Option<Side>,
+    // Path and similarity of the file the new version was renamed or copied from
+    rename: Option<Rename>,
+}
+
+impl FileDiff {
+    fn bytes(side: Option<&Side>) -> &[u8] {
+        side.map_or(&[][..], |x| &x.bytes)
+    }
+
+    fn is_binary(&self) -> bool {
+        diff::is_binary(FileDiff::bytes(self.old.as_ref()))
+            || diff::is_binary(FileDiff::bytes(self.new.as_ref()))
+    }
+
+    /// Numbers of added and deleted lines, for binary files the sizes of the new and old version
+    fn counts(&self, algorithm: Algorithm) -> (usize, usize) {
+        let (old, new) = (
+            FileDiff::bytes(self.old.as_ref()),
+            FileDiff::bytes(self.new.as_ref()),
+        );
+        if self.is_binary() {
+            return (new.len(), old.len());
+        }
+
+        let (old_lines, new_lines) = (diff::lines(old), diff::lines(new));
+        algorithm
+            .diff(&old_lines, &new_lines)
+            .into_iter()
+            .fold((0, 0), |(added, deleted), edit| match edit {
+                Edit::Insert(_) => (added + 1, deleted),
+                Edit::Delete(_) => (added, deleted + 1),
+                Edit::Equal(..) => (added, deleted),
+            })
+    }
+
+    /// Status of the name-status format, a letter followed by the similarity for renames and
+    /// copies
+    fn status(&self) -> String {
+        // Only the type bits of a mode, e.g. regular file or symlink
+        let kind = |side: &Side| side.entry.mode() & 0o170000;
+
+        match (&self.old, &self.new, &self.rename) {
+            (.., Some(rename)) if rename.copy => format!("C{:03}", rename.score),
+            (.., Some(rename)) => format!("R{:03}", rename.score),
+            (None, ..) => String::from("A"),
+            (_, None, _) => String::from("D"),
+            (Some(old), Some(new), _) if kind(old) != kind(new) => String::from("T"),
+            _ => String::from("M"),
+        }
+    }
+
+    /// Path shown by the stat formats, both paths for renames and copies
+    fn stat_path(&self, path: &str) -> String {
+        match &self.rename {
+            Some(rename) => rename_path(&rename.from, path),
+            None => quote_path(path, false),
+        }
+    }
+
+    /// Paths shown by the name-status format, separated by a tab for renames and copies
+    fn status_paths(&self, path: &str) -> String {
+        match &self.rename {
+            Some(rename) => format!(
+                "{}\t{}",
+                quote_path(&rename.from, false),
+                quote_path(path, false)
+            ),
+            None => quote_path(path, false),
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, path: &str, options: &DiffOptions) -> fmt::Result {
+        let (old, new) = (self.old.as_ref(), self.new.as_ref());
+        let old_mode = normalize_mode(old.map(|x| &x.entry));
+        let new_mode = normalize_mode(new.map(|x| &x.entry));
+        let old_sha = old.map_or(NULL_SHA, |x| x.entry.sha());
+        let new_sha = new.map_or(NULL_SHA, |x| x.entry.sha());
+        let from = self.rename.as_ref().map_or(path, |x| x.from.as_str());
+        let old_path = quote_path(&format!("a/{}", from), false);
+        let new_path = quote_path(&format!("b/{}", path), false);
+
+        writeln!(f, "diff --git {} {}", old_path, new_path)?;
+        match (old, new) {
+            (None, _) => writeln!(f, "new file mode {:o}", new_mode)?,
+            (_, None) => writeln!(f, "deleted file mode {:o}", old_mode)?,
+            _ if old_mode != new_mode => {
+                writeln!(f, "old mode {:o}", old_mode)?;
+                writeln!(f, "new mode {:o}", new_mode)?;
+            }
+            _ => (),
+        }
+        if let Some(rename) = &self.rename {
+            let kind = if rename.copy { "copy" } else { "rename" };
+            writeln!(f, "similarity index {}%", rename.score)?;
+            writeln!(f, "{} from {}", kind, quote_path(from, false))?;
+            writeln!(f, "{} to {}", kind, quote_path(path, false))?;
+        }
+
+        if old_sha == new_sha {
+            return Ok(());
+        }
+        match old_mode == new_mode {
+            true => writeln!(
+                f,
+                "index {}..{} {:o}",
+                &old_sha[..7],
+                &new_sha[..7],
+                old_mode
+            )?,
+            false => writeln!(f, "index {}..{}", &old_sha[..7], &new_sha[..7])?,
+        }
+
+        let old_path = old.map_or(String::from("/dev/null"), |_| old_path);
+        let new_path = new.map_or(String::from("/dev/null"), |_| new_path);
+
+        if self.is_binary() {
+            return writeln!(f, "Binary files {} and {} differ", old_path, new_path);
+        }
+
+        let (old_lines, new_lines) = (
+            diff::lines(FileDiff::bytes(old)),
+            diff::lines(FileDiff::bytes(new)),
+        );
+        let edits = options.algorithm.diff(&old_lines, &new_lines);
+        let hunks = diff::hunks(&edits, options.context);
+        if hunks.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(f, "--- {}", old_path)?;
+        writeln!(f, "+++ {}", new_path)?;
+        for hunk in hunks {
+            match options.format {
+                DiffFormat::WordDiff => write_word_hunk(f, &hunk, &old_lines, &new_lines)?,
+                _ => write_hunk(f, &hunk, &old_lines, &new_lines)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn write_hunk_header(f: &mut fmt::Formatter<'_>, hunk: &Hunk, old: &[&[u8]]) -> fmt::Result {
+    write!(
+        f,
+        "@@ -{} +{} @@",
+        hunk_range(hunk.old_start, hunk.old_len),
+        hunk_range(hunk.new_start, hunk.new_len)
+    )?;
+    match funcname(old, hunk.old_start) {
+        Some(funcname) => writeln!(f, " {}", funcname),
+        None => writeln!(f),
+    }
+}
+
+fn write_hunk(
+    f: &mut fmt::Formatter<'_>,
+    hunk: &Hunk,
+    old: &[&[u8]],
+    new: &[&[u8]],
+) -> fmt::Result {
+    write_hunk_header(f, hunk, old)?;
+
+    for edit in hunk.edits.iter() {
+        let (marker, line) = match *edit {
+            Edit::Equal(i, _) => (' ', old[i]),
+            Edit::Delete(i) => ('-', old[i]),
+            Edit::Insert(i) => ('+', new[i]),
+        };
+
+        write!(f, "{}{}", marker, String::from_utf8_lossy(line))?;
+        if !line.ends_with(b"\n") {
+            writeln!(f, "\n\\ No newline at end of file")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a hunk showing which words of the changed lines were deleted and inserted, like git's
+/// plain word diff. Unchanged lines are written as they are
+fn write_word_hunk(
+    f: &mut fmt::Formatter<'_>,
+    hunk: &Hunk,
+    old: &[&[u8]],
+    new: &[&[u8]],
+) -> fmt::Result {
+    write_hunk_header(f, hunk, old)?;
+
+    // Every line ends with a newline, even the last line of a file without one
+    let append = |text: &mut Vec<u8>, line: &[u8]| {
+        text.extend(line);
+        if !line.ends_with(b"\n") {
+            text.push(b'\n');
+        }
+    };
+
+    let (mut deleted, mut inserted) = (Vec::new(), Vec::new());
+    for edit in hunk.edits.iter() {
+        match *edit {
+            Edit::Delete(i) => append(&mut deleted, old[i]),
+            Edit::Insert(j) => append(&mut inserted, new[j]),
+            Edit::Equal(i, _) => {
+                write_words(f, &deleted, &inserted)?;
+                (deleted, inserted) = (Vec::new(), Vec::new());
+
+                let mut line = Vec::new();
+                append(&mut line, old[i]);
+                write_marked(f, "", "", &line)?;
+            }
+        }
+    }
+
+    write_words(f, &deleted, &inserted)
+}
+
+/// Writes the text of consecutive deleted and inserted lines, marking the words only one of
+/// them holds. Text both hold is taken from the inserted lines
+fn write_words(f: &mut fmt::Formatter<'_>, deleted: &[u8], inserted: &[u8]) -> fmt::Result {
+    if inserted.is_empty() {
+        return write_marked(f, "[-", "-]", deleted);
+    }
+
+    let (old_words, new_words) = (diff::words(deleted), diff::words(inserted));
+    let old: Vec<&[u8]> = old_words.iter().map(|x| &deleted[x.clone()]).collect();
+    let new: Vec<&[u8]> = new_words.iter().map(|x| &inserted[x.clone()]).collect();
+
+    // Byte ranges of the deleted and inserted words of each change, an empty range lies at the end
+    // of the last word before the change
+    let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let (mut old_end, mut new_end) = (0, 0);
+    let mut in_change = false;
+
+    for edit in diff::word_edits(&old, &new) {
+        if matches!(edit, Edit::Equal(..)) {
+            in_change = false;
+        } else if !in_change {
+            in_change = true;
+            changes.push((old_end..old_end, new_end..new_end));
+        }
+
+        match edit {
+            Edit::Equal(i, j) => (old_end, new_end) = (old_words[i].end, new_words[j].end),
+            Edit::Delete(i) => {
+                let change = &mut changes.last_mut().unwrap().0;
+                if change.start == change.end {
+                    change.start = old_words[i].start;
+                }
+                change.end = old_words[i].end;
+            }
+            Edit::Insert(j) => {
+                let change = &mut changes.last_mut().unwrap().1;
+                if change.start == change.end {
+                    change.start = new_words[j].start;
+                }
+                change.end = new_words[j].end;
+            }
+        }
+    }
+
+    // Part of the inserted text which is already written
+    let mut written = 0;
+    for (removed, added) in changes {
+        write_marked(f, "", "", &inserted[written..added.start])?;
+        write_marked(f, "[-", "-]", &deleted[removed])?;
+        write_marked(f, "{+", "+}", &inserted[added.clone()])?;
+        written = added.end;
+    }
+
+    write_marked(f, "", "", &inserted[written..])
+}
+
+/// Writes text with each of its lines enclosed in markers, leaving the newlines outside of them
+fn write_marked(f: &mut fmt::Formatter<'_>, open: &str, close: &str, text: &[u8]) -> fmt::Result {
+    let mut lines = text.split(|x| *x == b'\n').peekable();
+
+    while let Some(line) = lines.next() {
+        if !line.is_empty() {
+            write!(f, "{}{}{}", open, String::from_utf8_lossy(line), close)?;
+        }
+        if lines.peek().is_some() {
+            writeln!(f)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How the changes of a diff are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffFormat {
+    /// Unified diff
+    #[default]
+    Patch,
+    /// Unified diff showing which words of the changed lines differ
+    WordDiff,
+    /// Number of changed lines of each file with a histogram, followed by a summary
+    Stat,
+    /// Number of added and deleted lines of each file
+    Numstat,
+    /// Path of each file with a letter telling how it changed
+    NameStatus,
+}
+
+/// Options deciding how the changes of a diff are computed and shown
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    // Number of unchanged lines shown around each change
+    pub context: usize,
+    pub algorithm: Algorithm,
+    pub format: DiffFormat,
+    // Renames and copies are only detected if set
+    pub renames: Option<RenameOptions>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        DiffOptions {
+            context: 3,
+            algorithm: Algorithm::default(),
+            format: DiffFormat::default(),
+            renames: None,
+        }
+    }
+}
+
+/// Unified diff of files in git's patch format
+pub struct Diff {
+    // Changes sorted by path, Option::None for paths with unresolved merge conflicts
+    files: BTreeMap<String, Option<FileDiff>>,
+    options: DiffOptions,
+}
+
+impl Diff {
+    /// Creates the diff of changes selected by the given paths, if there are any. The new
+    /// versions of the files are read from the working directory instead of the database if
+    /// in_wd is set
+    fn new(
+        changes: Vec<TreeChange>,
+        unmerged: Vec<&str>,
+        in_wd: bool,
+        pathspecs: &[String],
+        options: DiffOptions,
+    ) -> Diff {
+        let mut files = BTreeMap::new();
+
+        for (old, new) in changes {
+            let path = old.as_ref().or(new.as_ref()).unwrap().path().to_owned();
+            if !matches_pathspec(&path, pathspecs) {
+                continue;
+            }
+
+            let file = FileDiff {
+                old: old.as_ref().map(Side::from_blob),
+                new: match in_wd {
+                    true => new.map(Side::from_wd_file),
+                    false => new.as_ref().map(Side::from_blob),
+                },
+                rename: None,
+            };
+            files.insert(path, Some(file));
+        }
+
+        if let Some(renames) = options.renames {
+            Diff::detect_renames(&mut files, renames);
+        }
+
+        for path in unmerged {
+            if matches_pathspec(path, pathspecs) {
+                files.insert(path.to_owned(), None);
+            }
+        }
+
+        Diff { files, options }
+    }
+
+    /// Turns added files which were renamed or copied from another file into a diff to that file,
+    /// the deleted files they were renamed from are dropped
+    fn detect_renames(files: &mut BTreeMap<String, Option<FileDiff>>, renames: RenameOptions) {
+        let pairs: Vec<(String, Rename)> = {
+            let candidates = |filter: fn(&FileDiff) -> Option<&Side>| -> Vec<Candidate> {
+                files
+                    .iter()
+                    .filter_map(|(path, file)| {
+                        let side = filter(file.as_ref()?)?;
+                        Some(Candidate {
+                            path,
+                            sha: side.entry.sha(),
+                            bytes: &side.bytes,
+                        })
+                    })
+                    .collect()
+            };
+            let deleted = candidates(|x| x.old.as_ref().filter(|_| x.new.is_none()));
+            let modified = candidates(|x| x.old.as_ref().filter(|_| x.new.is_some()));
+            let added = candidates(|x| x.new.as_ref().filter(|_| x.old.is_none()));
+
+            renames
+                .detect(&deleted, &modified, &added)
+                .into_iter()
+                .map(|(i, rename)| (added[i].path.to_owned(), rename))
+                .collect()
+        };
+
+        for (path, rename) in pairs {
+            let source = match rename.copy {
+                true => files[&rename.from].as_ref().and_then(|x| x.old.clone()),
+                false => files.remove(&rename.from).flatten().and_then(|x| x.old),
+            };
+            if let Some(Some(file)) = files.get_mut(&path) {
+                file.old = source;
+                file.rename = Some(rename);
+            }
+        }
+    }
+
+    /// Compares the files of the working directory with their versions in the index, limited to
+    /// the given paths if there are any
+    pub fn wd(index: &Index, pathspecs: &[String], options: DiffOptions) -> Diff {
+        let changes = index
+            .entries()
+            .iter()
+            .filter(|x| x.stage() == 0)
+            .map(|entry| (Some(entry.to_owned()), index.wd_entry(entry.path())))
+            .filter(|(old, new)| !same_version(old.as_ref(), new.as_ref()))
+            .collect();
+
+        Diff::new(changes, index.conflicts(), true, pathspecs, options)
+    }
+
+    /// Compares the files of a tree with the working directory, only files tracked by the index
+    /// count as present in the working directory
+    pub fn tree_to_wd(
+        tree: &Tree,
+        index: &Index,
+        pathspecs: &[String],
+        options: DiffOptions,
+    ) -> Diff {
+        let old: BTreeMap<String, index::Entry> = tree
+            .to_index_entries()
+            .into_iter()
+            .map(|x| (x.path().to_owned(), x))
+            .collect();
+        let paths: BTreeSet<&str> = old
+            .keys()
+            .map(|x| x.as_str())
+            .chain(
+                index
+                    .entries()
+                    .iter()
+                    .filter(|x| x.stage() == 0)
+                    .map(|x| x.path()),
+            )
+            .collect();
+
+        let changes = paths
+            .into_iter()
+            .map(|path| {
+                let new = index
+                    .entry(path)
+                    .filter(|x| x.stage() == 0)
+                    .and_then(|_| index.wd_entry(path));
+                (old.get(path).cloned(), new)
+            })
+            .filter(|(old, new)| !same_version(old.as_ref(), new.as_ref()))
+            .collect();
+
+        Diff::new(changes, index.conflicts(), true, pathspecs, options)
+    }
+
+    /// Compares the files of a tree, usually HEAD's, with the staged files of the index
+    pub fn cached(tree: &Tree, index: &Index, pathspecs: &[String], options: DiffOptions) -> Diff {
+        let staged = index
+            .entries()
+            .iter()
+            .filter(|x| x.stage() == 0)
+            .cloned()
+            .collect();
+
+        let changes = tree.changes(&Tree::new("ROOT", staged));
+        Diff::new(changes, index.conflicts(), false, pathspecs, options)
+    }
+
+    /// Compares the files of two trees
+    pub fn trees(old: &Tree, new: &Tree, pathspecs: &[String], options: DiffOptions) -> Diff {
+        Diff::new(old.changes(new), Vec::new(), false, pathspecs, options)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Limits the diff to a single file, returning the path the file had before the changes, which
+    /// differs if it was renamed or copied, or Option::None if the file didn't change
+    pub fn follow(&mut self, path: &str) -> Option<String> {
+        self.files.retain(|x, _| x == path);
+        let rename = self.files.get(path)?.as_ref()?.rename.as_ref();
+
+        Some(rename.map_or(path, |x| &x.from).to_owned())
+    }
+
+    /// Writes a line per file with the number of changed lines and a histogram of them, scaled
+    /// to the width of the terminal like git does, followed by a summary
+    fn write_stat(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.files.is_empty() {
+            return Ok(());
+        }
+
+        let files: Vec<_> = self
+            .files
+            .iter()
+            .map(|(path, file)| {
+                let stat = file.as_ref().map(|file| {
+                    let (added, deleted) = file.counts(self.options.algorithm);
+                    (file.is_binary(), added, deleted)
+                });
+                let name = match file {
+                    Some(file) => file.stat_path(path),
+                    None => quote_path(path, false),
+                };
+                (name, stat)
+            })
+            .collect();
+
+        let width = env::var("COLUMNS")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(STAT_WIDTH);
+
+        // Binary files show their sizes as "Bin X -> Y bytes", unmerged files "Unmerged"
+        let (mut max_change, mut max_len, mut bin_width, mut number_width) = (0, 0, 0, 0);
+        for (path, stat) in files.iter() {
+            max_len = max_len.max(path.chars().count());
+            match *stat {
+                Some((true, added, deleted)) => {
+                    bin_width = bin_width.max(14 + decimal_width(added) + decimal_width(deleted));
+                    number_width = 3;
+                }
+                Some((false, added, deleted)) => max_change = max_change.max(added + deleted),
+                None => bin_width = bin_width.max(8),
+            }
+        }
+        number_width = number_width.max(decimal_width(max_change));
+
+        // The graph gets at least 3/8 of the width if the names don't fit next to it
+        let width = width.max(16 + 6 + number_width);
+        let mut graph_width = match max_change + 4 > bin_width {
+            true => max_change,
+            false => bin_width - 4,
+        };
+        let mut name_width = max_len;
+        if name_width + number_width + 6 + graph_width > width {
+            if graph_width + number_width + 6 > width * 3 / 8 {
+                graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+            }
+            if name_width + number_width + 6 + graph_width > width {
+                name_width = width.saturating_sub(number_width + 6 + graph_width);
+            } else {
+                graph_width = width - number_width - 6 - name_width;
+            }
+        }
+
+        let (mut changed, mut insertions, mut deletions) = (0, 0, 0);
+        for (path, stat) in files.iter() {
+            // Names which are too long lose their start, up to the next directory if possible
+            let len = path.chars().count();
+            let (prefix, name) = match len > name_width {
+                true => {
+                    let name: String = path
+                        .chars()
+                        .skip(len - name_width.saturating_sub(3))
+                        .collect();
+                    match name.find('/') {
+                        Some(i) => ("...", name[i..].to_owned()),
+                        None => ("...", name),
+                    }
+                }
+                false => ("", path.to_owned()),
+            };
+            let padding = name_width.saturating_sub(prefix.len() + name.chars().count());
+            write!(f, " {}{}{:padding$} | ", prefix, name, "")?;
+
+            let Some((binary, added, deleted)) = *stat else {
+                writeln!(f, "{:>number_width$}", "Unmerged")?;
+                continue;
+            };
+            changed += 1;
+
+            if binary {
+                match added == 0 && deleted == 0 {
+                    true => writeln!(f, "{:>number_width$}", "Bin")?,
+                    false => {
+                        writeln!(f, "{:>number_width$} {} -> {} bytes", "Bin", deleted, added)?
+                    }
+                }
+                continue;
+            }
+            insertions += added;
+            deletions += deleted;
+
+            let (mut plus, mut minus) = (added, deleted);
+            if graph_width <= max_change {
+                let scale = |x: usize| match x {
+                    0 => 0,
+                    x => 1 + x * (graph_width - 1) / max_change,
+                };
+                let mut total = scale(added + deleted);
+                if total < 2 && added > 0 && deleted > 0 {
+                    total = 2;
+                }
+                if added < deleted {
+                    plus = scale(added);
+                    minus = total - plus;
+                } else {
+                    minus = scale(deleted);
+                    plus = total - minus;
+                }
+            }
+
+            write!(f, "{:>number_width$}", added + deleted)?;
+            if added + deleted > 0 {
+                write!(f, " ")?;
+            }
+            writeln!(f, "{}{}", "+".repeat(plus), "-".repeat(minus))?;
+        }
+
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        write!(f, " {} file{} changed", changed, plural(changed))?;
+        if insertions > 0 || deletions == 0 {
+            write!(f, ", {} insertion{}(+)", insertions, plural(insertions))?;
+        }
+        if deletions > 0 || insertions == 0 {
+            write!(f, ", {} deletion{}(-)", deletions, plural(deletions))?;
+        }
+        writeln!(f)
+    }
+}
+
+/// Number of digits of a number
+fn decimal_width(number: usize) -> usize {
+    number.to_string().len()
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.options.format == DiffFormat::Stat {
+            return self.write_stat(f);
+        }
+
+        for (path, file) in self.files.iter() {
+            match (self.options.format, file) {
+                (DiffFormat::Numstat, Some(file)) => match file.is_binary() {
+                    true => writeln!(f, "-\t-\t{}", file.stat_path(path))?,
+                    false => {
+                        let (added, deleted) = file.counts(self.options.algorithm);
+                        writeln!(f, "{}\t{}\t{}", added, deleted, file.stat_path(path))?
+                    }
+                },
+                (DiffFormat::Numstat, None) => writeln!(f, "0\t0\t{}", quote_path(path, false))?,
+                (DiffFormat::NameStatus, Some(file)) => {
+                    writeln!(f, "{}\t{}", file.status(), file.status_paths(path))?
+                }
+                (DiffFormat::NameStatus, None) => writeln!(f, "U\t{}", quote_path(path, false))?,
+                (_, Some(file)) => file.write(f, path, &self.options)?,
+                (_, None) => writeln!(f, "* Unmerged path {}", path)?,
+            }
+        }
+
+        Ok(())
+    }
+}
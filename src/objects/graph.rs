@@ -0,0 +1,529 @@
+// Characters of the edges of a merge's parents, from the leftmost one on
+const MERGE_CHARS: [char; 3] = ['/', '|', '\\'];
+
+/// Characters the graph is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphStyle {
+    Ascii,
+    Unicode,
+}
+
+impl GraphStyle {
+    pub fn from_name(name: &str) -> Option<GraphStyle> {
+        match name {
+            "ascii" => Some(GraphStyle::Ascii),
+            "unicode" => Some(GraphStyle::Unicode),
+            _ => None,
+        }
+    }
+
+    /// Translates the ascii characters git draws graphs with
+    fn char(&self, char: char) -> char {
+        match (self, char) {
+            (GraphStyle::Ascii, char) => char,
+            (GraphStyle::Unicode, '|') => '│',
+            (GraphStyle::Unicode, '/') => '╱',
+            (GraphStyle::Unicode, '\\') => '╲',
+            (GraphStyle::Unicode, '-') => '─',
+            (GraphStyle::Unicode, '.') => '╮',
+            (GraphStyle::Unicode, '*') => '●',
+            (GraphStyle::Unicode, char) => char,
+        }
+    }
+}
+
+/// What the next line of the graph shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    // Branch lines going straight down, between commits or next to their messages
+    Padding,
+    // Marks where a commit's lines weren't finished
+    Skip,
+    // Lines making room for the parents of an octopus merge
+    PreCommit,
+    Commit,
+    // Lines leading from a merge to its parents
+    PostMerge,
+    // Lines moving branch lines to the left where columns were freed
+    Collapsing,
+}
+
+/// Draws the history next to the commits of a log, one line at a time, as a column of branch
+/// lines for each commit still to be shown. A port of git's graph.c, so that the graph looks
+/// exactly like git's
+pub struct Graph {
+    style: GraphStyle,
+    commit: String,
+    // Parents of the commit which are shown too
+    parents: Vec<String>,
+    // Number of characters of each line of the current commit
+    width: isize,
+    // Number of the next pre-commit line
+    expansion_row: isize,
+    state: State,
+    prev_state: State,
+    // Column of the current commit
+    commit_index: isize,
+    prev_commit_index: isize,
+    // Whether the first parent of a merge is on the same column, 1, or to the left of it, 0
+    merge_layout: isize,
+    // Number of columns a merge adds
+    edges_added: isize,
+    prev_edges_added: isize,
+    // Commit each branch line leads to before and after the current commit
+    columns: Vec<String>,
+    new_columns: Vec<String>,
+    // Column of new_columns each character of the current line leads to, -1 for spaces
+    mapping: Vec<isize>,
+    old_mapping: Vec<isize>,
+    mapping_size: usize,
+}
+
+impl Graph {
+    pub fn new(style: GraphStyle) -> Graph {
+        Graph {
+            style,
+            commit: String::new(),
+            parents: Vec::new(),
+            width: 0,
+            expansion_row: 0,
+            state: State::Padding,
+            prev_state: State::Padding,
+            commit_index: 0,
+            prev_commit_index: 0,
+            merge_layout: 0,
+            edges_added: 0,
+            prev_edges_added: 0,
+            columns: Vec::new(),
+            new_columns: Vec::new(),
+            mapping: Vec::new(),
+            old_mapping: Vec::new(),
+            mapping_size: 0,
+        }
+    }
+
+    fn num_parents(&self) -> isize {
+        self.parents.len() as isize
+    }
+
+    fn num_columns(&self) -> isize {
+        self.columns.len() as isize
+    }
+
+    fn num_dashed_parents(&self) -> isize {
+        self.num_parents() + self.merge_layout - 3
+    }
+
+    fn num_expansion_rows(&self) -> isize {
+        self.num_dashed_parents() * 2
+    }
+
+    fn needs_pre_commit_line(&self) -> bool {
+        self.num_parents() >= 3
+            && self.commit_index < self.num_columns() - 1
+            && self.expansion_row < self.num_expansion_rows()
+    }
+
+    /// Whether all lines of the current commit have been drawn
+    pub fn is_finished(&self) -> bool {
+        self.state == State::Padding
+    }
+
+    fn update_state(&mut self, state: State) {
+        self.prev_state = self.state;
+        self.state = state;
+    }
+
+    /// Moves on to the next commit of the log, given with its parents which are shown as well
+    pub fn update(&mut self, commit: String, parents: Vec<String>) {
+        self.commit = commit;
+        self.parents = parents;
+        self.prev_commit_index = self.commit_index;
+        self.update_columns();
+        self.expansion_row = 0;
+
+        // The previous commit's lines weren't finished if it didn't get to the padding state
+        self.state = if self.state != State::Padding {
+            State::Skip
+        } else if self.needs_pre_commit_line() {
+            State::PreCommit
+        } else {
+            State::Commit
+        };
+    }
+
+    /// Computes the columns after the current commit, replacing it by its parents, and where
+    /// each branch line ends up after merges and collapsing
+    fn update_columns(&mut self) {
+        self.columns = std::mem::take(&mut self.new_columns);
+
+        let max_new_columns = self.columns.len() + self.parents.len();
+        self.mapping_size = 2 * max_new_columns;
+        if self.mapping.len() < self.mapping_size {
+            self.mapping.resize(self.mapping_size, -1);
+            self.old_mapping.resize(self.mapping_size, -1);
+        }
+        self.mapping[..self.mapping_size].fill(-1);
+
+        self.width = 0;
+        self.prev_edges_added = self.edges_added;
+        self.edges_added = 0;
+
+        // The commit gets a new column at the end if none of its children were shown
+        let mut seen_this = false;
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column.to_owned(),
+                None if seen_this => break,
+                None => self.commit.to_owned(),
+            };
+
+            if column == self.commit {
+                seen_this = true;
+                self.commit_index = i as isize;
+                self.merge_layout = -1;
+                let parents = self.parents.clone();
+                for parent in parents {
+                    self.insert_into_new_columns(parent, i as isize);
+                }
+                // The commit takes up 2 characters even without parents
+                if self.parents.is_empty() {
+                    self.width += 2;
+                }
+            } else {
+                self.insert_into_new_columns(column, -1);
+            }
+        }
+
+        while self.mapping_size > 1 && self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+    }
+
+    /// Adds a commit to the next columns unless it already has one, and maps the branch line
+    /// leading to it, coming from column index or -1 for branch lines of other commits
+    fn insert_into_new_columns(&mut self, commit: String, index: isize) {
+        let i = match self.new_columns.iter().position(|x| *x == commit) {
+            Some(i) => i,
+            None => {
+                self.new_columns.push(commit);
+                self.new_columns.len() - 1
+            }
+        } as isize;
+
+        let mapping_index;
+        if self.num_parents() > 1 && index > -1 && self.merge_layout == -1 {
+            // The first parent of a merge decides whether the merge's edges start from the
+            // merge's column or from the one left of it
+            let distance = index - i;
+            let shift = if distance > 1 { 2 * distance - 3 } else { 1 };
+
+            self.merge_layout = if distance > 0 { 0 } else { 1 };
+            self.edges_added = self.num_parents() + self.merge_layout - 2;
+
+            mapping_index = self.width + (self.merge_layout - 1) * shift;
+            self.width += 2 * self.merge_layout;
+        } else if self.edges_added > 0 && i == self.mapping[self.width as usize - 2] {
+            // The parent is in the last existing column, the edges join it right away
+            mapping_index = self.width - 2;
+            self.edges_added = -1;
+        } else {
+            mapping_index = self.width;
+            self.width += 2;
+        }
+
+        self.mapping[mapping_index as usize] = i;
+    }
+
+    fn is_mapping_correct(&self) -> bool {
+        self.mapping[..self.mapping_size]
+            .iter()
+            .enumerate()
+            .all(|(i, target)| *target < 0 || *target == i as isize / 2)
+    }
+
+    fn write(&self, line: &mut String, char: char) {
+        line.push(self.style.char(char));
+    }
+
+    /// Pads a line with spaces to the width of the current commit's lines, so that the text next
+    /// to the graph stays aligned
+    fn pad(&self, line: &mut String) {
+        let width = line.chars().count() as isize;
+        if width < self.width {
+            line.push_str(&" ".repeat((self.width - width) as usize));
+        }
+    }
+
+    /// Returns the next line of the graph, and whether it is the one of the commit
+    pub fn next_line(&mut self) -> (String, bool) {
+        let mut line = String::new();
+        let is_commit = self.state == State::Commit;
+
+        match self.state {
+            State::Padding => self.padding(&mut line),
+            State::Skip => self.skip(&mut line),
+            State::PreCommit => self.pre_commit(&mut line),
+            State::Commit => self.commit_line(&mut line),
+            State::PostMerge => self.post_merge(&mut line),
+            State::Collapsing => self.collapsing(&mut line),
+        }
+        self.pad(&mut line);
+
+        (line, is_commit)
+    }
+
+    /// Returns a line leaving all branch lines unchanged, the next line instead if the lines of
+    /// the commit aren't finished yet, unless the commit's own line is next
+    pub fn padding_line(&mut self) -> String {
+        if self.state != State::Commit {
+            return self.next_line().0;
+        }
+
+        let mut line = String::new();
+        for column in &self.columns {
+            self.write(&mut line, '|');
+            if *column == self.commit && self.num_parents() > 2 {
+                line.push_str(&" ".repeat((self.num_parents() as usize - 2) * 2));
+            } else {
+                line.push(' ');
+            }
+        }
+        self.pad(&mut line);
+        self.prev_state = State::Padding;
+
+        line
+    }
+
+    fn padding(&self, line: &mut String) {
+        for _ in &self.new_columns {
+            self.write(line, '|');
+            line.push(' ');
+        }
+    }
+
+    fn skip(&mut self, line: &mut String) {
+        line.push_str("...");
+
+        match self.needs_pre_commit_line() {
+            true => self.update_state(State::PreCommit),
+            false => self.update_state(State::Commit),
+        }
+    }
+
+    /// Moves the branch lines right of an octopus merge further to the right, making room for
+    /// the edges to its parents
+    fn pre_commit(&mut self, line: &mut String) {
+        let mut seen_this = false;
+
+        for (i, column) in self.columns.iter().enumerate() {
+            if *column == self.commit {
+                seen_this = true;
+                self.write(line, '|');
+                line.push_str(&" ".repeat(self.expansion_row as usize));
+            } else if seen_this && self.expansion_row == 0 {
+                // Branch lines after a merge's edges were already moving to the right
+                if self.prev_state == State::PostMerge && self.prev_commit_index < i as isize {
+                    self.write(line, '\\');
+                } else {
+                    self.write(line, '|');
+                }
+            } else if seen_this {
+                self.write(line, '\\');
+            } else {
+                self.write(line, '|');
+            }
+            line.push(' ');
+        }
+
+        self.expansion_row += 1;
+        if !self.needs_pre_commit_line() {
+            self.update_state(State::Commit);
+        }
+    }
+
+    fn commit_line(&mut self, line: &mut String) {
+        let mut seen_this = false;
+
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column,
+                None if seen_this => break,
+                None => &self.commit,
+            };
+            let i = i as isize;
+
+            if *column == self.commit {
+                seen_this = true;
+                self.write(line, '*');
+
+                // Dashes lead to the parents of octopus merges beyond the second one
+                let dashed_parents = self.num_dashed_parents();
+                for i in 0..dashed_parents {
+                    self.write(line, '-');
+                    self.write(line, if i == dashed_parents - 1 { '.' } else { '-' });
+                }
+            } else if seen_this && self.edges_added > 1 {
+                self.write(line, '\\');
+            } else if seen_this && self.edges_added == 1 {
+                // Continues branch lines which were moving right below the previous merge
+                if self.prev_state == State::PostMerge
+                    && self.prev_edges_added > 0
+                    && self.prev_commit_index < i
+                {
+                    self.write(line, '\\');
+                } else {
+                    self.write(line, '|');
+                }
+            } else if self.prev_state == State::Collapsing
+                && self.old_mapping.get(2 * i as usize + 1) == Some(&i)
+                && self.mapping.get(2 * i as usize).map_or(-1, |x| *x) < i
+            {
+                self.write(line, '/');
+            } else {
+                self.write(line, '|');
+            }
+            line.push(' ');
+        }
+
+        if self.num_parents() > 1 {
+            self.update_state(State::PostMerge);
+        } else if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        } else {
+            self.update_state(State::Collapsing);
+        }
+    }
+
+    fn post_merge(&mut self, line: &mut String) {
+        let mut seen_this = false;
+        // Whether the column of the merge's first parent is left of the current one
+        let mut seen_parent = false;
+
+        for i in 0..=self.columns.len() {
+            let column = match self.columns.get(i) {
+                Some(column) => column,
+                None if seen_this => break,
+                None => &self.commit,
+            };
+
+            if *column == self.commit {
+                seen_this = true;
+
+                let mut merge_char = self.merge_layout as usize;
+                for j in 0..self.parents.len() {
+                    self.write(line, MERGE_CHARS[merge_char]);
+                    if merge_char < 2 {
+                        merge_char += 1;
+                    } else if self.edges_added > 0 || j < self.parents.len() - 1 {
+                        line.push(' ');
+                    }
+                }
+                if self.edges_added == 0 {
+                    line.push(' ');
+                }
+            } else if seen_this {
+                match self.edges_added > 0 {
+                    true => self.write(line, '\\'),
+                    false => self.write(line, '|'),
+                }
+                line.push(' ');
+            } else {
+                self.write(line, '|');
+                if self.merge_layout != 0 || i as isize != self.commit_index - 1 {
+                    match seen_parent {
+                        true => self.write(line, '_'),
+                        false => line.push(' '),
+                    }
+                }
+            }
+
+            if *column == self.parents[0] {
+                seen_parent = true;
+            }
+        }
+
+        match self.is_mapping_correct() {
+            true => self.update_state(State::Padding),
+            false => self.update_state(State::Collapsing),
+        }
+    }
+
+    /// Moves each branch line which isn't in its column yet one step to the left, or all the
+    /// way along a horizontal edge for one of them
+    fn collapsing(&mut self, line: &mut String) {
+        std::mem::swap(&mut self.mapping, &mut self.old_mapping);
+        self.mapping[..self.mapping_size].fill(-1);
+
+        let mut used_horizontal = false;
+        let mut horizontal_edge = -1;
+        let mut horizontal_edge_target = -1;
+
+        for i in 0..self.mapping_size {
+            let target = self.old_mapping[i];
+            if target < 0 {
+                continue;
+            }
+
+            // Branch lines only ever move to the left, so that only one of two crossing lines
+            // changes its direction
+            let index = i as isize;
+            if target * 2 == index {
+                self.mapping[i] = target;
+            } else if self.mapping[i - 1] < 0 {
+                self.mapping[i - 1] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge = index;
+                    horizontal_edge_target = target;
+                    for j in ((target * 2 + 3)..(index - 2)).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            } else if self.mapping[i - 1] == target {
+                // Joins the branch line to the left, which leads to the same commit
+            } else {
+                // Crosses the branch line to the left, the space before it is free
+                self.mapping[i - 2] = target;
+                if horizontal_edge == -1 {
+                    horizontal_edge_target = target;
+                    horizontal_edge = index - 1;
+                    for j in ((target * 2 + 3)..(index - 2)).step_by(2) {
+                        self.mapping[j as usize] = target;
+                    }
+                }
+            }
+        }
+
+        self.old_mapping[..self.mapping_size].copy_from_slice(&self.mapping[..self.mapping_size]);
+        if self.mapping[self.mapping_size - 1] < 0 {
+            self.mapping_size -= 1;
+        }
+
+        for i in 0..self.mapping_size {
+            let target = self.mapping[i];
+            let index = i as isize;
+
+            if target < 0 {
+                line.push(' ');
+            } else if target * 2 == index {
+                self.write(line, '|');
+            } else if target == horizontal_edge_target && index != horizontal_edge - 1 {
+                // Only the first segment of the horizontal edge continues on the next line
+                if index != target * 2 + 3 {
+                    self.mapping[i] = -1;
+                }
+                used_horizontal = true;
+                self.write(line, '_');
+            } else {
+                if used_horizontal && index < horizontal_edge {
+                    self.mapping[i] = -1;
+                }
+                self.write(line, '/');
+            }
+        }
+
+        if self.is_mapping_correct() {
+            self.update_state(State::Padding);
+        }
+    }
+}
@@ -1,51 +1,262 @@
-use std::fs;
+use std::{fs, path};
 
-use crate::{objects::Commit, storing::Object, Paths};
+use crate::{
+    objects::Commit,
+    storing::{Object, Storable},
+    Paths,
+};
 
-/// Representation of a head ref in the .rgit/refs/heads directory
+// Prefix of the HEAD file when it points to a head ref instead of directly to a commit
+const SYMBOLIC_REF_PREFIX: &str = "ref: refs/heads/";
+
+/// Returns the names of all refs in a refs directory, sorted by name
+pub(super) fn ref_names(dir: String) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut dirs = vec![(dir, String::new())];
+
+    // Ref names containing slashes are stored in subdirectories
+    while let Some((dir, prefix)) = dirs.pop() {
+        for entry in fs::read_dir(dir).expect("Failed to read refs directory") {
+            let entry = entry.unwrap();
+            let name = prefix.to_owned() + &entry.file_name().to_string_lossy();
+
+            if entry.file_type().unwrap().is_dir() {
+                dirs.push((entry.path().to_string_lossy().to_string(), name + "/"));
+            } else {
+                names.push(name);
+            }
+        }
+    }
+
+    names.sort();
+    names
+}
+
+/// Representation of a head ref in the .rgit/refs/heads directory, or of a detached HEAD
+/// pointing directly to a commit
 pub struct Head {
-    name: String,
+    // None if the head is a detached HEAD
+    name: Option<String>,
     commit: Option<Commit>,
 }
 
 impl Head {
+    /// Creates a new head ref which only exists on disk once it is stored
+    pub fn new(name: String, commit: Commit) -> Head {
+        Head {
+            name: Some(name),
+            commit: Some(commit),
+        }
+    }
+
+    /// Creates a detached head pointing directly to a commit
+    pub fn detached(commit: Commit) -> Head {
+        Head {
+            name: None,
+            commit: Some(commit),
+        }
+    }
+
+    fn path(name: &str) -> String {
+        Paths::heads() + "/" + name
+    }
+
+    /// Reads a head ref, refs without a file are unborn branches without any commits yet
     fn read(name: String) -> Head {
-        let buf = fs::read(Paths::heads() + "/" + &name).unwrap();
+        let buf = fs::read_to_string(Head::path(&name)).unwrap_or_default();
+        let sha = buf.trim();
 
-        if buf.len() == 0 {
-            Head { name, commit: None }
+        if sha.is_empty() {
+            Head {
+                name: Some(name),
+                commit: None,
+            }
         } else {
-            let sha = String::from_utf8(buf).unwrap();
-            let commit = Commit::new_from_object_file(&sha, None);
+            let commit = Commit::new_from_object_file(sha, None);
 
             Head {
-                name,
+                name: Some(name),
                 commit: Some(commit),
             }
         }
     }
 
-    /// Returns the name of the current head ref
+    /// Reads the head ref of a name, returns Option::None if no such ref exists
+    pub fn try_read(name: &str) -> Option<Head> {
+        if Head::exists(name) {
+            Some(Head::read(String::from(name)))
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether a head ref of a valid name exists, so that names can't point outside of
+    /// the heads directory
+    pub fn exists(name: &str) -> bool {
+        Head::is_valid_name(name) && path::Path::new(&Head::path(name)).is_file()
+    }
+
+    /// Checks that a name can be used for a head ref, following a subset of git's rules
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.starts_with('-')
+            && !name.starts_with('/')
+            && !name.ends_with('/')
+            && !name.ends_with(".lock")
+            && !name.contains("..")
+            && !name.contains("//")
+            && !name.contains("@{")
+            && !name.split('/').any(|x| x.starts_with('.'))
+            && !name
+                .chars()
+                .any(|x| x.is_whitespace() || x.is_control() || "~^:?*[\\".contains(x))
+    }
+
+    /// Returns all head refs in the .rgit/refs/heads directory, sorted by name
+    pub fn all() -> Vec<Head> {
+        ref_names(Paths::heads())
+            .into_iter()
+            .map(Head::read)
+            .collect()
+    }
+
+    /// Returns the name of the head ref HEAD points to, or Option::None if HEAD is detached
     #[allow(non_snake_case)]
-    fn HEAD() -> String {
-        let buf = fs::read(Paths::HEAD()).unwrap();
-        let HEAD = String::from_utf8(buf).unwrap();
-        HEAD
+    fn HEAD() -> Option<String> {
+        let HEAD = fs::read_to_string(Paths::HEAD()).expect("Failed to read HEAD");
+        let HEAD = HEAD.trim();
+
+        if let Some(name) = HEAD.strip_prefix(SYMBOLIC_REF_PREFIX) {
+            Some(String::from(name))
+        } else if HEAD.len() == 40 && HEAD.chars().all(|x| x.is_ascii_hexdigit()) {
+            None
+        } else {
+            // Repositories created before the symbolic ref format hold the bare branch name
+            Some(String::from(HEAD))
+        }
     }
 
-    /// Returns the head ref currently pointed to in the HEAD file
+    /// Returns the head ref currently pointed to in the HEAD file, or a detached head
     #[allow(non_snake_case)]
     pub fn read_HEAD() -> Head {
-        Head::read(Head::HEAD())
+        match Head::HEAD() {
+            Some(name) => Head::read(name),
+            None => {
+                let sha = fs::read_to_string(Paths::HEAD()).unwrap();
+                Head::detached(Commit::new_from_object_file(sha.trim(), None))
+            }
+        }
     }
 
-    /// Updates the head ref file
+    /// Points the HEAD file to this head, as a symbolic ref or as a detached commit
+    #[allow(non_snake_case)]
+    pub fn write_HEAD(&self) {
+        let HEAD = match (&self.name, &self.commit) {
+            (Some(name), _) => format!("{}{}\n", SYMBOLIC_REF_PREFIX, name),
+            (None, Some(commit)) => commit.sha() + "\n",
+            (None, None) => panic!("Detached HEAD without a commit"),
+        };
+
+        fs::write(Paths::HEAD(), HEAD).expect("Failed to write HEAD");
+    }
+
+    /// Updates the head ref file, or the HEAD file itself if the head is detached
     #[allow(non_snake_case)]
     pub fn update(&self, commit: &Commit) {
-        fs::write(Paths::heads() + "/" + &self.name, commit.sha()).unwrap();
+        match &self.name {
+            Some(name) => {
+                let path = Head::path(name);
+                fs::create_dir_all(path::Path::new(&path).parent().unwrap()).unwrap();
+                fs::write(path, commit.sha() + "\n").unwrap();
+            }
+            None => Head::detached(commit.to_owned()).write_HEAD(),
+        }
+    }
+
+    /// Removes the directories of a ref name with slashes which are left empty, up to the heads
+    /// directory
+    fn remove_empty_dirs(name: &str) {
+        let mut dir = path::Path::new(name).parent();
+
+        while let Some(name) = dir.filter(|x| !x.as_os_str().is_empty()) {
+            // Fails if other refs are still in the directory, which is fine
+            if fs::remove_dir(Head::path(&name.to_string_lossy())).is_err() {
+                break;
+            }
+            dir = name.parent();
+        }
+    }
+
+    /// Removes the head ref file
+    pub fn delete(&self) {
+        let name = self.name.as_ref().expect("Cannot delete a detached HEAD");
+        assert!(Head::is_valid_name(name), "Invalid branch name {}", name);
+
+        fs::remove_file(Head::path(name))
+            .unwrap_or_else(|_| panic!("Failed to delete branch {}", name));
+        Head::remove_empty_dirs(name);
+    }
+
+    /// Moves the head ref file to a new name, keeping HEAD pointed at it if it is the current head.
+    /// Unborn branches have no ref file yet, so only HEAD changes
+    pub fn rename(&self, new_name: &str) {
+        let name = self.name.as_ref().expect("Cannot rename a detached HEAD");
+        for name in [name.as_str(), new_name] {
+            assert!(Head::is_valid_name(name), "Invalid branch name {}", name);
+        }
+
+        if Head::exists(name) {
+            let new_path = Head::path(new_name);
+            fs::create_dir_all(path::Path::new(&new_path).parent().unwrap()).unwrap();
+            fs::rename(Head::path(name), new_path)
+                .unwrap_or_else(|_| panic!("Failed to rename branch {}", name));
+            Head::remove_empty_dirs(name);
+        }
+
+        if self.is_current() {
+            Head::read(String::from(new_name)).write_HEAD();
+        }
+    }
+
+    /// Returns the name of the head ref, or Option::None if the head is detached
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn is_detached(&self) -> bool {
+        self.name.is_none()
     }
 
     pub fn commit(&self) -> &Option<Commit> {
         &self.commit
     }
+
+    /// Checks whether HEAD currently points to this head ref
+    pub fn is_current(&self) -> bool {
+        self.name.is_some() && Head::HEAD() == self.name
+    }
+
+    /// Describes the head the way status and branch listings show it
+    pub fn describe(&self) -> String {
+        match (&self.name, &self.commit) {
+            (Some(name), _) => format!("On branch {}", name),
+            (None, Some(commit)) => format!("HEAD detached at {}", &commit.sha()[..7]),
+            (None, None) => panic!("Detached HEAD without a commit"),
+        }
+    }
+}
+
+impl Storable for Head {
+    fn store(&self) {
+        let name = self
+            .name
+            .as_ref()
+            .expect("Cannot store a detached HEAD as a ref");
+        let path = Head::path(name);
+        fs::create_dir_all(path::Path::new(&path).parent().unwrap()).unwrap();
+
+        let sha = self.commit.as_ref().map(|x| x.sha() + "\n");
+        fs::write(path, sha.unwrap_or_default())
+            .unwrap_or_else(|_| panic!("Failed to write branch {}", name));
+    }
 }
@@ -1,25 +1,348 @@
-use crate::{objects::Blob, storing::Storable, IgnoreFilter, Paths};
-use glob;
+use crate::{
+    hex,
+    objects::{Blob, FsDatabase, ObjectDatabase},
+    storing::{Object, Storable},
+    IgnoreFilter, Paths,
+};
 use serde::{Deserialize, Serialize};
 use serde_json;
-use std::{collections::HashSet, fmt, fs, io, os::unix::prelude::MetadataExt, path};
+use sha1_smol::Sha1;
+use std::{fmt, fs, io, os::unix::prelude::MetadataExt, path, process};
+
+const SIGNATURE: &[u8] = b"DIRC";
+const DEFAULT_VERSION: u32 = 2;
+const HEADER_LEN: usize = 12;
+const CHECKSUM_LEN: usize = 20;
+// Offset of the flags in an entry, after ten 32 bit stat fields and the sha
+const FLAGS_OFFSET: usize = 60;
+const EXTENDED_FLAG: u16 = 0x4000;
+// Flags in the second flags field of entries with the extended flag
+const SKIP_WORKTREE_FLAG: u16 = 0x4000;
+const INTENT_TO_ADD_FLAG: u16 = 0x2000;
+const STAGE_SHIFT: u16 = 12;
+const NAME_MASK: u16 = 0xfff;
+const EMPTY_BLOB_SHA: &str = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+pub(super) const TYPE_MASK: u32 = 0o170000;
+pub(super) const SYMLINK_MODE: u32 = 0o120000;
+pub(super) const GITLINK_MODE: u32 = 0o160000;
+// Extensions caching what follows from the entries or the working directory, only written back
+// if the entries didn't change
+const CACHE_EXTENSIONS: [&[u8]; 3] = [b"TREE", b"UNTR", b"FSMN"];
+// Extensions holding offsets into the index file, which git writes anew every time
+const OFFSET_EXTENSIONS: [&[u8]; 2] = [b"EOIE", b"IEOT"];
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes(buf[pos..pos + 2].try_into().unwrap())
+}
+
+/// Encodes how many bytes of the previous path a v4 entry's path drops, using the same varint
+/// as OFS_DELTA offsets in packs
+fn encode_varint(mut value: usize) -> Vec<u8> {
+    let mut buf = vec![(value & 0x7f) as u8];
+    value >>= 7;
+
+    while value != 0 {
+        value -= 1;
+        buf.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+
+    buf.reverse();
+    buf
+}
+
+/// Decodes a varint, returning the value and the number of bytes it took up
+fn decode_varint(buf: &[u8]) -> (usize, usize) {
+    let mut len = 1;
+    let mut value = (buf[0] & 0x7f) as usize;
+
+    while buf[len - 1] & 0x80 != 0 {
+        value = ((value + 1) << 7) | (buf[len] & 0x7f) as usize;
+        len += 1;
+    }
 
+    (value, len)
+}
+
+/// The staging area, stored in git's binary index format in .rgit/index
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Index {
     entries: Vec<Entry>,
+    // Version of the index file format, 2 to 4, kept when the index is written back
+    #[serde(skip)]
+    version: u32,
+    // Modification time of the index file when it was read, files modified at the same time or
+    // later might have changed without their stat data showing it
+    #[serde(skip)]
+    timestamp: Option<(u32, u32)>,
+    // Extensions of the index file, e.g. git's TREE cache, by their signature
+    #[serde(skip)]
+    extensions: Vec<([u8; 4], Vec<u8>)>,
+    // Digest of the entries when they were read, telling whether cache extensions still hold
+    #[serde(skip)]
+    digest: Option<String>,
 }
 
 impl Index {
-    fn to_json_string(&self) -> String {
-        serde_json::to_string(self).unwrap()
+    pub fn new(entries: Vec<Entry>) -> Index {
+        Index {
+            entries,
+            version: DEFAULT_VERSION,
+            timestamp: None,
+            extensions: Vec::new(),
+            digest: None,
+        }
+    }
+
+    /// Exports the index as JSON for debugging
+    pub fn to_json_string(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
     }
 
     fn from_json_string(string: String) -> Index {
-        serde_json::from_str(&string).unwrap()
+        Index {
+            version: DEFAULT_VERSION,
+            ..serde_json::from_str(&string).expect("Failed to parse index.json")
+        }
+    }
+
+    /// Parses an index file, extensions are kept as they are to write them back
+    fn from_bytes(buf: &[u8]) -> Result<Index, String> {
+        if buf.len() < HEADER_LEN + CHECKSUM_LEN || !buf.starts_with(SIGNATURE) {
+            return Err("Index file is corrupt".to_owned());
+        }
+        let (content, checksum) = buf.split_at(buf.len() - CHECKSUM_LEN);
+        if Sha1::from(content).digest().bytes() != checksum {
+            return Err("Index file checksum mismatch".to_owned());
+        }
+
+        let version = read_u32(content, 4);
+        if !(2..=4).contains(&version) {
+            return Err(format!("Unsupported index version {}", version));
+        }
+        let count = read_u32(content, 8) as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = HEADER_LEN;
+        let mut previous_path: Vec<u8> = Vec::new();
+
+        for _ in 0..count {
+            let start = pos;
+            let field = |n: usize| read_u32(content, start + n * 4);
+            let stat = Stat {
+                ctime: field(0),
+                ctime_nsec: field(1),
+                mtime: field(2),
+                mtime_nsec: field(3),
+                dev: field(4),
+                ino: field(5),
+                uid: field(7),
+                gid: field(8),
+                size: field(9),
+            };
+            let mode = field(6);
+            let sha = hex::encode(&content[start + 40..start + FLAGS_OFFSET]);
+            let flags = read_u16(content, start + FLAGS_OFFSET);
+            pos = start + FLAGS_OFFSET + 2;
+
+            // Extended flags, e.g. skip-worktree, only exist since version 3
+            let mut extended_flags = 0;
+            if flags & EXTENDED_FLAG != 0 {
+                extended_flags = read_u16(content, pos);
+                pos += 2;
+            }
+
+            let path = if version == 4 {
+                // Paths are compressed by dropping the end of the previous path and appending
+                let (drop_len, varint_len) = decode_varint(&content[pos..]);
+                pos += varint_len;
+                let suffix_len = content[pos..].iter().position(|x| *x == 0).unwrap();
+
+                let mut path = previous_path[..previous_path.len() - drop_len].to_vec();
+                path.extend(&content[pos..pos + suffix_len]);
+                pos += suffix_len + 1;
+                path
+            } else {
+                let name_len = content[pos..].iter().position(|x| *x == 0).unwrap();
+                let path = content[pos..pos + name_len].to_vec();
+                // Entries are padded with one to eight null bytes to a multiple of eight bytes
+                pos = start + ((pos + name_len - start + 8) & !7);
+                path
+            };
+            previous_path = path.clone();
+            let path = String::from_utf8(path).map_err(|err| {
+                format!(
+                    "Index entry path '{}' is not valid utf-8",
+                    String::from_utf8_lossy(err.as_bytes())
+                )
+            })?;
+
+            entries.push(Entry {
+                mode,
+                path,
+                sha,
+                stage: ((flags >> STAGE_SHIFT) & 0b11) as u8,
+                stat,
+                extended_flags,
+            });
+        }
+
+        let mut extensions = Vec::new();
+        while pos < content.len() {
+            let signature: [u8; 4] = content[pos..pos + 4].try_into().unwrap();
+            let len = read_u32(content, pos + 4) as usize;
+            // Extensions starting with a lowercase letter change how the index has to be read,
+            // rgit can't keep them consistent with its entries and drops them
+            if signature[0].is_ascii_uppercase() {
+                extensions.push((signature, content[pos + 8..pos + 8 + len].to_vec()));
+            } else {
+                eprintln!(
+                    "warning: ignoring index extension {}",
+                    String::from_utf8_lossy(&signature)
+                );
+            }
+            pos += 8 + len;
+        }
+
+        let mut index = Index {
+            entries,
+            version,
+            timestamp: None,
+            extensions,
+            digest: None,
+        };
+        index.digest = Some(index.digest());
+
+        Ok(index)
+    }
+
+    fn sorted_entries(&self) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.entries.iter().collect();
+        entries.sort_by(|a, b| (a.path.as_bytes(), a.stage).cmp(&(b.path.as_bytes(), b.stage)));
+
+        entries
+    }
+
+    /// Hashes what cache extensions describe about the entries, without their stat data
+    fn digest(&self) -> String {
+        let mut hasher = Sha1::new();
+        for entry in self.sorted_entries() {
+            let line = format!(
+                "{:o} {} {} {}\0",
+                entry.git_mode(),
+                entry.stage,
+                entry.sha,
+                entry.path
+            );
+            hasher.update(line.as_bytes());
+        }
+
+        hasher.digest().to_string()
+    }
+
+    /// Serializes the index in git's binary format, sorted by path and stage
+    fn to_bytes(&self) -> Vec<u8> {
+        let entries = self.sorted_entries();
+        // Like git, version 2 indexes are upgraded if they have to hold extended flags
+        let version = match entries.iter().any(|x| x.extended_flags != 0) {
+            true => self.version.max(3),
+            false => self.version,
+        };
+
+        let mut buf = SIGNATURE.to_vec();
+        buf.extend(version.to_be_bytes());
+        buf.extend((entries.len() as u32).to_be_bytes());
+
+        let mut previous_path: &[u8] = &[];
+        for entry in entries {
+            let start = buf.len();
+            let stat = &entry.stat;
+
+            for field in [
+                stat.ctime,
+                stat.ctime_nsec,
+                stat.mtime,
+                stat.mtime_nsec,
+                stat.dev,
+                stat.ino,
+                entry.git_mode(),
+                stat.uid,
+                stat.gid,
+                stat.size,
+            ] {
+                buf.extend(field.to_be_bytes());
+            }
+            buf.extend(hex::decode(&entry.sha));
+
+            let path = entry.path.as_bytes();
+            let name_len = path.len().min(NAME_MASK as usize) as u16;
+            let mut flags = (entry.stage as u16) << STAGE_SHIFT | name_len;
+            if entry.extended_flags != 0 {
+                flags |= EXTENDED_FLAG;
+            }
+            buf.extend(flags.to_be_bytes());
+            if entry.extended_flags != 0 {
+                buf.extend(entry.extended_flags.to_be_bytes());
+            }
+
+            if version == 4 {
+                let common_len = path
+                    .iter()
+                    .zip(previous_path)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                buf.extend(encode_varint(previous_path.len() - common_len));
+                buf.extend(&path[common_len..]);
+                buf.push(0);
+            } else {
+                buf.extend(path);
+                let len = (buf.len() - start + 8) & !7;
+                buf.resize(start + len, 0);
+            }
+            previous_path = path;
+        }
+
+        let unchanged = self.digest.as_ref() == Some(&self.digest());
+        for (signature, data) in self.extensions.iter() {
+            if OFFSET_EXTENSIONS.contains(&&signature[..])
+                || (CACHE_EXTENSIONS.contains(&&signature[..]) && !unchanged)
+            {
+                continue;
+            }
+            buf.extend(signature);
+            buf.extend((data.len() as u32).to_be_bytes());
+            buf.extend(data);
+        }
+
+        let checksum = Sha1::from(&buf).digest().bytes();
+        buf.extend(checksum);
+
+        buf
     }
 
     fn update_index_file(&self) {
-        fs::write(Paths::index(), self.to_json_string()).expect("Failed to update index file")
+        fs::write(Paths::index(), self.to_bytes()).expect("Failed to update index file");
+
+        // Like git, entries of files modified in the same instant as the index are smudged, so
+        // that they are still hashed again once a later command writes the index
+        let timestamp = Stat::from(&fs::metadata(Paths::index()).unwrap()).mtime();
+        let is_racy = |x: &Entry| x.stat != Stat::default() && x.stat.mtime() >= timestamp;
+        if self.entries.iter().any(is_racy) {
+            let mut index = self.clone();
+            for entry in index.entries.iter_mut().filter(|x| is_racy(x)) {
+                entry.stat.size = 0;
+            }
+            fs::write(Paths::index(), index.to_bytes()).expect("Failed to update index file");
+        }
+
+        // Indexes of older repositories are converted to the binary format on their first write
+        if path::Path::new(&Paths::index_json()).exists() {
+            fs::remove_file(Paths::index_json()).expect("Failed to remove index.json");
+        }
     }
 
     /// Returns the index of an entry of the same path if it exists
@@ -27,51 +350,23 @@ impl Index {
     where
         T: AsRef<path::Path> + fmt::Display,
     {
-        let entry = self
-            .entries
+        self.entries
             .iter()
-            .enumerate()
-            .find(|(_, x)| x.path().eq(&path.to_string()));
-
-        match entry {
-            Some((i, _)) => return Some(i),
-            None => None,
-        }
+            .position(|x| x.path().eq(&path.to_string()))
     }
 
-    // TODO: Change so gitignore warnings appear only if necessary
-    /// Finds all individual paths for all recursively contained files of a certain path and calls
-    /// add_entry_from_path() for each of them
-    pub fn add(&mut self, mut path: String) {
-        let ignore_filter = IgnoreFilter::new(Paths::ignore());
+    /// Finds all individual paths for all recursively contained files of a certain path which
+    /// aren't ignored and calls add_entry_from_path() for each of them
+    pub fn add(&mut self, path: String) {
+        let ignore_filter = IgnoreFilter::new();
 
-        let paths: Vec<String> = if path::Path::is_dir(&path::Path::new(&path)) {
-            if !path.ends_with("/") {
-                path.push_str("/");
-            }
-
-            path.push_str("**/*");
-
-            glob::glob(&path)
-                .unwrap()
-                .map(|x| x.unwrap())
-                .filter(|x| !x.is_dir())
-                .map(|x| x.to_string_lossy().to_string())
-                .filter(|x| {
-                    let is_valid = ignore_filter.is_valid(x);
-                    if !is_valid {
-                        println!("{} is included in gitignore", x);
-                    }
-                    is_valid
-                })
-                .collect()
+        let paths: Vec<String> = if ignore_filter.is_ignored(&path) {
+            println!("{} is included in gitignore", path);
+            vec![]
+        } else if fs::symlink_metadata(&path).is_ok_and(|x| x.is_dir()) {
+            ignore_filter.files(&path)
         } else {
-            if !ignore_filter.is_valid(&path) {
-                println!("{} is included in gitignore", path);
-                vec![]
-            } else {
-                vec![path]
-            }
+            vec![path]
         };
 
         for path in paths.iter() {
@@ -82,36 +377,27 @@ impl Index {
         }
     }
 
-    // TODO: Not update if shas match
     /// Creates a new entry from the path and adds it to the index, updates files already existing in
     /// the index and removes files which only exist in the index and not at the path
     fn add_entry_from_path<T>(&mut self, path: T) -> Result<(), io::Error>
     where
         T: AsRef<path::Path> + fmt::Display,
     {
-        let new_maybe_entry = Entry::try_new(&path);
-        let existing_maybe_i = self.query_by_path(&path);
-
-        match new_maybe_entry {
-            None => match existing_maybe_i {
-                None => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::NotFound,
-                        format!("File {} not found", path),
-                    ))
-                }
-                Some(existing_i) => {
-                    self.entries.remove(existing_i);
-                }
-            },
+        let new_maybe_entry = self.wd_entry(&path.to_string());
 
-            Some(new_entry) => {
-                if let Some(existing_i) = existing_maybe_i {
-                    self.entries.remove(existing_i);
-                }
+        if new_maybe_entry.is_none() && self.query_by_path(&path).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("File {} not found", path),
+            ));
+        }
 
-                self.entries.push(new_entry);
-            }
+        // Adding a path marks it as resolved, so the entries of all conflict stages are replaced
+        let path = path.to_string();
+        self.entries.retain(|x| x.path() != path);
+
+        if let Some(new_entry) = new_maybe_entry {
+            self.entries.push(new_entry);
         }
 
         Ok(())
@@ -119,90 +405,237 @@ impl Index {
 
     /// Retrieves the index data stored in the index file
     pub fn new_from_index_file() -> Index {
-        let mut json_string =
-            fs::read_to_string(Paths::index()).expect(&format!("Failed to read index file"));
+        let mut index = match fs::read(Paths::index()) {
+            Ok(buf) => Index::from_bytes(&buf).unwrap_or_else(|err| {
+                eprintln!("fatal: {}", err);
+                process::exit(128)
+            }),
+            Err(_) => match fs::read_to_string(Paths::index_json()) {
+                Ok(json_string) if !json_string.is_empty() => Index::from_json_string(json_string),
+                // Repositories don't have an index until something is added
+                _ => Index::new(Vec::new()),
+            },
+        };
+        index.timestamp = fs::metadata(Paths::index())
+            .ok()
+            .map(|x| Stat::from(&x).mtime());
 
-        if json_string.is_empty() {
-            Index {
-                entries: Vec::new(),
-            }
-            .update_index_file();
+        index
+    }
 
-            json_string =
-                fs::read_to_string(Paths::index()).expect(&format!("Failed to read index file"));
-        }
+    /// Checks whether an entry's file could have been modified in the same instant the index was
+    /// written, which its stat data can't tell apart from being unchanged. Entries smudged when
+    /// an earlier index was written have a size of 0 without being empty
+    fn is_racy(&self, entry: &Entry) -> bool {
+        self.timestamp
+            .is_none_or(|timestamp| entry.stat.mtime() >= timestamp)
+            || (entry.stat.size == 0 && entry.sha != EMPTY_BLOB_SHA)
+    }
 
-        let index = Index::from_json_string(json_string);
+    /// Returns the version of a file in the working directory, or Option::None if there is no
+    /// such file. The sha of the index entry is reused without hashing the file again if the
+    /// file's stat data didn't change since the entry was created
+    pub fn wd_entry(&self, path: &str) -> Option<Entry> {
+        let indexed = self
+            .entries
+            .iter()
+            .find(|x| x.path() == path && x.stage() == 0);
 
-        index
-    }
+        // Files outside of a sparse checkout are taken to match the index, whether they exist
+        // or not
+        if let Some(entry) = indexed.filter(|x| x.is_skip_worktree()) {
+            return Some(entry.to_owned());
+        }
 
-    /// Prints the status as debug output
-    pub fn status(&self) {
-        let ignore_filter = IgnoreFilter::new(Paths::ignore());
+        let metadata = fs::symlink_metadata(path).ok()?;
 
-        let wd_entries: HashSet<Entry> = glob::glob("**/*.*")
-            .expect("Failed to read glob pattern")
-            .map(|x| x.unwrap())
-            .map(|x| x.to_string_lossy().to_string())
-            .filter(|x| ignore_filter.is_valid(x))
-            .map(|x| Entry::new_from_path(x))
-            .collect();
+        // rgit doesn't read the repositories of submodules, their directories are taken to be
+        // at the commit the index records
+        if metadata.is_dir() {
+            return indexed.filter(|x| x.git_mode() == GITLINK_MODE).cloned();
+        }
+        if !metadata.is_file() && !metadata.is_symlink() {
+            return None;
+        }
 
-        let index_entries: HashSet<Entry> = self.entries.iter().cloned().collect();
+        let stat = Stat::from(&metadata);
+        let cached = indexed.filter(|x| x.stat == stat && !self.is_racy(x));
+
+        match cached {
+            Some(entry) => Some(Entry {
+                mode: metadata.mode(),
+                path: String::from(path),
+                sha: entry.sha.to_owned(),
+                stage: 0,
+                stat,
+                extended_flags: 0,
+            }),
+            None => Entry::try_new(path),
+        }
+    }
 
-        let delta = &wd_entries - &index_entries;
+    /// Updates the stat data of entries whose files still hold the same content, so that they
+    /// don't have to be hashed again by later commands
+    pub fn refresh(&mut self) {
+        let refreshed: Vec<Option<Stat>> = self
+            .entries
+            .iter()
+            .map(|entry| {
+                self.wd_entry(entry.path())
+                    .filter(|x| entry.stage() == 0 && x.sha() == entry.sha())
+                    .map(|x| x.stat)
+            })
+            .collect();
 
-        // TODO: Sort diffrences
-        dbg!(&delta);
+        for (entry, stat) in self.entries.iter_mut().zip(refreshed) {
+            if let Some(stat) = stat {
+                entry.stat = stat;
+            }
+        }
     }
 
     pub fn entries(&self) -> &[Entry] {
         &self.entries
     }
+
+    /// Returns the entry of a path, for conflicted paths the first of its stages
+    pub fn entry(&self, path: &str) -> Option<&Entry> {
+        self.entries.iter().find(|x| x.path() == path)
+    }
+
+    /// Returns the sorted paths which still have unresolved merge conflicts
+    pub fn conflicts(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self
+            .entries
+            .iter()
+            .filter(|x| x.stage() != 0)
+            .map(|x| x.path())
+            .collect();
+        paths.sort();
+        paths.dedup();
+
+        paths
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|x| x.stage() != 0)
+    }
 }
 
 impl Storable for Index {
     fn store(&self) {
-        for entry in self.entries.iter() {
-            entry.store();
+        let errors: Vec<String> = self
+            .entries
+            .iter()
+            .filter_map(|x| x.store_blob().err())
+            .collect();
+        // Writing the index would leave entries whose blobs don't exist
+        if !errors.is_empty() {
+            for err in errors {
+                eprintln!("error: {}", err);
+            }
+            eprintln!("fatal: failed to write the index");
+            process::exit(128);
         }
 
         self.update_index_file()
     }
 }
 
+/// File system metadata of a file when its entry was created, used to tell whether the file
+/// changed without hashing it. Like in git's index all fields are truncated to 32 bits
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct Stat {
+    ctime: u32,
+    ctime_nsec: u32,
+    mtime: u32,
+    mtime_nsec: u32,
+    dev: u32,
+    ino: u32,
+    uid: u32,
+    gid: u32,
+    size: u32,
+}
+
+impl Stat {
+    fn mtime(&self) -> (u32, u32) {
+        (self.mtime, self.mtime_nsec)
+    }
+}
+
+impl From<&fs::Metadata> for Stat {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Stat {
+            ctime: metadata.ctime() as u32,
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime() as u32,
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+        }
+    }
+}
+
 /// Holds the metadata about a file in the working directory
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Debug)]
 pub struct Entry {
     mode: u32,
     path: String,
     sha: String,
+    // 0 for regular entries, conflicted paths have an entry for each of the base (1), ours (2)
+    // and theirs (3) version that exists
+    #[serde(default)]
+    stage: u8,
+    // Entries which weren't created from a file, e.g. from a tree, never match any stat data
+    #[serde(default)]
+    stat: Stat,
+    // Flags of version 3 and later, skip-worktree and intent-to-add, kept as they were read
+    #[serde(default)]
+    extended_flags: u16,
 }
 
 impl Entry {
-    /// Reads the metadata of the file at the path and creates an Entry from it
-    pub fn new_from_path(path: impl AsRef<path::Path> + fmt::Display) -> Entry {
-        let mode = fs::File::open(&path)
-            .expect(&format!("Failed to open {} to retrieve metadata", path))
-            .metadata()
-            .expect(&format!("Failed to retrieve metadata for {}", path))
-            .mode();
-
-        let sha = Blob::new_from_wd_file(&path).sha().into();
+    /// Creates an entry for a blob which doesn't have to exist in the working directory
+    pub fn new(mode: u32, path: String, sha: String) -> Entry {
+        Entry::new_staged(mode, path, sha, 0)
+    }
 
+    /// Creates an entry for one of the versions of a path with merge conflicts
+    pub fn new_staged(mode: u32, path: String, sha: String, stage: u8) -> Entry {
         Entry {
             mode,
-            path: path.to_string(),
+            path,
             sha,
+            stage,
+            stat: Stat::default(),
+            extended_flags: 0,
+        }
+    }
+
+    /// Reads the metadata of the file at the path and creates an Entry from it, a symlink isn't
+    /// followed but stored as the path it points to
+    pub fn new_from_path(path: impl AsRef<path::Path> + fmt::Display) -> Entry {
+        let metadata = fs::symlink_metadata(&path)
+            .unwrap_or_else(|_| panic!("Failed to retrieve metadata for {}", path));
+
+        let sha = Blob::try_new_from_wd(&path)
+            .unwrap_or_else(|| panic!("Failed to read {} to create blob", path))
+            .sha();
+
+        Entry {
+            stat: Stat::from(&metadata),
+            ..Entry::new(metadata.mode(), path.to_string(), sha)
         }
     }
 
     /// Tries to create an entry from a path, returns Option::None if the path doesn't exists
     pub fn try_new(path: impl AsRef<path::Path> + fmt::Display) -> Option<Entry> {
-        if !path::Path::try_exists(path.as_ref()).unwrap() {
-            return None;
-        }
+        // Symlinks exist even if the path they point to doesn't
+        fs::symlink_metadata(&path).ok()?;
 
         Some(Entry::new_from_path(path))
     }
@@ -223,17 +656,228 @@ impl Entry {
         &self.sha
     }
 
+    pub fn stage(&self) -> u8 {
+        self.stage
+    }
+
     pub fn is_executable(&self) -> bool {
-        (self.mode & 0o001) != 0
+        (self.mode & 0o100) != 0
+    }
+
+    /// Mode as git records it, symlinks and submodules keep theirs while files are only
+    /// executable or not
+    pub fn git_mode(&self) -> u32 {
+        match self.mode & TYPE_MASK {
+            SYMLINK_MODE | GITLINK_MODE => self.mode & TYPE_MASK,
+            _ if self.is_executable() => 0o100755,
+            _ => 0o100644,
+        }
+    }
+
+    /// Whether the file is outside of a sparse checkout and not in the working directory
+    pub fn is_skip_worktree(&self) -> bool {
+        self.extended_flags & SKIP_WORKTREE_FLAG != 0
+    }
+
+    /// Whether the path was added with `git add -N`, which records it without its content
+    pub fn is_intent_to_add(&self) -> bool {
+        self.extended_flags & INTENT_TO_ADD_FLAG != 0
     }
 
     pub fn is_top_level(&self) -> bool {
-        !self.path.contains("/")
+        !self.path.contains('/')
+    }
+
+    /// Stores the blob of the entry from its file if the database doesn't have it yet. The file
+    /// might have changed since the entry was created, then the entry's content is lost
+    fn store_blob(&self) -> Result<(), String> {
+        // Submodule commits are stored in the submodule's own repository and paths only intended
+        // to be added don't have any content yet
+        if self.git_mode() == GITLINK_MODE
+            || self.is_intent_to_add()
+            || FsDatabase.exists(&self.sha)
+        {
+            return Ok(());
+        }
+
+        match Blob::try_new_from_wd(&self.path) {
+            Some(blob) if blob.sha() == self.sha => {
+                blob.store();
+                Ok(())
+            }
+            _ => Err(format!(
+                "unable to store {} {}: the file changed since it was added",
+                self.sha, self.path
+            )),
+        }
     }
 }
 
-impl Storable for Entry {
-    fn store(&self) {
-        Blob::new_from_wd_file(&self.path).store();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad";
+
+    fn index(version: u32) -> Index {
+        let mut stat = Stat {
+            mtime: 1700000000,
+            size: 12,
+            ..Stat::default()
+        };
+        let mut entry = |mode: u32, path: &str, stage: u8| {
+            stat.ino += 1;
+            Entry {
+                mode,
+                path: path.to_owned(),
+                sha: SHA.to_owned(),
+                stage,
+                stat,
+                extended_flags: 0,
+            }
+        };
+
+        Index {
+            version,
+            ..Index::new(vec![
+                entry(0o100644, "src/main.rs", 0),
+                entry(0o100755, "build.sh", 0),
+                entry(SYMLINK_MODE, "src/link", 0),
+                entry(GITLINK_MODE, "vendor/lib", 0),
+                entry(0o100644, "src/conflict.rs", 3),
+                entry(0o100644, "src/conflict.rs", 2),
+                entry(0o100644, "src/conflict.rs", 1),
+            ])
+        }
+    }
+
+    #[test]
+    fn distrusts_files_modified_with_the_index() {
+        let entry = Entry {
+            stat: Stat {
+                mtime: 100,
+                mtime_nsec: 5,
+                size: 12,
+                ..Stat::default()
+            },
+            ..Entry::new(0o100644, "file".to_owned(), SHA.to_owned())
+        };
+        let mut index = Index::new(Vec::new());
+
+        // Without an index file every entry might have changed
+        assert!(index.is_racy(&entry));
+        index.timestamp = Some((100, 5));
+        assert!(index.is_racy(&entry));
+        index.timestamp = Some((100, 6));
+        assert!(!index.is_racy(&entry));
+
+        // Entries smudged when the index was written are hashed again
+        let smudged = Entry {
+            stat: Stat {
+                size: 0,
+                ..entry.stat
+            },
+            ..entry.clone()
+        };
+        assert!(index.is_racy(&smudged));
+    }
+
+    #[test]
+    fn round_trips_all_versions() {
+        for version in 2..=4 {
+            let index = index(version);
+            let read = Index::from_bytes(&index.to_bytes()).unwrap();
+
+            assert_eq!(read.version, version);
+            assert_eq!(
+                read.entries,
+                index
+                    .sorted_entries()
+                    .into_iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!(read.to_bytes(), index.to_bytes());
+        }
+    }
+
+    #[test]
+    fn compresses_paths_in_version_4() {
+        assert!(index(4).to_bytes().len() < index(2).to_bytes().len());
+    }
+
+    #[test]
+    fn reads_extended_flags() {
+        let path = b"extended";
+        let mut buf = SIGNATURE.to_vec();
+        buf.extend(3u32.to_be_bytes());
+        buf.extend(1u32.to_be_bytes());
+        for field in [0, 0, 0, 0, 0, 0, 0o100644u32, 0, 0, 0] {
+            buf.extend(field.to_be_bytes());
+        }
+        buf.extend(hex::decode(SHA));
+        buf.extend((EXTENDED_FLAG | path.len() as u16).to_be_bytes());
+        // Skip-worktree
+        buf.extend(0x4000u16.to_be_bytes());
+        buf.extend(path);
+        buf.resize(HEADER_LEN + ((FLAGS_OFFSET + 4 + path.len() + 8) & !7), 0);
+        buf.extend(Sha1::from(&buf).digest().bytes());
+
+        let index = Index::from_bytes(&buf).unwrap();
+        assert_eq!(
+            index.entries,
+            vec![Entry {
+                extended_flags: 0x4000,
+                ..Entry::new(0o100644, "extended".to_owned(), SHA.to_owned())
+            }]
+        );
+        assert!(index.entries[0].is_skip_worktree());
+        assert_eq!(index.to_bytes(), buf);
+    }
+
+    #[test]
+    fn keeps_extensions_which_still_hold() {
+        let mut index = index(2);
+        index.extensions = vec![
+            (*b"TREE", b"cached tree".to_vec()),
+            (*b"REUC", b"resolved conflicts".to_vec()),
+            (*b"EOIE", b"entry offsets".to_vec()),
+        ];
+        index.digest = Some(index.digest());
+
+        let read = Index::from_bytes(&index.to_bytes()).unwrap();
+        let signatures: Vec<&[u8]> = read.extensions.iter().map(|x| &x.0[..]).collect();
+        assert_eq!(signatures, [b"TREE", b"REUC"]);
+
+        index.entries.pop();
+        let read = Index::from_bytes(&index.to_bytes()).unwrap();
+        let signatures: Vec<&[u8]> = read.extensions.iter().map(|x| &x.0[..]).collect();
+        assert_eq!(signatures, [b"REUC"]);
+    }
+
+    #[test]
+    fn drops_extensions_it_cant_read() {
+        let mut index = index(2);
+        index.extensions = vec![
+            (*b"link", b"split index".to_vec()),
+            (*b"REUC", b"resolved conflicts".to_vec()),
+        ];
+
+        let read = Index::from_bytes(&index.to_bytes()).unwrap();
+        let signatures: Vec<&[u8]> = read.extensions.iter().map(|x| &x.0[..]).collect();
+        assert_eq!(signatures, [b"REUC"]);
+    }
+
+    #[test]
+    fn rejects_paths_which_arent_utf8() {
+        let mut index = index(2);
+        index.entries.truncate(1);
+        let mut buf = index.to_bytes();
+        buf.truncate(buf.len() - CHECKSUM_LEN);
+        let pos = HEADER_LEN + FLAGS_OFFSET + 2;
+        buf[pos] = 0xff;
+        buf.extend(Sha1::from(&buf).digest().bytes());
+
+        assert!(Index::from_bytes(&buf).is_err());
     }
 }
@@ -0,0 +1,557 @@
+use super::{
+    graph::{Graph, GraphStyle},
+    signature,
+};
+use crate::{
+    objects::{Commit, Diff, DiffOptions, Head, Signature, Tag},
+    storing::Object,
+    Config,
+};
+use regex::{Regex, RegexBuilder};
+use std::collections::HashMap;
+
+/// Number of characters abbreviated shas are shortened to
+const ABBREV_LEN: usize = 7;
+
+/// Splits a commit message into its subject, the first paragraph joined into one line, and its
+/// body, everything after it
+fn split_message(message: &str) -> (String, &str) {
+    let message = message.trim_start_matches('\n');
+    let end = message.find("\n\n").unwrap_or(message.len());
+
+    let subject: Vec<&str> = message[..end].lines().map(str::trim).collect();
+    (subject.join(" "), message[end..].trim_start_matches('\n'))
+}
+
+/// Translates a POSIX basic regular expression, which git matches --author and --grep with, to
+/// the syntax of the regex crate. Like in GNU grep, groups, alternatives and repetitions other
+/// than * are written with a backslash, without one the characters match themselves
+fn translate_basic_regex(pattern: &str) -> String {
+    let mut regex = String::new();
+    let mut chars = pattern.chars().peekable();
+    // At the start of the pattern or a group ^ is an anchor and * matches itself
+    let mut at_start = true;
+
+    while let Some(c) = chars.next() {
+        let was_at_start = std::mem::take(&mut at_start);
+
+        match c {
+            '\\' => match chars.next() {
+                Some(c @ ('(' | '|')) => {
+                    regex.push(c);
+                    at_start = true;
+                }
+                Some(c @ (')' | '{' | '}' | '+' | '?')) => regex.push(c),
+                Some('<' | '>') => regex.push_str(r"\b"),
+                Some(c) => {
+                    regex.push('\\');
+                    regex.push(c);
+                }
+                None => regex.push_str(r"\\"),
+            },
+            '^' if was_at_start => {
+                regex.push('^');
+                at_start = true;
+            }
+            '*' if was_at_start => regex.push_str(r"\*"),
+            // $ is only an anchor at the end of the pattern or a group
+            '$' if chars.peek().is_none()
+                || chars.clone().take(2).eq(['\\', ')'])
+                || chars.clone().take(2).eq(['\\', '|']) =>
+            {
+                regex.push('$')
+            }
+            '^' | '$' | '(' | ')' | '{' | '}' | '|' | '+' | '?' => {
+                regex.push('\\');
+                regex.push(c);
+            }
+            '[' => {
+                regex.push('[');
+                if chars.next_if_eq(&'^').is_some() {
+                    regex.push('^');
+                }
+                // A ] right after the opening bracket is part of the set
+                if chars.next_if_eq(&']').is_some() {
+                    regex.push_str(r"\]");
+                }
+                while let Some(c) = chars.next() {
+                    match c {
+                        ']' => {
+                            regex.push(']');
+                            break;
+                        }
+                        // Character classes like [:alpha:] are written the same way
+                        '[' if chars.peek() == Some(&':') => {
+                            regex.push('[');
+                            while let Some(c) = chars.next() {
+                                regex.push(c);
+                                if c == ':' && chars.peek() == Some(&']') {
+                                    regex.push(chars.next().unwrap());
+                                    break;
+                                }
+                            }
+                        }
+                        // Backslashes match themselves in brackets, the others would start
+                        // nested sets or set operations
+                        '\\' | '[' | '&' | '~' => {
+                            regex.push('\\');
+                            regex.push(c);
+                        }
+                        c => regex.push(c),
+                    }
+                }
+            }
+            c => regex.push(c),
+        }
+    }
+
+    regex
+}
+
+/// Names of the branches and tags pointing at each commit by its sha, in git's order: HEAD first,
+/// together with the branch it is on, then tags and branches in reverse order of their names
+fn decorations() -> HashMap<String, String> {
+    let mut names: HashMap<String, Vec<String>> = HashMap::new();
+
+    for branch in Head::all().iter().filter(|x| !x.is_current()) {
+        if let (Some(name), Some(commit)) = (branch.name(), branch.commit()) {
+            names.entry(commit.sha()).or_default().push(name.to_owned());
+        }
+    }
+    for tag in Tag::all() {
+        if let Some(commit) = tag.commit() {
+            let name = format!("tag: {}", tag.name());
+            names.entry(commit.sha()).or_default().push(name);
+        }
+    }
+
+    let head = Head::read_HEAD();
+    if let Some(commit) = head.commit() {
+        let name = match head.name() {
+            Some(branch) => format!("HEAD -> {}", branch),
+            None => String::from("HEAD"),
+        };
+        names.entry(commit.sha()).or_default().push(name);
+    }
+
+    names
+        .into_iter()
+        .map(|(sha, names)| {
+            let names: Vec<String> = names.into_iter().rev().collect();
+            (sha, names.join(", "))
+        })
+        .collect()
+}
+
+/// Expands the placeholder at the start of spec, returning its value and length, or Option::None
+/// if spec doesn't start with a known placeholder. Refs are the names decorating the commit
+fn placeholder(spec: &str, commit: &Commit, refs: &str) -> Option<(String, usize)> {
+    let sha = commit.sha();
+    let parents = commit.parent_shas();
+
+    let value = match spec.chars().next()? {
+        'H' => sha,
+        'h' => sha[..ABBREV_LEN].to_owned(),
+        'T' => commit.tree_sha().to_owned(),
+        't' => commit.tree_sha()[..ABBREV_LEN].to_owned(),
+        'P' => parents.join(" "),
+        'p' => {
+            let parents: Vec<&str> = parents.iter().map(|x| &x[..ABBREV_LEN]).collect();
+            parents.join(" ")
+        }
+        's' => split_message(commit.message()).0,
+        'b' => split_message(commit.message()).1.to_owned(),
+        'd' if !refs.is_empty() => format!(" ({})", refs),
+        'd' => String::new(),
+        'D' => refs.to_owned(),
+        'n' => "\n".to_owned(),
+        '%' => "%".to_owned(),
+        role @ ('a' | 'c') => {
+            let signature = match role {
+                'a' => commit.author(),
+                _ => commit.committer(),
+            };
+            let field: fn(&Signature) -> String = match spec.chars().nth(1)? {
+                'n' => |x| x.name().to_owned(),
+                'e' => |x| x.email().to_owned(),
+                'd' => Signature::format_date,
+                _ => return None,
+            };
+            return Some((signature.as_ref().map(field).unwrap_or_default(), 2));
+        }
+        _ => return None,
+    };
+
+    Some((value, 1))
+}
+
+/// How each commit of a log is shown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Sha, merged parents, author, date and the indented message, git's default
+    Medium,
+    /// Sha and subject on a single line
+    Oneline { abbrev: bool },
+    /// A template of placeholders like %h or %s, either terminating or separating commits
+    Custom { template: String, terminated: bool },
+}
+
+impl LogFormat {
+    /// Parses the value of --format, either the name of a format or a template in which
+    /// `tformat:` or `format:` can choose whether each commit ends with a newline
+    pub fn parse(value: &str) -> Result<LogFormat, String> {
+        let custom = |template: &str, terminated| LogFormat::Custom {
+            template: template.to_owned(),
+            terminated,
+        };
+
+        match value {
+            "medium" => Ok(LogFormat::Medium),
+            "oneline" => Ok(LogFormat::Oneline { abbrev: false }),
+            _ => match (
+                value.strip_prefix("tformat:"),
+                value.strip_prefix("format:"),
+            ) {
+                (Some(template), _) => Ok(custom(template, true)),
+                (_, Some(template)) => Ok(custom(template, false)),
+                _ if value.contains('%') => Ok(custom(value, true)),
+                _ => Err(format!("invalid --pretty format: {}", value)),
+            },
+        }
+    }
+
+    /// Whether each commit ends with a newline, instead of being separated by one from the next
+    pub fn is_terminated(&self) -> bool {
+        matches!(
+            self,
+            LogFormat::Oneline { .. }
+                | LogFormat::Custom {
+                    terminated: true,
+                    ..
+                }
+        )
+    }
+
+    /// Formats the description of a commit shown above its changes, without the newline
+    /// terminating it. Refs are the names decorating the commit, which the medium and oneline
+    /// formats show if decorate is set and templates with %d and %D
+    pub fn format(&self, commit: &Commit, refs: &str, decorate: bool) -> String {
+        let decoration = match decorate && !refs.is_empty() {
+            true => format!(" ({})", refs),
+            false => String::new(),
+        };
+
+        match self {
+            LogFormat::Medium => {
+                let mut lines = vec![format!("commit {}{}", commit.sha(), decoration)];
+                if commit.is_merge() {
+                    let parents: Vec<&str> = commit
+                        .parent_shas()
+                        .iter()
+                        .map(|x| &x[..ABBREV_LEN])
+                        .collect();
+                    lines.push(format!("Merge: {}", parents.join(" ")));
+                }
+                if let Some(author) = commit.author() {
+                    lines.push(format!("Author: {} <{}>", author.name(), author.email()));
+                    lines.push(format!("Date:   {}", author.format_date()));
+                }
+                lines.push(String::new());
+                for line in commit.message().trim_end().lines() {
+                    lines.push(format!("    {}", line));
+                }
+
+                lines.join("\n") + "\n"
+            }
+            LogFormat::Oneline { abbrev } => {
+                let sha = commit.sha();
+                let sha = if *abbrev { &sha[..ABBREV_LEN] } else { &sha };
+                format!(
+                    "{}{} {}",
+                    sha,
+                    decoration,
+                    split_message(commit.message()).0
+                )
+            }
+            LogFormat::Custom { template, .. } => {
+                let mut output = String::new();
+                let mut rest = template.as_str();
+
+                // Unknown placeholders are kept as they are, like in git
+                while let Some(i) = rest.find('%') {
+                    output.push_str(&rest[..i]);
+                    rest = &rest[i + 1..];
+                    match placeholder(rest, commit, refs) {
+                        Some((value, len)) => {
+                            output.push_str(&value);
+                            rest = &rest[len..];
+                        }
+                        None => output.push('%'),
+                    }
+                }
+                output.push_str(rest);
+
+                output
+            }
+        }
+    }
+}
+
+/// Selects which commits a log shows and how
+#[derive(Debug, Clone)]
+pub struct LogOptions {
+    pub diff: DiffOptions,
+    // Whether each commit's changes are shown with the diff options
+    pub show_changes: bool,
+    pub format: LogFormat,
+    // Whether branches and tags are shown next to the commits they point to
+    pub decorate: bool,
+    pub graph: Option<GraphStyle>,
+    // Shows the history of all branches and tags instead of HEAD's
+    pub all: bool,
+    // File whose history is shown across renames
+    pub follow: Option<String>,
+    // Only commits changing these files or directories are shown
+    pub paths: Vec<String>,
+    pub max_count: Option<usize>,
+    // Oldest commits first, after max_count limited the log to the newest ones
+    pub reverse: bool,
+    // Range of commit dates as unix timestamps
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    // Commits are shown if their author matches any of the authors and their message any of
+    // the greps
+    pub authors: Vec<Regex>,
+    pub greps: Vec<Regex>,
+}
+
+impl LogOptions {
+    /// Parses the value of --since and --until into a unix timestamp
+    pub fn parse_time(value: &str) -> Result<i64, String> {
+        signature::parse_approxidate(value).ok_or_else(|| format!("invalid date {}", value))
+    }
+
+    /// Parses the value of --author and --grep, a basic regular expression like git's
+    pub fn parse_pattern(value: &str) -> Result<Regex, String> {
+        // ^ and $ match at the start and end of every line, git matches messages as a whole
+        RegexBuilder::new(&translate_basic_regex(value))
+            .multi_line(true)
+            .build()
+            .map_err(|_| format!("invalid regular expression {}", value))
+    }
+
+    /// Whether commits are decorated unless --decorate or --no-decorate is given: as the
+    /// log.decorate config says, or like git only if the log is shown on a terminal
+    pub fn decorate_by_default() -> bool {
+        let config = Config::load();
+
+        match config
+            .get("log.decorate")
+            .map(|x| x.to_lowercase())
+            .as_deref()
+        {
+            Some("false" | "no" | "off" | "0") => false,
+            Some("auto") | None => {
+                // SAFETY: isatty only inspects the file descriptor
+                unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+            }
+            Some(_) => true,
+        }
+    }
+
+    /// Checks whether a commit is within the dates and its author and message match one of the
+    /// given patterns each
+    pub fn matches(&self, commit: &Commit) -> bool {
+        let time = commit.committer().map_or(0, |x| x.time());
+        let author = commit
+            .author()
+            .map(|x| format!("{} <{}>", x.name(), x.email()))
+            .unwrap_or_default();
+        let any = |patterns: &[Regex], text: &str| {
+            patterns.is_empty() || patterns.iter().any(|x| x.is_match(text))
+        };
+
+        self.since.is_none_or(|x| time >= x)
+            && self.until.is_none_or(|x| time <= x)
+            && any(&self.authors, &author)
+            && any(&self.greps, commit.message())
+    }
+}
+
+/// Writes the commits of a log one after another the way git does, with the graph drawn next to
+/// them if there is one
+pub struct LogWriter<'a> {
+    options: &'a LogOptions,
+    graph: Option<Graph>,
+    decorations: HashMap<String, String>,
+    shown_one: bool,
+    // Whether the text of the previous commit didn't end with a newline
+    missing_newline: bool,
+}
+
+impl<'a> LogWriter<'a> {
+    pub fn new(options: &'a LogOptions) -> LogWriter<'a> {
+        LogWriter {
+            options,
+            graph: options.graph.map(Graph::new),
+            decorations: decorations(),
+            shown_one: false,
+            missing_newline: false,
+        }
+    }
+
+    /// Returns the next line of the graph, nothing without a graph
+    fn next_line(&mut self) -> String {
+        self.graph
+            .as_mut()
+            .map_or(String::new(), |x| x.next_line().0)
+    }
+
+    /// Returns a line of the graph leaving all branch lines unchanged, or the next line of the
+    /// commit if it has more
+    fn padding_line(&mut self) -> String {
+        self.graph
+            .as_mut()
+            .map_or(String::new(), |x| x.padding_line())
+    }
+
+    /// Returns the graph's lines of the commit up to its own line, which isn't terminated so that
+    /// the commit's text follows it
+    fn commit_lines(&mut self) -> String {
+        let Some(graph) = &mut self.graph else {
+            return String::new();
+        };
+
+        let mut lines = String::new();
+        loop {
+            let (line, is_commit) = graph.next_line();
+            lines.push_str(&line);
+            if is_commit {
+                return lines;
+            }
+            lines.push('\n');
+        }
+    }
+
+    /// Returns the remaining lines of the graph of the commit, separated by newlines
+    fn remaining_lines(&mut self) -> String {
+        let Some(graph) = &mut self.graph else {
+            return String::new();
+        };
+
+        let mut lines = Vec::new();
+        while !graph.is_finished() {
+            lines.push(graph.next_line().0);
+        }
+        lines.join("\n")
+    }
+
+    /// Prefixes each line after the first with the next line of the graph, and finishes the
+    /// graph of the commit if the text is too short for it
+    fn write_text(&mut self, text: &str) -> String {
+        let mut output = String::new();
+
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            if i > 0 {
+                output.push_str(&self.next_line());
+            }
+            output.push_str(line);
+        }
+
+        if self.graph.as_ref().is_some_and(|x| !x.is_finished()) {
+            let terminated = text.ends_with('\n');
+            if !terminated {
+                output.push('\n');
+            }
+            output.push_str(&self.remaining_lines());
+            if terminated {
+                output.push('\n');
+            }
+        }
+
+        output
+    }
+
+    /// Formats a commit of the log, given with the parents it has in the graph, followed by its
+    /// changes
+    pub fn write(&mut self, commit: &Commit, parents: Vec<String>, diff: Option<&Diff>) -> String {
+        let sha = commit.sha();
+        let format = &self.options.format;
+        let refs = self.decorations.get(&sha).map_or("", |x| x.as_str());
+        let text = format.format(commit, refs, self.options.decorate);
+        let terminated = format.is_terminated();
+        let oneline = matches!(format, LogFormat::Oneline { .. });
+
+        if let Some(graph) = &mut self.graph {
+            graph.update(sha, parents);
+        }
+
+        let mut output = String::new();
+        if self.shown_one && !terminated {
+            // A separating empty line continues the graph unless it ends the previous text
+            if !self.missing_newline {
+                output.push_str(&self.padding_line());
+            }
+            output.push('\n');
+        }
+        self.shown_one = true;
+
+        output.push_str(&self.commit_lines());
+        self.missing_newline = !text.ends_with('\n');
+        output.push_str(&self.write_text(&text));
+        if terminated {
+            if !self.missing_newline {
+                output.push_str(&self.padding_line());
+            }
+            output.push('\n');
+        }
+
+        if let Some(diff) = diff {
+            if !oneline {
+                output.push_str(&self.padding_line());
+                output.push('\n');
+            }
+            for line in diff.to_string().split_inclusive('\n') {
+                output.push_str(&self.padding_line());
+                output.push_str(line);
+            }
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        LogOptions::parse_pattern(pattern).unwrap().is_match(text)
+    }
+
+    #[test]
+    fn matches_basic_regular_expressions() {
+        assert!(matches(r"a\(b\|c\)", "ac"));
+        assert!(matches(r"^ab\{2\}$", "abb"));
+        assert!(!matches(r"^ab\{2\}$", "ab"));
+        assert!(matches(r"\<fix\>", "a fix here"));
+        assert!(!matches(r"\<fix\>", "prefix"));
+        assert!(matches("[[:digit:]]", "user-024"));
+    }
+
+    #[test]
+    fn matches_special_characters_literally_where_git_does() {
+        assert!(matches("a+", "a+"));
+        assert!(!matches("a+", "aa"));
+        assert!(matches("(x|y)", "(x|y)"));
+        assert!(matches("^*x", "*x"));
+        assert!(matches("a^b$c", "a^b$c"));
+        assert!(matches("[]a]", "]"));
+        assert!(matches(r"[\]", r"\"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        assert!(LogOptions::parse_pattern(r"a\(b").is_err());
+    }
+}
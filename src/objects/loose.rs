@@ -0,0 +1,146 @@
+use crate::{storing::Storable, Paths};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use sha1_smol::Sha1;
+use std::{
+    fs,
+    io::{Read, Write},
+    path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Seconds since the epoch at which a file was last modified
+pub(super) fn modified(path: &str) -> i64 {
+    let time = fs::metadata(path)
+        .and_then(|x| x.modified())
+        .unwrap_or_else(|_| panic!("Failed to read the modification time of {}", path));
+
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as i64)
+}
+
+/// Reads and writes zlib compressed loose objects in the .rgit/objects directory
+pub struct Loose;
+
+impl Loose {
+    /// Objects are fanned out into directories named after the first two characters of their sha
+    fn path(sha: &str) -> String {
+        format!("{}/{}/{}", Paths::objects(), &sha[..2], &sha[2..])
+    }
+
+    pub fn exists(sha: &str) -> bool {
+        path::Path::new(&Loose::path(sha)).exists()
+    }
+
+    /// Returns the shas of all loose objects
+    pub fn all() -> Vec<String> {
+        let Ok(dirs) = fs::read_dir(Paths::objects()) else {
+            return Vec::new();
+        };
+
+        dirs.map(|dir| dir.unwrap())
+            .filter(|dir| dir.file_name().len() == 2 && dir.file_type().unwrap().is_dir())
+            .flat_map(|dir| {
+                let prefix = dir.file_name().to_string_lossy().to_string();
+                fs::read_dir(dir.path()).unwrap().map(move |entry| {
+                    prefix.clone() + &entry.unwrap().file_name().to_string_lossy()
+                })
+            })
+            .collect()
+    }
+
+    /// Seconds since the epoch at which the object was written
+    pub fn modified(sha: &str) -> i64 {
+        modified(&Loose::path(sha))
+    }
+
+    /// Backdates an object, so that it expires as if it had been written at that time
+    pub fn set_modified(sha: &str, time: i64) {
+        let time = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+        fs::File::options()
+            .write(true)
+            .open(Loose::path(sha))
+            .and_then(|file| file.set_modified(time))
+            .unwrap_or_else(|_| panic!("Failed to set the modification time of object {}", sha));
+    }
+
+    /// Removes a loose object and its fan-out directory once it is empty
+    pub fn remove(sha: &str) {
+        let path = Loose::path(sha);
+        fs::remove_file(&path).unwrap_or_else(|_| panic!("Failed to remove object {}", sha));
+
+        // Fails if other objects are still in the directory, which is fine
+        let _ = fs::remove_dir(path::Path::new(&path).parent().unwrap());
+    }
+
+    /// Compresses a serialized object and stores it under the sha of its uncompressed bytes
+    pub fn write(buf: &[u8]) -> String {
+        let sha = Sha1::from(buf).digest().to_string();
+
+        if Loose::exists(&sha) {
+            return sha;
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(buf).unwrap();
+        let compressed = encoder.finish().expect("Failed to compress object");
+
+        let path = Loose::path(&sha);
+        fs::create_dir_all(path::Path::new(&path).parent().unwrap()).unwrap();
+        fs::write(path, compressed).unwrap_or_else(|_| panic!("Failed to store object {}", sha));
+
+        sha
+    }
+
+    /// Reads and decompresses an object, returning its serialized representation including the header
+    pub fn read(sha: &str) -> Vec<u8> {
+        let compressed =
+            fs::read(Loose::path(sha)).unwrap_or_else(|_| panic!("Object {} not found", sha));
+
+        let mut buf = Vec::new();
+        ZlibDecoder::new(compressed.as_slice())
+            .read_to_end(&mut buf)
+            .unwrap_or_else(|_| panic!("Failed to decompress object {}", sha));
+
+        buf
+    }
+}
+
+/// Moves objects of repositories created before the fan-out layout from
+/// objects/<sha> to objects/<sha[..2]>/<sha[2..]>
+pub struct FanOutMigration {
+    shas: Vec<String>,
+}
+
+impl FanOutMigration {
+    /// Collects all objects still stored directly in the objects directory
+    pub fn new() -> FanOutMigration {
+        let shas = fs::read_dir(Paths::objects())
+            .expect("Failed to read objects directory")
+            .map(|entry| entry.unwrap())
+            .filter(|entry| entry.file_type().unwrap().is_file())
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .filter(|name| name.len() == 40 && name.chars().all(|x| x.is_ascii_hexdigit()))
+            .collect();
+
+        FanOutMigration { shas }
+    }
+}
+
+impl Default for FanOutMigration {
+    fn default() -> Self {
+        FanOutMigration::new()
+    }
+}
+
+impl Storable for FanOutMigration {
+    fn store(&self) {
+        println!("Migrating {} objects...", self.shas.len());
+
+        for sha in self.shas.iter() {
+            let new_path = Loose::path(sha);
+            fs::create_dir_all(path::Path::new(&new_path).parent().unwrap()).unwrap();
+            fs::rename(Paths::objects() + "/" + sha, new_path)
+                .unwrap_or_else(|_| panic!("Failed to migrate object {}", sha));
+        }
+    }
+}
@@ -0,0 +1,567 @@
+use crate::{
+    diff::{self, Algorithm, Edit},
+    objects::{
+        checkout::{head_entries, remove_wd_file, wd_file_matches, write_wd_file},
+        index, Blob, Commit, Head, Index, Signature, Tree,
+    },
+    rename::{Candidate, RenameOptions},
+    storing::{Object, Storable},
+    Paths,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs, path,
+};
+
+const MARKER_LEN: usize = 7;
+
+/// Result of merging the contents of a file changed on both sides
+struct FileMerge {
+    bytes: Vec<u8>,
+    has_conflicts: bool,
+}
+
+/// Merges the lines of two versions of a file with their common base version like diff3 does,
+/// marking the chunks changed differently on both sides with conflict markers
+fn merge_lines(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: (&str, &str),
+    algorithm: Algorithm,
+) -> FileMerge {
+    let (base, ours, theirs) = (diff::lines(base), diff::lines(ours), diff::lines(theirs));
+
+    // Lines of each side matching a line of the base
+    let matches = |side: &[&[u8]]| {
+        let mut matches = vec![None; base.len()];
+        for edit in algorithm.diff(&base, side) {
+            if let Edit::Equal(base_i, side_i) = edit {
+                matches[base_i] = Some(side_i);
+            }
+        }
+        matches
+    };
+    let (ours_matches, theirs_matches) = (matches(&ours), matches(&theirs));
+
+    let mut bytes = Vec::new();
+    let mut has_conflicts = false;
+    let (mut o, mut a, mut b) = (0, 0, 0);
+
+    while o < base.len() || a < ours.len() || b < theirs.len() {
+        // Lines all three versions agree on are kept as they are
+        if o < base.len() && ours_matches[o] == Some(a) && theirs_matches[o] == Some(b) {
+            bytes.extend(base[o]);
+            (o, a, b) = (o + 1, a + 1, b + 1);
+            continue;
+        }
+
+        // The unstable chunk reaches up to the next base line both sides still hold
+        let (next_o, next_a, next_b) = (o..base.len())
+            .find_map(|i| Some((i, ours_matches[i]?, theirs_matches[i]?)))
+            .unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        let base_chunk = &base[o..next_o];
+        let ours_chunk = &ours[a..next_a];
+        let theirs_chunk = &theirs[b..next_b];
+
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            theirs_chunk.iter().for_each(|x| bytes.extend(*x));
+        } else if theirs_chunk == base_chunk {
+            ours_chunk.iter().for_each(|x| bytes.extend(*x));
+        } else {
+            has_conflicts = true;
+
+            let mut section = |marker: u8, label: &str, chunk: &[&[u8]]| {
+                bytes.extend(vec![marker; MARKER_LEN]);
+                if !label.is_empty() {
+                    bytes.push(b' ');
+                    bytes.extend(label.as_bytes());
+                }
+                bytes.push(b'\n');
+                chunk.iter().for_each(|x| bytes.extend(*x));
+                if !bytes.ends_with(b"\n") {
+                    bytes.push(b'\n');
+                }
+            };
+            section(b'<', labels.0, ours_chunk);
+            section(b'=', "", theirs_chunk);
+            bytes.extend(vec![b'>'; MARKER_LEN]);
+            bytes.push(b' ');
+            bytes.extend(labels.1.as_bytes());
+            bytes.push(b'\n');
+        }
+
+        (o, a, b) = (next_o, next_a, next_b);
+    }
+
+    FileMerge {
+        bytes,
+        has_conflicts,
+    }
+}
+
+/// Compares entries by content and mode as git records it, i.e. the file type and whether a
+/// file is executable
+pub(super) fn same_version(a: Option<&index::Entry>, b: Option<&index::Entry>) -> bool {
+    a.map(|x| (x.sha(), x.git_mode())) == b.map(|x| (x.sha(), x.git_mode()))
+}
+
+/// Files of one version of a tree which the other doesn't have, with their contents
+fn only_in<'a>(
+    entries: &'a BTreeMap<String, index::Entry>,
+    others: &BTreeMap<String, index::Entry>,
+) -> Vec<(&'a index::Entry, Blob)> {
+    entries
+        .values()
+        // The commits of submodules aren't in the database and can't be compared
+        .filter(|x| !others.contains_key(x.path()) && x.git_mode() != index::GITLINK_MODE)
+        .map(|x| (x, Blob::new_from_object_file(x.sha(), None)))
+        .collect()
+}
+
+fn candidates<'a>(blobs: &'a [(&index::Entry, Blob)]) -> Vec<Candidate<'a>> {
+    blobs
+        .iter()
+        .map(|(entry, blob)| Candidate {
+            path: entry.path(),
+            sha: entry.sha(),
+            bytes: blob.bytes(),
+        })
+        .collect()
+}
+
+/// Three-way merge of the commit of another branch into the current head. Clean merges are
+/// committed right away, otherwise the conflicts are left in the working directory and the
+/// index until they are resolved and committed
+pub struct Merge {
+    head: Head,
+    theirs: Commit,
+    // Merged contents which aren't stored as objects yet
+    blobs: Vec<Blob>,
+    // Files which have to be written with their mode and content, and files to be removed
+    writes: Vec<(String, u32, Vec<u8>)>,
+    removals: Vec<String>,
+    index: Index,
+    conflicts: Vec<String>,
+    // Only merges without conflicts are committed right away
+    commit: Option<Commit>,
+    algorithm: Algorithm,
+}
+
+impl Merge {
+    /// Plans merging a commit into the current head, returns the paths whose local changes
+    /// would be overwritten if the merge isn't possible
+    pub fn new(theirs: Commit, base: Option<Commit>, name: &str) -> Result<Merge, Vec<String>> {
+        let head = Head::read_HEAD();
+        let index = Index::new_from_index_file();
+
+        let mut base_entries = match base {
+            Some(base) => head_entries(&Head::detached(base)),
+            None => BTreeMap::new(),
+        };
+        let ours_entries = head_entries(&head);
+        let mut theirs_entries = head_entries(&Head::detached(theirs.to_owned()));
+
+        // Staged changes would get lost in the merge commit
+        let index_paths: BTreeSet<&str> = index.entries().iter().map(|x| x.path()).collect();
+        let staged: Vec<String> = ours_entries
+            .keys()
+            .map(|x| x.as_str())
+            .chain(index_paths)
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .filter(|x| !same_version(index.entry(x), ours_entries.get(*x)))
+            .map(String::from)
+            .collect();
+        if !staged.is_empty() {
+            return Err(staged);
+        }
+
+        let mut merge = Merge {
+            head,
+            theirs,
+            blobs: Vec::new(),
+            writes: Vec::new(),
+            removals: Vec::new(),
+            index: Index::new(Vec::new()),
+            conflicts: Vec::new(),
+            commit: None,
+            algorithm: Algorithm::configured(),
+        };
+
+        // A file renamed on one side is merged with the other side's version of it at its new
+        // path. Merges only follow renames, copies are new files
+        let mut moved_entries = ours_entries.clone();
+        let mut moved = Vec::new();
+        if RenameOptions::configured(&["merge.renames", "diff.renames"]).is_some() {
+            let options = RenameOptions::new(None, false);
+            let ours_renames = Merge::renames(&base_entries, &ours_entries, options);
+            let theirs_renames = Merge::renames(&base_entries, &theirs_entries, options);
+
+            for (renames, other) in [
+                (theirs_renames, &mut moved_entries),
+                (ours_renames, &mut theirs_entries),
+            ] {
+                for (from, to) in renames {
+                    if base_entries.contains_key(&to) || other.contains_key(&to) {
+                        continue;
+                    }
+                    let Some(entry) = other.remove(&from) else {
+                        continue;
+                    };
+
+                    let renamed = |x: &index::Entry| {
+                        index::Entry::new(x.mode(), to.to_owned(), x.sha().to_owned())
+                    };
+                    other.insert(to.to_owned(), renamed(&entry));
+                    if let Some(base) = base_entries.remove(&from) {
+                        base_entries.insert(to.to_owned(), renamed(&base));
+                    }
+                    if ours_entries.contains_key(&from) {
+                        moved.push((from, to));
+                    }
+                }
+            }
+        }
+
+        let paths: BTreeSet<&String> = base_entries
+            .keys()
+            .chain(moved_entries.keys())
+            .chain(theirs_entries.keys())
+            .collect();
+
+        let mut entries = Vec::new();
+        for path in paths {
+            let base = base_entries.get(path);
+            let ours = moved_entries.get(path);
+            let theirs = theirs_entries.get(path);
+
+            if same_version(ours, theirs) || same_version(base, theirs) {
+                entries.extend(ours.cloned());
+            } else if same_version(base, ours) {
+                match theirs {
+                    Some(theirs) => {
+                        merge.write_blob(path, theirs.mode(), theirs.sha());
+                        entries.push(theirs.to_owned());
+                    }
+                    None => merge.removals.push(path.to_owned()),
+                }
+            } else {
+                entries.extend(merge.merge_path(path, base, ours, theirs, name));
+            }
+        }
+
+        // Our files which the other side renamed are moved to their new path
+        for (from, to) in moved {
+            merge.removals.push(from);
+            if !merge.writes.iter().any(|(path, _, _)| *path == to) {
+                if let Some(entry) = entries.iter().find(|x| x.path() == to && x.stage() != 3) {
+                    let (mode, sha) = (entry.mode(), entry.sha().to_owned());
+                    merge.write_blob(&to, mode, &sha);
+                }
+            }
+        }
+
+        // Files the merge changes must not hold local changes
+        let overwritten: Vec<String> = merge
+            .writes
+            .iter()
+            .map(|(path, _, _)| path)
+            .chain(merge.removals.iter())
+            .filter(|path| match ours_entries.get(*path) {
+                Some(entry) => !wd_file_matches(entry),
+                None => path::Path::new(path).exists(),
+            })
+            .cloned()
+            .collect();
+        if !overwritten.is_empty() {
+            return Err(overwritten);
+        }
+
+        entries.sort_by(|a, b| (a.path(), a.stage()).cmp(&(b.path(), b.stage())));
+        merge.index = Index::new(entries);
+
+        Ok(merge)
+    }
+
+    /// Merges a path changed on both sides, returning its new index entries
+    fn merge_path(
+        &mut self,
+        path: &str,
+        base: Option<&index::Entry>,
+        ours: Option<&index::Entry>,
+        theirs: Option<&index::Entry>,
+        name: &str,
+    ) -> Vec<index::Entry> {
+        let stages = || {
+            [(base, 1), (ours, 2), (theirs, 3)]
+                .into_iter()
+                .filter_map(|(entry, stage)| {
+                    entry.map(|x| {
+                        index::Entry::new_staged(
+                            x.mode(),
+                            path.to_owned(),
+                            x.sha().to_owned(),
+                            stage,
+                        )
+                    })
+                })
+                .collect()
+        };
+
+        let (ours, theirs) = match (ours, theirs) {
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (Some(_), None) | (None, Some(_)) => {
+                let (deleted, modified) = if ours.is_none() {
+                    ("HEAD", name)
+                } else {
+                    (name, "HEAD")
+                };
+                println!(
+                    "CONFLICT (modify/delete): {} deleted in {} and modified in {}.",
+                    path, deleted, modified
+                );
+
+                // The modified version stays in the working directory
+                if let Some(theirs) = theirs {
+                    self.write_blob(path, theirs.mode(), theirs.sha());
+                }
+                self.conflicts.push(path.to_owned());
+                return stages();
+            }
+            (None, None) => unreachable!("Path {} is in neither side", path),
+        };
+
+        // The mode is merged on its own, a side which didn't change it takes the other's
+        let mode = match base {
+            Some(base) if base.git_mode() == ours.git_mode() => theirs.mode(),
+            _ => ours.mode(),
+        };
+
+        if ours.sha() == theirs.sha() {
+            self.write_blob(path, mode, ours.sha());
+            return vec![index::Entry::new(
+                mode,
+                path.to_owned(),
+                ours.sha().to_owned(),
+            )];
+        }
+
+        // Only regular files can be merged line by line, git keeps our symlink or submodule
+        let is_file =
+            |x: &index::Entry| !matches!(x.git_mode(), index::SYMLINK_MODE | index::GITLINK_MODE);
+        if !is_file(ours) || !is_file(theirs) {
+            println!("CONFLICT (content): Merge conflict in {}", path);
+            self.conflicts.push(path.to_owned());
+            return stages();
+        }
+
+        let base_bytes = base.filter(|x| is_file(x)).map_or(Vec::new(), |x| {
+            Blob::new_from_object_file(x.sha(), None).bytes().to_vec()
+        });
+        let ours_blob = Blob::new_from_object_file(ours.sha(), None);
+        let theirs_blob = Blob::new_from_object_file(theirs.sha(), None);
+
+        println!("Auto-merging {}", path);
+
+        if [&base_bytes, ours_blob.bytes(), theirs_blob.bytes()]
+            .iter()
+            .any(|x| diff::is_binary(x))
+        {
+            // Binary files can't be merged line by line, our version stays in place
+            println!("warning: Cannot merge binary files: {}", path);
+            println!("CONFLICT (content): Merge conflict in {}", path);
+            self.conflicts.push(path.to_owned());
+            return stages();
+        }
+
+        let merged = merge_lines(
+            &base_bytes,
+            ours_blob.bytes(),
+            theirs_blob.bytes(),
+            ("HEAD", name),
+            self.algorithm,
+        );
+
+        if merged.has_conflicts {
+            println!("CONFLICT (content): Merge conflict in {}", path);
+            self.writes.push((path.to_owned(), mode, merged.bytes));
+            self.conflicts.push(path.to_owned());
+            return stages();
+        }
+
+        let blob = Blob::new(merged.bytes);
+        let entry = index::Entry::new(mode, path.to_owned(), blob.sha());
+        self.writes
+            .push((path.to_owned(), mode, blob.bytes().to_vec()));
+        self.blobs.push(blob);
+
+        vec![entry]
+    }
+
+    /// Returns the paths of the files of the base which were renamed on a side, with their new path
+    fn renames(
+        base: &BTreeMap<String, index::Entry>,
+        side: &BTreeMap<String, index::Entry>,
+        options: RenameOptions,
+    ) -> Vec<(String, String)> {
+        let (deleted, added) = (only_in(base, side), only_in(side, base));
+        let added = candidates(&added);
+        options
+            .detect(&candidates(&deleted), &[], &added)
+            .into_iter()
+            .map(|(i, rename)| (rename.from, added[i].path.to_owned()))
+            .collect()
+    }
+
+    fn write_blob(&mut self, path: &str, mode: u32, sha: &str) {
+        // The commit of a submodule isn't in the database, there is nothing to write
+        if mode & index::TYPE_MASK == index::GITLINK_MODE {
+            self.writes.push((path.to_owned(), mode, Vec::new()));
+            return;
+        }
+
+        let blob = Blob::new_from_object_file(sha, None);
+        self.writes
+            .push((path.to_owned(), mode, blob.bytes().to_vec()));
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+
+    /// Returns the sha of the commit being merged if a merge with conflicts is in progress
+    pub fn in_progress() -> Option<String> {
+        fs::read_to_string(Paths::merge_head())
+            .ok()
+            .map(|x| x.trim().to_owned())
+    }
+
+    /// Ends a merge in progress once its commit is made
+    pub fn finish() {
+        if path::Path::new(&Paths::merge_head()).exists() {
+            fs::remove_file(Paths::merge_head()).expect("Failed to remove MERGE_HEAD");
+        }
+    }
+
+    /// Prepares the commit which is made when the merge is stored, if it has no conflicts
+    pub fn commit(&mut self, author: Signature, committer: Signature, message: String) {
+        let parents = self
+            .head
+            .commit()
+            .iter()
+            .chain([&self.theirs])
+            .map(|x| x.sha())
+            .collect();
+
+        self.commit = Some(Commit::new(
+            Tree::from(self.index.to_owned()),
+            parents,
+            author,
+            committer,
+            message,
+        ));
+    }
+}
+
+impl Storable for Merge {
+    fn store(&self) {
+        for blob in self.blobs.iter() {
+            blob.store();
+        }
+
+        for path in self.removals.iter() {
+            remove_wd_file(path);
+        }
+
+        for (path, mode, bytes) in self.writes.iter() {
+            write_wd_file(path, *mode, bytes);
+        }
+
+        self.index.store();
+
+        if self.has_conflicts() {
+            fs::write(Paths::merge_head(), self.theirs.sha() + "\n")
+                .expect("Failed to write MERGE_HEAD");
+            println!("Automatic merge failed; fix conflicts and then commit the result.");
+        } else if let Some(commit) = &self.commit {
+            commit.store();
+            self.head.update(commit);
+            println!("Merge made by the 'three-way' strategy.");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: &str = "1\n2\n3\n4\n5\n";
+
+    fn merge(ours: &str, theirs: &str) -> (String, bool) {
+        let merge = merge_lines(
+            BASE.as_bytes(),
+            ours.as_bytes(),
+            theirs.as_bytes(),
+            ("ours", "theirs"),
+            Algorithm::Myers,
+        );
+
+        (String::from_utf8(merge.bytes).unwrap(), merge.has_conflicts)
+    }
+
+    #[test]
+    fn combines_separate_changes() {
+        assert_eq!(
+            merge("1\nX\n3\n4\n5\n", "1\n2\n3\n4\nY\n"),
+            ("1\nX\n3\n4\nY\n".to_owned(), false)
+        );
+        assert_eq!(
+            merge("0\n1\n2\n3\n4\n5\n", "1\n2\n3\n5\n"),
+            ("0\n1\n2\n3\n5\n".to_owned(), false)
+        );
+    }
+
+    #[test]
+    fn takes_changes_made_on_both_sides_once() {
+        assert_eq!(
+            merge("1\nX\n3\n4\n5\n", "1\nX\n3\n4\n5\n"),
+            ("1\nX\n3\n4\n5\n".to_owned(), false)
+        );
+        assert_eq!(merge(BASE, ""), ("".to_owned(), false));
+    }
+
+    #[test]
+    fn marks_conflicting_changes() {
+        assert_eq!(
+            merge("1\nX\n3\n4\n5\n", "1\nA\n3\n4\n5\n"),
+            (
+                "1\n<<<<<<< ours\nX\n=======\nA\n>>>>>>> theirs\n3\n4\n5\n".to_owned(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn ends_conflicting_lines_with_newlines() {
+        assert_eq!(
+            merge("1\n2\n3\n4\n6", "1\n2\n3\n4\n5"),
+            (
+                "1\n2\n3\n4\n<<<<<<< ours\n6\n=======\n5\n>>>>>>> theirs\n".to_owned(),
+                true
+            )
+        );
+    }
+
+    #[test]
+    fn compares_file_types() {
+        let sha = "3b18e512dba79e4c8300dd08aeb37f8e728b8dad".to_owned();
+        let file = index::Entry::new(0o100664, "a".to_owned(), sha.to_owned());
+        let same_file = index::Entry::new(0o100644, "a".to_owned(), sha.to_owned());
+        let link = index::Entry::new(0o120777, "a".to_owned(), sha.to_owned());
+
+        assert!(same_version(Some(&file), Some(&same_file)));
+        assert!(!same_version(Some(&file), Some(&link)));
+    }
+}
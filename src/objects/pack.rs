@@ -0,0 +1,769 @@
+use super::{loose, signature};
+use crate::{
+    hex, identifiers,
+    objects::{delta, Commit, FsDatabase, Head, Index, Loose, Merge, ObjectDatabase, Tag, Tree},
+    storing::{self, Object, Storable},
+    Config, Paths,
+};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use sha1_smol::Sha1;
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs,
+    io::{BufReader, Read, Seek, SeekFrom, Write},
+};
+
+const PACK_SIGNATURE: &[u8] = b"PACK";
+const PACK_VERSION: u32 = 2;
+const IDX_SIGNATURE: &[u8] = b"\xfftOc";
+const IDX_VERSION: u32 = 2;
+
+// Type numbers stored in the header of every packed object
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+// Amount of previous blobs tried as delta bases and the longest allowed delta chain
+const DELTA_WINDOW: usize = 10;
+const MAX_DELTA_DEPTH: usize = 50;
+
+// Offsets which don't fit into 31 bits are stored in the idx's large offset table
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+// Resolved delta bases are kept up to this many bytes per pack, like git's deltaBaseCacheLimit
+const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+// Like git, unreachable objects are kept for a while when pruning, as a command running at the
+// same time might be about to reference them
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+
+fn type_number(kind: &str) -> u8 {
+    match kind {
+        identifiers::COMMIT => OBJ_COMMIT,
+        identifiers::TREE => OBJ_TREE,
+        identifiers::BLOB => OBJ_BLOB,
+        identifiers::TAG => OBJ_TAG,
+        _ => panic!("Unknown object type {}", kind),
+    }
+}
+
+fn type_name(type_number: u8) -> &'static str {
+    match type_number {
+        OBJ_COMMIT => identifiers::COMMIT,
+        OBJ_TREE => identifiers::TREE,
+        OBJ_BLOB => identifiers::BLOB,
+        OBJ_TAG => identifiers::TAG,
+        _ => panic!("Unknown packed object type {}", type_number),
+    }
+}
+
+/// Encodes the type and uncompressed size of a packed object
+fn encode_entry_header(type_number: u8, size: usize) -> Vec<u8> {
+    let mut buf = Vec::new();
+    let mut byte = (type_number << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+
+    while size != 0 {
+        buf.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    buf.push(byte);
+
+    buf
+}
+
+/// Encodes the distance to the base of an OFS_DELTA the way git does, big endian with an
+/// offset added to every continuation byte so that no encoding is redundant
+fn encode_delta_offset(mut offset: usize) -> Vec<u8> {
+    let mut buf = vec![(offset & 0x7f) as u8];
+    offset >>= 7;
+
+    while offset != 0 {
+        offset -= 1;
+        buf.push(0x80 | (offset & 0x7f) as u8);
+        offset >>= 7;
+    }
+    buf.reverse();
+
+    buf
+}
+
+fn compress(buf: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(buf).unwrap();
+    encoder.finish().expect("Failed to compress object")
+}
+
+struct PackObject {
+    sha: String,
+    kind: String,
+    payload: Vec<u8>,
+    // Path the object was found at, used to find similar blobs to delta against
+    path: String,
+}
+
+impl PackObject {
+    fn read(sha: String, path: String) -> PackObject {
+        let (kind, payload) = FsDatabase.read_existing(&sha);
+
+        PackObject {
+            sha,
+            kind,
+            payload,
+            path,
+        }
+    }
+
+    fn file_name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap()
+    }
+}
+
+/// Collects all objects reachable from the heads, tags and the index into a single packfile and
+/// removes the loose objects which become redundant
+pub struct Repack {
+    objects: Vec<PackObject>,
+    // Unreachable objects older than this are removed, never if it is Option::None
+    expire: Option<i64>,
+}
+
+impl Repack {
+    /// Walks all heads, tags and the index, prune also removes unreachable objects once they are older
+    /// than the gc.pruneExpire config
+    pub fn new(prune: bool) -> Repack {
+        let mut seen = HashSet::new();
+        let mut reachable = Vec::new();
+
+        // A detached HEAD is reachable even though no head ref points to it, and the commit being
+        // merged is needed once the merge's conflicts are resolved
+        let mut commits: Vec<Commit> = Head::all()
+            .into_iter()
+            .chain([Head::read_HEAD()])
+            .filter_map(|x| x.commit().to_owned())
+            .chain(Merge::in_progress().map(|x| Commit::new_from_object_file(&x, None)))
+            .collect();
+
+        // Tags keep their annotated tag objects reachable, and the objects they tag, which are
+        // usually commits but can also be trees or blobs
+        for tag in Tag::all() {
+            let (sha, kind) = tag.object();
+            for tag_object in tag.tag_objects() {
+                if seen.insert(tag_object.to_owned()) {
+                    reachable.push((tag_object.to_owned(), String::new()));
+                }
+            }
+
+            match kind {
+                identifiers::COMMIT => commits.extend(tag.commit()),
+                identifiers::TREE => {
+                    Tree::collect_objects(&FsDatabase, sha, "", &mut seen, &mut reachable)
+                }
+                _ => {
+                    if seen.insert(sha.to_owned()) {
+                        reachable.push((sha.to_owned(), String::new()));
+                    }
+                }
+            }
+        }
+
+        while let Some(commit) = commits.pop() {
+            let sha = commit.sha();
+            if !seen.insert(sha.to_owned()) {
+                continue;
+            }
+            reachable.push((sha, String::new()));
+
+            // Most of a commit's tree is shared with its parents, subtrees which didn't change
+            // are only walked once
+            Tree::collect_objects(
+                &FsDatabase,
+                commit.tree_sha(),
+                "",
+                &mut seen,
+                &mut reachable,
+            );
+
+            commits.extend(commit.parents());
+        }
+
+        // Staged but uncommitted files are reachable through the index
+        for entry in Index::new_from_index_file().entries() {
+            if seen.insert(entry.sha().to_owned()) {
+                reachable.push((entry.sha().to_owned(), entry.path().to_owned()));
+            }
+        }
+
+        let objects = reachable
+            .into_iter()
+            .map(|(sha, path)| PackObject::read(sha, path))
+            .collect();
+
+        let expire = match prune {
+            true => Repack::prune_expire(),
+            false => None,
+        };
+
+        Repack { objects, expire }
+    }
+
+    /// Reads the gc.pruneExpire config, which is a date or "never"
+    fn prune_expire() -> Option<i64> {
+        let config = Config::load();
+        let value = config.get("gc.pruneExpire").unwrap_or(DEFAULT_PRUNE_EXPIRE);
+
+        match value {
+            "never" => None,
+            _ => Some(
+                signature::parse_approxidate(value)
+                    .unwrap_or_else(|| panic!("Invalid gc.pruneExpire {}", value)),
+            ),
+        }
+    }
+
+    fn is_expired(&self, time: i64) -> bool {
+        self.expire.is_some_and(|x| time < x)
+    }
+
+    /// Orders objects so that similar blobs end up next to each other, with bigger blobs first
+    /// because deleting data makes for smaller deltas than adding it
+    fn sorted_objects(&self) -> Vec<&PackObject> {
+        let mut objects: Vec<&PackObject> = self.objects.iter().collect();
+        objects.sort_by_key(|x| {
+            (
+                type_number(&x.kind),
+                x.file_name().to_owned(),
+                Reverse(x.payload.len()),
+            )
+        });
+
+        objects
+    }
+
+    /// Finds the best delta base for every blob among the previous blobs in the window,
+    /// returns the base index and the delta for each object that should be deltified
+    fn find_deltas(objects: &[&PackObject]) -> Vec<Option<(usize, Vec<u8>)>> {
+        let mut deltas: Vec<Option<(usize, Vec<u8>)>> = Vec::with_capacity(objects.len());
+        let mut depths = vec![0; objects.len()];
+
+        for (i, object) in objects.iter().enumerate() {
+            if object.kind != identifiers::BLOB {
+                deltas.push(None);
+                continue;
+            }
+
+            let best = (i.saturating_sub(DELTA_WINDOW)..i)
+                .filter(|j| objects[*j].kind == identifiers::BLOB)
+                .filter(|j| depths[*j] < MAX_DELTA_DEPTH)
+                .map(|j| (j, delta::create(&objects[j].payload, &object.payload)))
+                .min_by_key(|(_, delta)| delta.len())
+                .filter(|(_, delta)| delta.len() < object.payload.len() / 2);
+
+            if let Some((j, _)) = best {
+                depths[i] = depths[j] + 1;
+            }
+            deltas.push(best);
+        }
+
+        deltas
+    }
+
+    /// Serializes all objects into a packfile, returning it together with the sha, offset and crc32
+    /// of every entry
+    fn write_pack(&self) -> (Vec<u8>, Vec<(String, usize, u32)>) {
+        let objects = self.sorted_objects();
+        let deltas = Repack::find_deltas(&objects);
+
+        let mut pack = Vec::new();
+        pack.extend_from_slice(PACK_SIGNATURE);
+        pack.extend(PACK_VERSION.to_be_bytes());
+        pack.extend((objects.len() as u32).to_be_bytes());
+
+        let mut entries: Vec<(String, usize, u32)> = Vec::with_capacity(objects.len());
+
+        for (object, delta) in objects.iter().zip(deltas) {
+            let offset = pack.len();
+
+            let entry = match delta {
+                Some((base_i, delta)) => {
+                    let base_offset = entries[base_i].1;
+                    let mut entry = encode_entry_header(OBJ_OFS_DELTA, delta.len());
+                    entry.extend(encode_delta_offset(offset - base_offset));
+                    entry.extend(compress(&delta));
+                    entry
+                }
+                None => {
+                    let mut entry =
+                        encode_entry_header(type_number(&object.kind), object.payload.len());
+                    entry.extend(compress(&object.payload));
+                    entry
+                }
+            };
+
+            entries.push((object.sha.to_owned(), offset, crc32fast::hash(&entry)));
+            pack.extend(entry);
+        }
+
+        let checksum = Sha1::from(&pack).digest().bytes();
+        pack.extend(checksum);
+
+        (pack, entries)
+    }
+
+    /// Builds a version 2 idx file for the entries of a packfile
+    fn write_idx(mut entries: Vec<(String, usize, u32)>, pack_checksum: &[u8]) -> Vec<u8> {
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        let shas: Vec<Vec<u8>> = entries.iter().map(|x| hex::decode(&x.0)).collect();
+
+        let mut idx = Vec::new();
+        idx.extend_from_slice(IDX_SIGNATURE);
+        idx.extend(IDX_VERSION.to_be_bytes());
+
+        for byte in 0..=255u8 {
+            let count = shas.iter().filter(|sha| sha[0] <= byte).count() as u32;
+            idx.extend(count.to_be_bytes());
+        }
+
+        shas.iter().for_each(|sha| idx.extend(sha));
+        entries.iter().for_each(|x| idx.extend(x.2.to_be_bytes()));
+
+        let mut large_offsets = Vec::new();
+        for (_, offset, _) in entries.iter() {
+            if *offset < LARGE_OFFSET_FLAG as usize {
+                idx.extend((*offset as u32).to_be_bytes());
+            } else {
+                let i = (large_offsets.len() / 8) as u32;
+                idx.extend((LARGE_OFFSET_FLAG | i).to_be_bytes());
+                large_offsets.extend((*offset as u64).to_be_bytes());
+            }
+        }
+        idx.extend(large_offsets);
+
+        idx.extend_from_slice(pack_checksum);
+        let checksum = Sha1::from(&idx).digest().bytes();
+        idx.extend(checksum);
+
+        idx
+    }
+}
+
+impl Storable for Repack {
+    fn store(&self) {
+        if self.objects.is_empty() {
+            println!("Nothing to pack");
+            return;
+        }
+
+        let (pack, entries) = self.write_pack();
+        let pack_checksum = &pack[pack.len() - 20..];
+        let name = format!("{}/pack-{}", Paths::packs(), hex::encode(pack_checksum));
+
+        let idx = Repack::write_idx(entries, pack_checksum);
+
+        fs::create_dir_all(Paths::packs()).expect("Failed to create pack directory");
+        fs::write(name.to_owned() + ".pack", &pack).expect("Failed to write packfile");
+        fs::write(name.to_owned() + ".idx", idx).expect("Failed to write pack index");
+
+        let packed: HashSet<&str> = self.objects.iter().map(|x| x.sha.as_str()).collect();
+        let mut removed = 0;
+
+        for sha in Loose::all() {
+            if packed.contains(sha.as_str()) || self.is_expired(Loose::modified(&sha)) {
+                Loose::remove(&sha);
+                removed += 1;
+            }
+        }
+
+        // Older packs are redundant once the new pack holds all of their objects. When pruning,
+        // their unreachable objects are written out as loose objects as old as the pack, so
+        // that they are removed once they expire
+        for pack in Pack::all().iter().filter(|pack| pack.path() != name) {
+            let unreachable: Vec<String> = pack
+                .shas()
+                .into_iter()
+                .filter(|sha| !packed.contains(sha.as_str()))
+                .collect();
+            if !unreachable.is_empty() && self.expire.is_none() {
+                continue;
+            }
+
+            let time = pack.modified();
+            if !self.is_expired(time) {
+                for sha in unreachable.iter().filter(|x| !Loose::exists(x)) {
+                    let (kind, payload) = pack.read(sha).unwrap();
+                    Loose::write(&storing::add_header(&kind, &payload));
+                    Loose::set_modified(sha, time);
+                }
+            }
+
+            fs::remove_file(pack.path().to_owned() + ".pack").expect("Failed to remove pack");
+            fs::remove_file(pack.path().to_owned() + ".idx").expect("Failed to remove pack");
+        }
+
+        println!(
+            "Packed {} objects into {}.pack, removed {} loose objects",
+            self.objects.len(),
+            name,
+            removed
+        );
+    }
+}
+
+/// A packfile together with its idx, used to look up and read packed objects
+pub struct Pack {
+    // Path of the pack without the .pack or .idx extension
+    path: String,
+    idx: Vec<u8>,
+    version: u32,
+    // The packfile is opened on the first read and kept open for all later ones
+    file: OnceCell<RefCell<BufReader<fs::File>>>,
+    // Objects which were delta bases by their offset, delta chains often share their bases
+    bases: RefCell<HashMap<u64, (String, Vec<u8>)>>,
+    bases_size: Cell<usize>,
+}
+
+impl Pack {
+    fn load(path: String) -> Pack {
+        let idx = fs::read(path.to_owned() + ".idx")
+            .unwrap_or_else(|_| panic!("Failed to read pack index {}.idx", path));
+
+        // Version 1 idx files have no signature and start directly with the fan-out table
+        let version = if idx.starts_with(IDX_SIGNATURE) {
+            u32::from_be_bytes(idx[4..8].try_into().unwrap())
+        } else {
+            1
+        };
+        assert!(
+            version <= IDX_VERSION,
+            "Unsupported pack index version {}",
+            version
+        );
+
+        Pack {
+            path,
+            idx,
+            version,
+            file: OnceCell::new(),
+            bases: RefCell::new(HashMap::new()),
+            bases_size: Cell::new(0),
+        }
+    }
+
+    /// Returns all packs in the .rgit/objects/pack directory
+    pub fn all() -> Vec<Pack> {
+        let Ok(entries) = fs::read_dir(Paths::packs()) else {
+            return Vec::new();
+        };
+
+        entries
+            .map(|entry| entry.unwrap().path().to_string_lossy().to_string())
+            .filter_map(|path| path.strip_suffix(".idx").map(String::from))
+            .map(Pack::load)
+            .collect()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Seconds since the epoch at which the pack was written
+    fn modified(&self) -> i64 {
+        loose::modified(&(self.path.to_owned() + ".pack"))
+    }
+
+    fn fan_out_start(&self) -> usize {
+        if self.version == 1 {
+            0
+        } else {
+            8
+        }
+    }
+
+    fn fan_out(&self, byte: usize) -> usize {
+        let i = self.fan_out_start() + byte * 4;
+        u32::from_be_bytes(self.idx[i..i + 4].try_into().unwrap()) as usize
+    }
+
+    fn count(&self) -> usize {
+        self.fan_out(255)
+    }
+
+    /// Returns the binary sha of the nth object in the idx
+    fn sha_at(&self, n: usize) -> &[u8] {
+        let table = self.fan_out_start() + 256 * 4;
+        let i = if self.version == 1 {
+            table + n * 24 + 4
+        } else {
+            table + n * 20
+        };
+
+        &self.idx[i..i + 20]
+    }
+
+    fn offset_at(&self, n: usize) -> u64 {
+        let table = self.fan_out_start() + 256 * 4;
+
+        if self.version == 1 {
+            let i = table + n * 24;
+            return u32::from_be_bytes(self.idx[i..i + 4].try_into().unwrap()) as u64;
+        }
+
+        let offsets = table + self.count() * (20 + 4);
+        let i = offsets + n * 4;
+        let offset = u32::from_be_bytes(self.idx[i..i + 4].try_into().unwrap());
+
+        if offset & LARGE_OFFSET_FLAG == 0 {
+            return offset as u64;
+        }
+
+        let large_offsets = offsets + self.count() * 4;
+        let i = large_offsets + (offset & !LARGE_OFFSET_FLAG) as usize * 8;
+        u64::from_be_bytes(self.idx[i..i + 8].try_into().unwrap())
+    }
+
+    /// Looks up the offset of an object in the pack using the fan-out table and a binary search
+    fn find(&self, sha: &str) -> Option<u64> {
+        let sha = hex::decode(sha);
+        let first = sha[0] as usize;
+
+        let mut low = if first == 0 {
+            0
+        } else {
+            self.fan_out(first - 1)
+        };
+        let mut high = self.fan_out(first);
+
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.sha_at(mid).cmp(&sha) {
+                std::cmp::Ordering::Equal => return Some(self.offset_at(mid)),
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+            }
+        }
+
+        None
+    }
+
+    pub fn contains(&self, sha: &str) -> bool {
+        self.find(sha).is_some()
+    }
+
+    /// Returns the shas of all objects in the pack
+    pub fn shas(&self) -> Vec<String> {
+        (0..self.count())
+            .map(|n| hex::encode(self.sha_at(n)))
+            .collect()
+    }
+
+    /// Reads an object from the pack, returning its type and payload, or None if it isn't packed here
+    pub fn read(&self, sha: &str) -> Option<(String, Vec<u8>)> {
+        self.find(sha).map(|offset| self.read_at(offset))
+    }
+
+    /// Reads the entry at an offset of the packfile, resolving deltas against their bases
+    fn read_at(&self, offset: u64) -> (String, Vec<u8>) {
+        let (type_number, base, data) = self.read_entry(offset);
+
+        let base = match base {
+            Some(DeltaBase::Offset(base_offset)) => Some(self.read_base(base_offset)),
+            Some(DeltaBase::Sha(sha)) => Some(
+                self.read(&sha)
+                    .unwrap_or_else(|| FsDatabase.read_existing(&sha)),
+            ),
+            None => None,
+        };
+
+        match base {
+            Some((kind, base)) => (kind, delta::apply(&base, &data)),
+            None => (type_name(type_number).to_owned(), data),
+        }
+    }
+
+    /// Reads an object a delta is based on, keeping it in case other deltas share the base
+    fn read_base(&self, offset: u64) -> (String, Vec<u8>) {
+        if let Some(base) = self.bases.borrow().get(&offset) {
+            return base.clone();
+        }
+
+        let base = self.read_at(offset);
+        if self.bases_size.get() + base.1.len() > DELTA_BASE_CACHE_LIMIT {
+            self.bases.borrow_mut().clear();
+            self.bases_size.set(0);
+        }
+        self.bases_size.set(self.bases_size.get() + base.1.len());
+        self.bases.borrow_mut().insert(offset, base.clone());
+
+        base
+    }
+
+    /// Reads the type, the delta base if it is a delta, and the inflated data of the entry at an
+    /// offset, without resolving the delta
+    fn read_entry(&self, offset: u64) -> (u8, Option<DeltaBase>, Vec<u8>) {
+        let file = self.file.get_or_init(|| {
+            let file = fs::File::open(self.path.to_owned() + ".pack")
+                .unwrap_or_else(|_| panic!("Failed to open packfile {}.pack", self.path));
+            RefCell::new(BufReader::new(file))
+        });
+        let reader = &mut *file.borrow_mut();
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+
+        let mut byte = read_byte(reader);
+        let type_number = (byte >> 4) & 0x07;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = read_byte(reader);
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        let base = match type_number {
+            OBJ_OFS_DELTA => {
+                let mut byte = read_byte(reader);
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = read_byte(reader);
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+
+                Some(DeltaBase::Offset(offset - distance))
+            }
+            OBJ_REF_DELTA => {
+                let mut sha = [0; 20];
+                reader.read_exact(&mut sha).unwrap();
+
+                Some(DeltaBase::Sha(hex::encode(&sha)))
+            }
+            _ => None,
+        };
+
+        let mut data = Vec::with_capacity(size);
+        ZlibDecoder::new(reader)
+            .read_to_end(&mut data)
+            .expect("Failed to decompress packed object");
+        assert_eq!(data.len(), size, "Packed object has the wrong size");
+
+        (type_number, base, data)
+    }
+}
+
+/// Where the base of a deltified pack entry is found
+enum DeltaBase {
+    Offset(u64),
+    Sha(String),
+}
+
+fn read_byte(reader: &mut impl Read) -> u8 {
+    let mut byte = [0];
+    reader
+        .read_exact(&mut byte)
+        .expect("Unexpected end of packfile");
+    byte[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storing;
+    use std::{env, process};
+
+    fn object(kind: &str, payload: &[u8], path: &str) -> PackObject {
+        PackObject {
+            sha: Sha1::from(storing::add_header(kind, payload))
+                .digest()
+                .to_string(),
+            kind: kind.to_owned(),
+            payload: payload.to_vec(),
+            path: path.to_owned(),
+        }
+    }
+
+    fn repack() -> Repack {
+        let text: Vec<u8> = (0..200)
+            .flat_map(|x| format!("line {}\n", x).into_bytes())
+            .collect();
+        let mut edited = text.clone();
+        edited.extend(b"one more line\n");
+
+        Repack {
+            objects: vec![
+                object(identifiers::BLOB, &text, "src/file.txt"),
+                object(identifiers::BLOB, &edited, "src/file.txt"),
+                object(identifiers::BLOB, b"", "empty"),
+                object(identifiers::TREE, b"", ""),
+            ],
+            expire: None,
+        }
+    }
+
+    #[test]
+    fn deltifies_similar_blobs() {
+        // One version of the file is stored as a delta of the other
+        let repack = repack();
+        let deltas = Repack::find_deltas(&repack.sorted_objects());
+
+        assert_eq!(deltas.iter().filter(|x| x.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn writes_pack_with_checksum() {
+        let (pack, entries) = repack().write_pack();
+        let (content, checksum) = pack.split_at(pack.len() - 20);
+
+        assert_eq!(content[..8], *b"PACK\0\0\0\x02");
+        assert_eq!(content[8..12], 4u32.to_be_bytes());
+        assert_eq!(checksum, Sha1::from(content).digest().bytes());
+        assert_eq!(entries[0].1, 12);
+        assert!(entries.windows(2).all(|x| x[0].1 < x[1].1));
+    }
+
+    #[test]
+    fn writes_idx_for_every_entry() {
+        let (pack, entries) = repack().write_pack();
+        let mut shas: Vec<Vec<u8>> = entries.iter().map(|x| hex::decode(&x.0)).collect();
+        shas.sort();
+
+        let idx = Repack::write_idx(entries, &pack[pack.len() - 20..]);
+        let fan_out_end = 8 + 256 * 4;
+        assert_eq!(idx[..8], *b"\xfftOc\0\0\0\x02");
+        assert_eq!(idx[fan_out_end - 4..fan_out_end], 4u32.to_be_bytes());
+        assert_eq!(idx[fan_out_end..fan_out_end + 4 * 20], shas.concat());
+        assert_eq!(idx.len(), fan_out_end + 4 * (20 + 4 + 4) + 2 * 20);
+        assert_eq!(idx[idx.len() - 40..idx.len() - 20], pack[pack.len() - 20..]);
+    }
+
+    #[test]
+    fn reads_back_written_pack() {
+        let repack = repack();
+        let (pack, entries) = repack.write_pack();
+        let idx = Repack::write_idx(entries, &pack[pack.len() - 20..]);
+
+        let path = env::temp_dir()
+            .join(format!("rgit-pack-test-{}", process::id()))
+            .to_string_lossy()
+            .to_string();
+        fs::write(path.to_owned() + ".pack", pack).unwrap();
+        fs::write(path.to_owned() + ".idx", idx).unwrap();
+        let loaded = Pack::load(path.to_owned());
+
+        let mut shas: Vec<String> = repack.objects.iter().map(|x| x.sha.to_owned()).collect();
+        shas.sort();
+        assert_eq!(loaded.shas(), shas);
+        for object in repack.objects.iter() {
+            let read = loaded.read(&object.sha);
+            assert_eq!(
+                read,
+                Some((object.kind.to_owned(), object.payload.to_owned()))
+            );
+        }
+        assert!(!loaded.contains(&"0".repeat(40)));
+
+        fs::remove_file(path.to_owned() + ".pack").unwrap();
+        fs::remove_file(path + ".idx").unwrap();
+    }
+}
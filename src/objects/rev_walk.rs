@@ -0,0 +1,215 @@
+use super::diff::matches_pathspec;
+use crate::{
+    objects::{Commit, Tree},
+    storing::Object,
+};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+/// Checks whether any of the files selected by the pathspecs differs between two trees
+fn changes_paths(old: &Tree, new: &Tree, pathspecs: &[String]) -> bool {
+    old.changes(new).iter().any(|(old, new)| {
+        let path = old.as_ref().or(new.as_ref()).unwrap().path();
+        matches_pathspec(path, pathspecs)
+    })
+}
+
+/// Commit waiting in the queue of a walk, ordered by commit date and then by insertion order
+struct Queued {
+    time: i64,
+    order: Reverse<usize>,
+    commit: Commit,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.time, self.order).cmp(&(other.time, other.order))
+    }
+}
+
+/// Iterates over commits and all of their ancestors, following every parent of merge commits,
+/// newest commit date first like git log does. Every commit is returned once.
+pub struct RevWalk {
+    queue: BinaryHeap<Queued>,
+    seen: HashSet<String>,
+    pushed: usize,
+    // Only commits changing these paths are returned if there are any
+    pathspecs: Vec<String>,
+    // Commits left out for not changing the paths, with the parent the walk continued with
+    simplified: HashMap<String, Option<String>>,
+}
+
+impl RevWalk {
+    pub fn new(commits: Vec<Commit>) -> RevWalk {
+        let mut walk = RevWalk {
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            pushed: 0,
+            pathspecs: Vec::new(),
+            simplified: HashMap::new(),
+        };
+
+        commits.into_iter().for_each(|commit| walk.push(commit));
+
+        walk
+    }
+
+    /// Limits the walk to commits changing the files selected by the pathspecs. Like git's
+    /// history simplification, a merge with the same files as one of its parents only continues
+    /// with that parent, as the other ones didn't contribute to those files
+    pub fn limit_to(mut self, pathspecs: Vec<String>) -> RevWalk {
+        self.pathspecs = pathspecs;
+        self
+    }
+
+    /// Parents of a commit returned by the walk, where commits left out by limit_to are replaced
+    /// by the ancestors returned in their place, like git's parent rewriting
+    pub fn parents(&self, commit: &Commit) -> Vec<String> {
+        let mut parents = Vec::new();
+
+        for parent in commit.parent_shas() {
+            let mut parent = Some(parent.to_owned());
+            while let Some(next) = parent.as_ref().and_then(|x| self.simplified.get(x)) {
+                parent = next.to_owned();
+            }
+
+            if let Some(parent) = parent.filter(|x| !parents.contains(x)) {
+                parents.push(parent);
+            }
+        }
+
+        parents
+    }
+
+    /// Walks all commits and orders them like git log --graph: no commit before any of its
+    /// children, and the commits of a branch together as far as possible. Each commit comes with
+    /// its rewritten parents
+    pub fn topo_order(mut self) -> Vec<(Commit, Vec<String>)> {
+        let (mut commits, mut shown) = (Vec::new(), Vec::new());
+        while let Some((commit, changes_paths)) = self.walk_one() {
+            commits.push(commit);
+            shown.push(changes_paths);
+        }
+
+        // Like in git, commits left out by limit_to are ordered too, leading to the parent the
+        // walk continued with
+        let parents: Vec<Vec<String>> = commits
+            .iter()
+            .map(|x| match self.simplified.get(&x.sha()) {
+                Some(parent) => parent.iter().cloned().collect(),
+                None => x.parent_shas().to_vec(),
+            })
+            .collect();
+        let indices: HashMap<String, usize> = commits
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (x.sha(), i))
+            .collect();
+
+        // Number of children of each commit which still have to come first
+        let mut children = vec![0; commits.len()];
+        for parent in parents.iter().flatten() {
+            if let Some(i) = indices.get(parent) {
+                children[*i] += 1;
+            }
+        }
+
+        // The tips come in the order of the walk, and every commit is followed by the last of
+        // its parents whose children have all come
+        let mut stack: Vec<usize> = (0..commits.len()).filter(|x| children[*x] == 0).collect();
+        stack.reverse();
+        let mut order = Vec::new();
+        while let Some(i) = stack.pop() {
+            for parent in &parents[i] {
+                if let Some(parent) = indices.get(parent) {
+                    children[*parent] -= 1;
+                    if children[*parent] == 0 {
+                        stack.push(*parent);
+                    }
+                }
+            }
+            order.push(i);
+        }
+
+        order
+            .into_iter()
+            .filter(|i| shown[*i])
+            .map(|i| {
+                let parents = self.parents(&commits[i]);
+                (commits[i].to_owned(), parents)
+            })
+            .collect()
+    }
+
+    /// Takes the next commit of the walk, together with whether it changes the paths the walk
+    /// is limited to
+    fn walk_one(&mut self) -> Option<(Commit, bool)> {
+        let commit = self.queue.pop()?.commit;
+        let mut parents = commit.parents();
+
+        if !self.pathspecs.is_empty() {
+            let same = parents
+                .iter()
+                .position(|x| !changes_paths(x.tree(), commit.tree(), &self.pathspecs));
+            if let Some(i) = same {
+                let parent = parents.swap_remove(i);
+                self.simplified.insert(commit.sha(), Some(parent.sha()));
+                self.push(parent);
+                return Some((commit, false));
+            }
+
+            let root = Tree::new("ROOT", Vec::new());
+            if parents.is_empty() && !changes_paths(&root, commit.tree(), &self.pathspecs) {
+                self.simplified.insert(commit.sha(), None);
+                return Some((commit, false));
+            }
+        }
+
+        for parent in parents {
+            self.push(parent);
+        }
+
+        Some((commit, true))
+    }
+
+    fn push(&mut self, commit: Commit) {
+        if !self.seen.insert(commit.sha()) {
+            return;
+        }
+
+        self.queue.push(Queued {
+            time: commit.committer().map_or(0, |x| x.time()),
+            order: Reverse(self.pushed),
+            commit,
+        });
+        self.pushed += 1;
+    }
+}
+
+impl Iterator for RevWalk {
+    type Item = Commit;
+
+    fn next(&mut self) -> Option<Commit> {
+        loop {
+            if let (commit, true) = self.walk_one()? {
+                return Some(commit);
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use crate::Config;
+use std::{env, fmt, time};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Converts days since the unix epoch to a (year, month, day) date of the gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+/// Converts a date of the gregorian calendar to days since the unix epoch
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the current unix timestamp
+fn now() -> i64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Returns the offset of the local timezone at a point in time in minutes
+fn local_offset(time: i64) -> i32 {
+    let time = time as libc::time_t;
+
+    // SAFETY: localtime_r only writes into the zeroed tm struct owned by this function
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+
+        (tm.tm_gmtoff / 60) as i32
+    }
+}
+
+/// Parses a timezone offset such as +0200, -05:30 or Z into minutes
+fn parse_offset(offset: &str) -> Option<i32> {
+    if offset == "Z" {
+        return Some(0);
+    }
+
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = offset[1..].replace(':', "");
+    if digits.len() != 4 || !digits.chars().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    let hours: i32 = digits[..2].parse().ok()?;
+    let minutes: i32 = digits[2..].parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parses a date either in git's raw `<unix timestamp> <offset>` format or as an ISO 8601 like
+/// `YYYY-MM-DD[ HH:MM[:SS]][ offset]`, returning the unix timestamp and the offset in minutes
+pub fn parse_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.trim();
+    let raw = date.strip_prefix('@').unwrap_or(date);
+
+    if let Some((time, offset)) = raw.split_once(' ') {
+        if let (Ok(time), Some(offset)) = (time.parse::<i64>(), parse_offset(offset)) {
+            return Some((time, offset));
+        }
+    }
+    if let Ok(time) = raw.parse::<i64>() {
+        return Some((time, local_offset(time)));
+    }
+
+    let (day, rest) = date.split_at(date.find([' ', 'T']).unwrap_or(date.len()));
+    let mut day = day.split('-').map(|x| x.parse::<i64>());
+    let (year, month, day) = (day.next()?.ok()?, day.next()?.ok()?, day.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let rest = rest.trim_start_matches([' ', 'T']);
+    let offset_i = rest.find(['+', '-', 'Z']).unwrap_or(rest.len());
+    let (clock, offset) = rest.split_at(offset_i);
+
+    let mut clock = clock
+        .trim()
+        .split(':')
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse::<i64>());
+    let hours = clock.next().unwrap_or(Ok(0)).ok()?;
+    let minutes = clock.next().unwrap_or(Ok(0)).ok()?;
+    let seconds = clock.next().unwrap_or(Ok(0)).ok()?;
+
+    let local_time = days_from_civil(year, month as u32, day as u32) * 86400
+        + hours * 3600
+        + minutes * 60
+        + seconds;
+
+    let offset = match offset.trim() {
+        "" => local_offset(local_time),
+        offset => parse_offset(offset)?,
+    };
+
+    Some((local_time - offset as i64 * 60, offset))
+}
+
+/// Parses a date anything parse_date understands, `now`, `yesterday` or a relative date like
+/// `2 weeks ago`, returning the unix timestamp. Months and years are approximated by 30 and 365
+/// days
+pub fn parse_approxidate(date: &str) -> Option<i64> {
+    if let Some((time, _)) = parse_date(date) {
+        return Some(time);
+    }
+
+    let words: Vec<&str> = date
+        .split(|x: char| x.is_whitespace() || x == '.')
+        .filter(|x| !x.is_empty())
+        .collect();
+    let (amount, unit) = match words[..] {
+        ["now"] => return Some(now()),
+        ["yesterday"] => (1, "day"),
+        [amount, unit, "ago"] => (amount.parse::<i64>().ok()?, unit),
+        _ => return None,
+    };
+
+    let seconds = match unit.strip_suffix('s').unwrap_or(unit) {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 3600,
+        "day" => 86400,
+        "week" => 7 * 86400,
+        "month" => 30 * 86400,
+        "year" => 365 * 86400,
+        _ => return None,
+    };
+
+    Some(now() - amount * seconds)
+}
+
+/// Identity and point in time of a commit's author or committer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    name: String,
+    email: String,
+    time: i64,
+    // Offset of the timezone in minutes
+    offset: i32,
+}
+
+impl Signature {
+    pub fn new(name: String, email: String, time: i64, offset: i32) -> Signature {
+        Signature {
+            name,
+            email,
+            time,
+            offset,
+        }
+    }
+
+    /// Signature of the author of a new commit
+    pub fn author() -> Option<Signature> {
+        Signature::from_env("AUTHOR")
+    }
+
+    /// Signature of the committer of a new commit
+    pub fn committer() -> Option<Signature> {
+        Signature::from_env("COMMITTER")
+    }
+
+    /// Reads the RGIT_<ROLE>_NAME, _EMAIL and _DATE environment variables, falling back to
+    /// user.name and user.email of the config and the current time
+    fn from_env(role: &str) -> Option<Signature> {
+        let config = Config::load();
+        let var = |key: &str| env::var(format!("RGIT_{}_{}", role, key)).ok();
+
+        let name = var("NAME").or(config.get("user.name").map(String::from))?;
+        let email = var("EMAIL").or(config.get("user.email").map(String::from))?;
+
+        let (time, offset) = match var("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => (now(), local_offset(now())),
+        };
+
+        Some(Signature::new(name, email, time, offset))
+    }
+
+    /// Parses a signature in the `Name <email> <unix timestamp> <offset>` format of commits
+    pub fn parse(signature: &str) -> Option<Signature> {
+        let (name, rest) = signature.split_once(" <")?;
+        let (email, date) = rest.split_once("> ")?;
+        let (time, offset) = date.split_once(' ')?;
+
+        Some(Signature::new(
+            name.to_owned(),
+            email.to_owned(),
+            time.parse().ok()?,
+            parse_offset(offset)?,
+        ))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn email(&self) -> &str {
+        &self.email
+    }
+
+    pub fn time(&self) -> i64 {
+        self.time
+    }
+
+    fn format_offset(&self) -> String {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.abs();
+        format!("{}{:02}{:02}", sign, offset / 60, offset % 60)
+    }
+
+    /// Formats the date in the signature's timezone the way git log does by default
+    pub fn format_date(&self) -> String {
+        let local_time = self.time + self.offset as i64 * 60;
+        let days = local_time.div_euclid(86400);
+        let seconds = local_time.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            seconds / 3600,
+            seconds % 3600 / 60,
+            seconds % 60,
+            year,
+            self.format_offset()
+        )
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            self.format_offset()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_raw_dates() {
+        assert_eq!(parse_date("1700000000 +0200"), Some((1700000000, 120)));
+        assert_eq!(parse_date("@1700000000 -0530"), Some((1700000000, -330)));
+        assert_eq!(
+            parse_date("1700000000"),
+            Some((1700000000, local_offset(1700000000)))
+        );
+    }
+
+    #[test]
+    fn parses_iso_dates() {
+        assert_eq!(parse_date("1970-01-01 00:00:00 +0000"), Some((0, 0)));
+        assert_eq!(parse_date("2023-11-14T22:13:20Z"), Some((1700000000, 0)));
+        assert_eq!(
+            parse_date("2023-11-15 00:13 +02:00"),
+            Some((1700000000 - 20, 120))
+        );
+        assert_eq!(parse_date("2000-02-29 -0100"), Some((951786000, -60)));
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in [
+            "",
+            "yesterday",
+            "2023-13-01",
+            "2023-01-32",
+            "2023-01",
+            "1700000000 +2x00",
+        ] {
+            assert_eq!(parse_date(date), None, "{}", date);
+        }
+    }
+
+    #[test]
+    fn parses_relative_dates() {
+        let close_to = |date: &str, ago: i64| {
+            let time = parse_approxidate(date).unwrap();
+            assert!((now() - ago - time).abs() <= 1, "{}", date);
+        };
+
+        close_to("now", 0);
+        close_to("yesterday", 86400);
+        close_to("2.weeks.ago", 14 * 86400);
+        close_to("3 hours ago", 3 * 3600);
+        close_to("1 month ago", 30 * 86400);
+        close_to("1.year.ago", 365 * 86400);
+        assert_eq!(parse_approxidate("1700000000 +0000"), Some(1700000000));
+        assert_eq!(parse_approxidate("2 fortnights ago"), None);
+        assert_eq!(parse_approxidate("soon"), None);
+    }
+}
@@ -0,0 +1,699 @@
+use crate::{
+    objects::{
+        checkout::head_entries, index, merge::same_version, Blob, Commit, Head, Index, Merge,
+        RevWalk,
+    },
+    rename::{Candidate, Rename, RenameOptions},
+    storing::Object,
+    Config, IgnoreFilter,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fmt,
+};
+
+/// Kind of change of a path between two of HEAD, the index and the working directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+    Renamed,
+    Copied,
+}
+
+impl Change {
+    fn compare(old: Option<&index::Entry>, new: Option<&index::Entry>) -> Option<Change> {
+        match (old, new) {
+            (None, Some(_)) => Some(Change::Added),
+            (Some(_), None) => Some(Change::Deleted),
+            (old, new) if !same_version(old, new) => Some(Change::Modified),
+            _ => None,
+        }
+    }
+
+    fn describe(&self) -> &str {
+        match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+            Change::Renamed => "renamed",
+            Change::Copied => "copied",
+        }
+    }
+
+    /// Status letter of the porcelain formats
+    fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+            Change::Renamed => 'R',
+            Change::Copied => 'C',
+        }
+    }
+}
+
+/// Describes an unmerged path by the conflict stages the index holds for it, together with the
+/// status letters of the porcelain formats
+fn describe_conflict(stages: &[index::Entry]) -> (&'static str, &'static str) {
+    match stages.iter().map(|x| x.stage()).collect::<Vec<_>>()[..] {
+        [1, 2, 3] => ("both modified", "UU"),
+        [2, 3] => ("both added", "AA"),
+        [1, 2] => ("deleted by them", "UD"),
+        [1, 3] => ("deleted by us", "DU"),
+        [2] => ("added by us", "AU"),
+        [3] => ("added by them", "UA"),
+        _ => ("both deleted", "DD"),
+    }
+}
+
+/// Quotes paths containing special characters the way git does, so that every path fits on
+/// one line. Short formats also quote paths containing spaces
+pub(super) fn quote_path(path: &str, quote_space: bool) -> String {
+    let needs_quotes = |x: u8| x == b'"' || x == b'\\' || !(0x20..0x7f).contains(&x);
+    if !path
+        .bytes()
+        .any(|x| needs_quotes(x) || (quote_space && x == b' '))
+    {
+        return path.to_owned();
+    }
+
+    let mut quoted = String::from("\"");
+    for byte in path.bytes() {
+        match byte {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\t' => quoted.push_str("\\t"),
+            b'\n' => quoted.push_str("\\n"),
+            0x20..=0x7e => quoted.push(byte as char),
+            _ => quoted.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Mode as recorded in trees, 0 for a missing entry
+pub(super) fn normalize_mode(entry: Option<&index::Entry>) -> u32 {
+    entry.map_or(0, |x| x.git_mode())
+}
+
+/// How the current branch relates to the branch configured as its upstream
+struct Upstream {
+    name: String,
+    ahead: usize,
+    behind: usize,
+}
+
+impl Upstream {
+    /// Reads the upstream of a branch from its branch.<name>.merge config, rgit has no remotes
+    /// so only branches of the same repository can be upstreams
+    fn of(head: &Head) -> Option<Upstream> {
+        let config = Config::load();
+        let name = head.name()?;
+
+        if !matches!(
+            config.get(&format!("branch.{}.remote", name)),
+            None | Some(".")
+        ) {
+            return None;
+        }
+        let merge = config.get(&format!("branch.{}.merge", name))?;
+        let upstream_name = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+        let upstream = Head::try_read(upstream_name)?;
+
+        let ancestors = |commit: &Option<Commit>| -> HashSet<String> {
+            RevWalk::new(commit.iter().cloned().collect())
+                .map(|x| x.sha())
+                .collect()
+        };
+        let ours = ancestors(head.commit());
+        let theirs = ancestors(upstream.commit());
+
+        Some(Upstream {
+            name: String::from(upstream_name),
+            ahead: ours.difference(&theirs).count(),
+            behind: theirs.difference(&ours).count(),
+        })
+    }
+
+    fn describe(&self) -> String {
+        let commits = |n: usize| format!("{} commit{}", n, if n == 1 { "" } else { "s" });
+
+        match (self.ahead, self.behind) {
+            (0, 0) => format!("Your branch is up to date with '{}'.", self.name),
+            (ahead, 0) => format!(
+                "Your branch is ahead of '{}' by {}.",
+                self.name,
+                commits(ahead)
+            ),
+            (0, behind) => format!(
+                "Your branch is behind '{}' by {}, and can be fast-forwarded.",
+                self.name,
+                commits(behind)
+            ),
+            (ahead, behind) => format!(
+                "Your branch and '{}' have diverged,\nand have {} and {} different commits each, respectively.",
+                self.name, ahead, behind
+            ),
+        }
+    }
+}
+
+/// Differences between HEAD, the index and the working directory
+pub struct Status {
+    head: Head,
+    upstream: Option<Upstream>,
+    is_merging: bool,
+    // Changes of HEAD's tree to the index, and of the index to the working directory
+    staged: BTreeMap<String, Change>,
+    unstaged: BTreeMap<String, Change>,
+    // Where staged files which were renamed or copied come from, by their new path
+    renames: BTreeMap<String, Rename>,
+    // Entries of all conflict stages of unmerged paths
+    unmerged: BTreeMap<String, Vec<index::Entry>>,
+    untracked: Vec<String>,
+    // Versions of the paths in HEAD, the index and the working directory, kept for the
+    // porcelain formats
+    head_entries: BTreeMap<String, index::Entry>,
+    index_entries: BTreeMap<String, index::Entry>,
+    wd_entries: BTreeMap<String, index::Entry>,
+}
+
+impl Status {
+    pub fn new(index: &Index) -> Status {
+        let head = Head::read_HEAD();
+
+        let mut unmerged: BTreeMap<String, Vec<index::Entry>> = BTreeMap::new();
+        let mut entries = BTreeMap::new();
+        for entry in index.entries() {
+            match entry.stage() {
+                0 => {
+                    entries.insert(entry.path().to_owned(), entry.to_owned());
+                }
+                _ => unmerged
+                    .entry(entry.path().to_owned())
+                    .or_default()
+                    .push(entry.to_owned()),
+            }
+        }
+        unmerged
+            .values_mut()
+            .for_each(|x| x.sort_by_key(|x| x.stage()));
+
+        let head_entries = head_entries(&head);
+        let paths: BTreeSet<&String> = head_entries.keys().chain(entries.keys()).collect();
+        let mut staged = paths
+            .into_iter()
+            .filter(|path| !unmerged.contains_key(*path))
+            .filter_map(|path| {
+                Change::compare(head_entries.get(path), entries.get(path))
+                    .map(|change| (path.to_owned(), change))
+            })
+            .collect();
+
+        let wd_entries: BTreeMap<String, index::Entry> = entries
+            .keys()
+            .chain(unmerged.keys())
+            .filter_map(|path| index.wd_entry(path).map(|x| (path.to_owned(), x)))
+            .collect();
+        let unstaged = entries
+            .iter()
+            .filter_map(|(path, entry)| {
+                Change::compare(Some(entry), wd_entries.get(path)).map(|x| (path.to_owned(), x))
+            })
+            .collect();
+
+        let renames = match RenameOptions::configured(&["status.renames", "diff.renames"]) {
+            Some(options) => Status::detect_renames(&mut staged, &head_entries, &entries, options),
+            None => BTreeMap::new(),
+        };
+
+        let untracked = Status::untracked(index);
+
+        Status {
+            upstream: Upstream::of(&head),
+            head,
+            is_merging: Merge::in_progress().is_some(),
+            staged,
+            unstaged,
+            renames,
+            unmerged,
+            untracked,
+            head_entries,
+            index_entries: entries,
+            wd_entries,
+        }
+    }
+
+    /// Turns staged files which were renamed or copied from a file of HEAD into renames or copies,
+    /// renamed files are no longer staged as deleted
+    fn detect_renames(
+        staged: &mut BTreeMap<String, Change>,
+        head_entries: &BTreeMap<String, index::Entry>,
+        entries: &BTreeMap<String, index::Entry>,
+        options: RenameOptions,
+    ) -> BTreeMap<String, Rename> {
+        // New versions of added files, old versions of all others
+        let blobs: BTreeMap<&String, (&str, Blob)> = staged
+            .iter()
+            .filter_map(|(path, change)| match change {
+                Change::Added => Some((path, entries.get(path)?)),
+                _ => Some((path, head_entries.get(path)?)),
+            })
+            // The commits of submodules aren't in the database and can't be compared
+            .filter(|(_, entry)| entry.git_mode() != index::GITLINK_MODE)
+            .map(|(path, entry)| {
+                let blob = Blob::new_from_object_file(entry.sha(), None);
+                (path, (entry.sha(), blob))
+            })
+            .collect();
+        let candidates = |kind: Change| -> Vec<Candidate> {
+            staged
+                .iter()
+                .filter(|(path, change)| **change == kind && blobs.contains_key(path))
+                .map(|(path, _)| Candidate {
+                    path,
+                    sha: blobs[path].0,
+                    bytes: blobs[path].1.bytes(),
+                })
+                .collect()
+        };
+
+        let (deleted, modified, added) = (
+            candidates(Change::Deleted),
+            candidates(Change::Modified),
+            candidates(Change::Added),
+        );
+        let renames: BTreeMap<String, Rename> = options
+            .detect(&deleted, &modified, &added)
+            .into_iter()
+            .map(|(i, rename)| (added[i].path.to_owned(), rename))
+            .collect();
+
+        for (path, rename) in renames.iter() {
+            if !rename.copy {
+                staged.remove(&rename.from);
+            }
+            let change = if rename.copy {
+                Change::Copied
+            } else {
+                Change::Renamed
+            };
+            staged.insert(path.to_owned(), change);
+        }
+
+        renames
+    }
+
+    /// Collects the files which aren't in the index, directories without any tracked files are
+    /// listed as a whole
+    fn untracked(index: &Index) -> Vec<String> {
+        let ignore_filter = IgnoreFilter::new();
+        let tracked: HashSet<&str> = index.entries().iter().map(|x| x.path()).collect();
+        let tracked_dirs: HashSet<&str> = tracked
+            .iter()
+            .flat_map(|path| path.match_indices('/').map(|(i, _)| &path[..=i]))
+            .collect();
+
+        let untracked: BTreeSet<String> = ignore_filter
+            .files(".")
+            .into_iter()
+            .filter(|x| !tracked.contains(x.as_str()))
+            .map(|path| {
+                match path
+                    .match_indices('/')
+                    .map(|(i, _)| &path[..=i])
+                    .find(|dir| !tracked_dirs.contains(dir))
+                {
+                    Some(dir) => dir.to_owned(),
+                    None => path,
+                }
+            })
+            .collect();
+
+        untracked.into_iter().collect()
+    }
+
+    fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.unmerged.is_empty()
+    }
+
+    /// Returns the paths of all tracked changes, sorted, each with its index and working
+    /// directory status letters
+    fn changed_paths(&self) -> Vec<(&str, char, char)> {
+        let paths: BTreeSet<&String> = self.staged.keys().chain(self.unstaged.keys()).collect();
+
+        paths
+            .into_iter()
+            .map(|path| {
+                (
+                    path.as_str(),
+                    self.staged.get(path).map_or(' ', |x| x.code()),
+                    self.unstaged.get(path).map_or(' ', |x| x.code()),
+                )
+            })
+            .collect()
+    }
+
+    /// Formats the branch header line of the short formats
+    fn short_branch(&self) -> String {
+        let Some(name) = self.head.name() else {
+            return String::from("## HEAD (no branch)");
+        };
+        if self.head.commit().is_none() {
+            return format!("## No commits yet on {}", name);
+        }
+
+        let mut line = format!("## {}", name);
+        if let Some(upstream) = &self.upstream {
+            line.push_str(&format!("...{}", upstream.name));
+
+            let counts: Vec<String> = [("ahead", upstream.ahead), ("behind", upstream.behind)]
+                .into_iter()
+                .filter(|(_, n)| *n > 0)
+                .map(|(kind, n)| format!("{} {}", kind, n))
+                .collect();
+            if !counts.is_empty() {
+                line.push_str(&format!(" [{}]", counts.join(", ")));
+            }
+        }
+
+        line
+    }
+
+    /// Formats the status like `git status --porcelain=v1`, lines are terminated by null bytes
+    /// instead of newlines and paths aren't quoted if nul_terminated is set
+    pub fn porcelain_v1(&self, branch: bool, nul_terminated: bool) -> String {
+        let terminator = if nul_terminated { '\0' } else { '\n' };
+        let path = |path: &str| {
+            if nul_terminated {
+                path.to_owned()
+            } else {
+                quote_path(path, true)
+            }
+        };
+
+        let mut lines = Vec::new();
+        if branch {
+            lines.push(self.short_branch());
+        }
+
+        let mut changes: Vec<(&str, String)> = self
+            .changed_paths()
+            .into_iter()
+            .map(
+                |(p, x, y)| match self.renames.get(p).filter(|_| x == 'R' || x == 'C') {
+                    // Null terminated lines hold the original path after the path instead
+                    Some(rename) if nul_terminated => {
+                        (p, format!("{}{} {}\0{}", x, y, path(p), path(&rename.from)))
+                    }
+                    Some(rename) => (
+                        p,
+                        format!("{}{} {} -> {}", x, y, path(&rename.from), path(p)),
+                    ),
+                    None => (p, format!("{}{} {}", x, y, path(p))),
+                },
+            )
+            .collect();
+        changes.extend(self.unmerged.iter().map(|(p, stages)| {
+            (
+                p.as_str(),
+                format!("{} {}", describe_conflict(stages).1, path(p)),
+            )
+        }));
+        changes.sort();
+
+        lines.extend(changes.into_iter().map(|(_, line)| line));
+        lines.extend(self.untracked.iter().map(|p| format!("?? {}", path(p))));
+
+        lines
+            .into_iter()
+            .map(|x| format!("{}{}", x, terminator))
+            .collect()
+    }
+
+    /// Formats the status like `git status --porcelain=v2`, with the modes and shas of each
+    /// version of a changed path
+    pub fn porcelain_v2(&self, branch: bool, nul_terminated: bool) -> String {
+        let terminator = if nul_terminated { '\0' } else { '\n' };
+        let path = |path: &str| {
+            if nul_terminated {
+                path.to_owned()
+            } else {
+                quote_path(path, false)
+            }
+        };
+        let sha =
+            |entry: Option<&index::Entry>| entry.map_or("0".repeat(40), |x| x.sha().to_owned());
+
+        let mut lines = Vec::new();
+        if branch {
+            lines.push(format!(
+                "# branch.oid {}",
+                self.head
+                    .commit()
+                    .as_ref()
+                    .map_or(String::from("(initial)"), |x| x.sha())
+            ));
+            lines.push(format!(
+                "# branch.head {}",
+                self.head.name().unwrap_or("(detached)")
+            ));
+            if let Some(upstream) = &self.upstream {
+                lines.push(format!("# branch.upstream {}", upstream.name));
+                lines.push(format!(
+                    "# branch.ab +{} -{}",
+                    upstream.ahead, upstream.behind
+                ));
+            }
+        }
+
+        let mut changes: Vec<(&str, String)> = self
+            .changed_paths()
+            .into_iter()
+            .map(|(p, x, y)| {
+                let rename = self.renames.get(p).filter(|_| x == 'R' || x == 'C');
+                // Renamed and copied files are compared to the file of HEAD they come from
+                let head_path = rename.map_or(p, |x| x.from.as_str());
+                let (head, index, wd) = (
+                    self.head_entries.get(head_path),
+                    self.index_entries.get(p),
+                    self.wd_entries.get(p),
+                );
+
+                let fields = format!(
+                    "{}{} N... {:06o} {:06o} {:06o} {} {}",
+                    x.to_string().replace(' ', "."),
+                    y.to_string().replace(' ', "."),
+                    normalize_mode(head),
+                    normalize_mode(index),
+                    normalize_mode(wd),
+                    sha(head),
+                    sha(index),
+                );
+                let line = match rename {
+                    Some(rename) => format!(
+                        "2 {} {}{} {}{}{}",
+                        fields,
+                        x,
+                        rename.score,
+                        path(p),
+                        if nul_terminated { '\0' } else { '\t' },
+                        path(&rename.from)
+                    ),
+                    None => format!("1 {} {}", fields, path(p)),
+                };
+                (p, line)
+            })
+            .collect();
+        changes.extend(self.unmerged.iter().map(|(p, stages)| {
+            let stage = |n: u8| stages.iter().find(|x| x.stage() == n);
+
+            let line = format!(
+                "u {} N... {:06o} {:06o} {:06o} {:06o} {} {} {} {}",
+                describe_conflict(stages).1,
+                normalize_mode(stage(1)),
+                normalize_mode(stage(2)),
+                normalize_mode(stage(3)),
+                normalize_mode(self.wd_entries.get(p)),
+                sha(stage(1)),
+                sha(stage(2)),
+                sha(stage(3)),
+                path(p)
+            );
+            (p.as_str(), line)
+        }));
+        changes.sort();
+
+        lines.extend(changes.into_iter().map(|(_, line)| line));
+        lines.extend(self.untracked.iter().map(|p| format!("? {}", path(p))));
+
+        lines
+            .into_iter()
+            .map(|x| format!("{}{}", x, terminator))
+            .collect()
+    }
+
+    /// Describes the status as a JSON document
+    pub fn to_json(&self) -> Value {
+        let changes = |changes: &BTreeMap<String, Change>| -> Vec<Value> {
+            changes
+                .iter()
+                .map(|(path, change)| {
+                    let name = match change {
+                        Change::Added => "added",
+                        Change::Modified => "modified",
+                        Change::Deleted => "deleted",
+                        Change::Renamed => "renamed",
+                        Change::Copied => "copied",
+                    };
+                    match self.renames.get(path) {
+                        Some(rename) if matches!(change, Change::Renamed | Change::Copied) => {
+                            json!({
+                                "path": path,
+                                "change": name,
+                                "from": rename.from,
+                                "similarity": rename.score,
+                            })
+                        }
+                        _ => json!({ "path": path, "change": name }),
+                    }
+                })
+                .collect()
+        };
+
+        let unmerged: Vec<Value> = self
+            .unmerged
+            .iter()
+            .map(|(path, stages)| {
+                json!({
+                    "path": path,
+                    "conflict": describe_conflict(stages).0,
+                    "stages": stages.iter().map(|x| json!({
+                        "stage": x.stage(),
+                        "mode": format!("{:06o}", normalize_mode(Some(x))),
+                        "sha": x.sha(),
+                    })).collect::<Vec<Value>>(),
+                })
+            })
+            .collect();
+
+        json!({
+            "branch": {
+                "head": self.head.name(),
+                "oid": self.head.commit().as_ref().map(|x| x.sha()),
+                "upstream": self.upstream.as_ref().map(|x| json!({
+                    "name": x.name,
+                    "ahead": x.ahead,
+                    "behind": x.behind,
+                })),
+            },
+            "merging": self.is_merging,
+            "staged": changes(&self.staged),
+            "unstaged": changes(&self.unstaged),
+            "unmerged": unmerged,
+            "untracked": self.untracked,
+        })
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.head.describe())?;
+        if let Some(upstream) = &self.upstream {
+            writeln!(f, "{}", upstream.describe())?;
+        }
+
+        if self.head.commit().is_none() {
+            writeln!(f, "\nNo commits yet")?;
+        }
+
+        if self.is_merging {
+            if self.unmerged.is_empty() {
+                writeln!(f, "\nAll conflicts fixed but you are still merging.")?;
+                writeln!(f, "  (use \"rgit commit\" to conclude merge)")?;
+            } else {
+                writeln!(f, "\nYou have unmerged paths.")?;
+                writeln!(f, "  (fix conflicts and run \"rgit commit\")")?;
+            }
+        }
+
+        let section = |f: &mut fmt::Formatter<'_>,
+                       title: &str,
+                       hint: Option<&str>,
+                       lines: Vec<String>|
+         -> fmt::Result {
+            if lines.is_empty() {
+                return Ok(());
+            }
+
+            writeln!(f, "\n{}:", title)?;
+            if let Some(hint) = hint {
+                writeln!(f, "  ({})", hint)?;
+            }
+            for line in lines {
+                writeln!(f, "\t{}", line)?;
+            }
+            Ok(())
+        };
+        let changes = |changes: &BTreeMap<String, Change>| {
+            changes
+                .iter()
+                .map(|(path, change)| {
+                    let path = match (change, self.renames.get(path)) {
+                        (Change::Renamed | Change::Copied, Some(rename)) => {
+                            format!("{} -> {}", rename.from, path)
+                        }
+                        _ => path.to_owned(),
+                    };
+                    format!("{:<12}{}", change.describe().to_owned() + ":", path)
+                })
+                .collect()
+        };
+
+        section(f, "Changes to be committed", None, changes(&self.staged))?;
+        section(
+            f,
+            "Unmerged paths",
+            Some("use \"rgit add <file>...\" to mark resolution"),
+            self.unmerged
+                .iter()
+                .map(|(path, stages)| {
+                    format!(
+                        "{:<17}{}",
+                        describe_conflict(stages).0.to_owned() + ":",
+                        path
+                    )
+                })
+                .collect(),
+        )?;
+        section(
+            f,
+            "Changes not staged for commit",
+            Some("use \"rgit add <file>...\" to update what will be committed"),
+            changes(&self.unstaged),
+        )?;
+        section(
+            f,
+            "Untracked files",
+            Some("use \"rgit add <file>...\" to include in what will be committed"),
+            self.untracked.clone(),
+        )?;
+
+        if self.is_clean() {
+            match (self.untracked.is_empty(), self.head.commit().is_none()) {
+                (true, true) => writeln!(f, "\nnothing to commit (create/copy files and use \"rgit add\" to track)")?,
+                (true, false) => writeln!(f, "\nnothing to commit, working tree clean")?,
+                (false, _) => writeln!(f, "\nnothing added to commit but untracked files present (use \"rgit add\" to track)")?,
+            }
+        } else if self.staged.is_empty() {
+            writeln!(f, "\nno changes added to commit (use \"rgit add\")")?;
+        }
+
+        Ok(())
+    }
+}
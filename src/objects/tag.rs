@@ -0,0 +1,140 @@
+use super::head::ref_names;
+use crate::{
+    identifiers,
+    objects::{Commit, FsDatabase, ObjectDatabase},
+    storing::Object,
+    Paths,
+};
+use std::{fs, path};
+
+thread_local! {
+    // Tags are only read once per run, so that broken ones are only reported once
+    static TAGS: Vec<Tag> = Tag::read_all();
+}
+
+/// A tag in the .rgit/refs/tags directory, pointing to an object either directly or through
+/// annotated tag objects
+#[derive(Clone)]
+pub struct Tag {
+    name: String,
+    // Annotated tag objects leading from the ref to the tagged object, none for lightweight tags
+    tag_objects: Vec<String>,
+    // Tagged object with its type, usually a commit
+    object: String,
+    kind: String,
+}
+
+impl Tag {
+    fn read(name: String) -> Option<Tag> {
+        let path = Paths::tags() + "/" + &name;
+        Tag::read_from(&FsDatabase, name, &path)
+    }
+
+    /// Resolves the object a tag ref points to, following annotated tags to the object they tag.
+    /// Returns Option::None if the ref isn't a full sha or one of the objects is missing
+    fn read_from(db: &dyn ObjectDatabase, name: String, path: &str) -> Option<Tag> {
+        let buf = fs::read_to_string(path).ok()?;
+        let mut sha = buf.trim().to_owned();
+        let mut tag_objects = Vec::new();
+
+        loop {
+            if sha.len() != 40 || !sha.chars().all(|x| x.is_ascii_hexdigit()) {
+                return None;
+            }
+
+            match db.read(&sha)? {
+                (kind, payload) if kind == identifiers::TAG => {
+                    let payload = String::from_utf8_lossy(&payload);
+                    let object = payload.lines().next()?.strip_prefix("object ")?.to_owned();
+                    tag_objects.push(std::mem::replace(&mut sha, object));
+                }
+                (kind, _) => {
+                    return Some(Tag {
+                        name,
+                        tag_objects,
+                        object: sha,
+                        kind,
+                    })
+                }
+            }
+        }
+    }
+
+    /// Returns all tags sorted by name, tags whose objects are missing are reported and left out
+    pub fn all() -> Vec<Tag> {
+        TAGS.with(|tags| tags.clone())
+    }
+
+    /// Repositories created before rgit knew about tags have no tags directory
+    fn read_all() -> Vec<Tag> {
+        if !path::Path::new(&Paths::tags()).is_dir() {
+            return Vec::new();
+        }
+
+        ref_names(Paths::tags())
+            .into_iter()
+            .filter_map(|name| {
+                let tag = Tag::read(name.to_owned());
+                if tag.is_none() {
+                    eprintln!("warning: ignoring broken ref refs/tags/{}", name);
+                }
+                tag
+            })
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn tag_objects(&self) -> &[String] {
+        &self.tag_objects
+    }
+
+    /// Sha and type of the tagged object
+    pub fn object(&self) -> (&str, &str) {
+        (&self.object, &self.kind)
+    }
+
+    /// Returns the tagged commit, Option::None for tags of other objects
+    pub fn commit(&self) -> Option<Commit> {
+        (self.kind == identifiers::COMMIT).then(|| Commit::new_from_object_file(&self.object, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{objects::MemoryDatabase, storing};
+    use std::{env, process};
+
+    #[test]
+    fn ignores_refs_which_arent_shas() {
+        let db = MemoryDatabase::new();
+        let sha = db.write(&storing::add_header(identifiers::BLOB, b"hello\n"));
+        let path = env::temp_dir()
+            .join(format!("rgit-tag-test-{}", process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        fs::write(&path, sha.to_owned() + "\n").unwrap();
+        let tag = Tag::read_from(&db, "v1".to_owned(), &path).unwrap();
+        assert_eq!(
+            (tag.object, tag.kind),
+            (sha.to_owned(), identifiers::BLOB.to_owned())
+        );
+
+        for garbage in [
+            "",
+            "garbage\n",
+            &sha[..7],
+            &(sha.to_owned() + "00"),
+            "../../HEAD",
+        ] {
+            fs::write(&path, garbage).unwrap();
+            assert!(Tag::read_from(&db, "v1".to_owned(), &path).is_none());
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+}
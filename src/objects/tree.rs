@@ -1,41 +1,90 @@
 use crate::{
-    identifiers,
-    objects::{index, Blob, Index},
-    storing::{Object, Storable},
-    Paths,
+    hex, identifiers,
+    objects::{checkout::write_wd_file, index, Blob, Commit, FsDatabase, Index, ObjectDatabase},
+    storing::{self, Object, Storable},
 };
 use sha1_smol::Sha1;
-use std::{fs, io::Write, os::unix::prelude::PermissionsExt, path};
+use std::{
+    cell::OnceCell,
+    collections::{BTreeMap, HashSet},
+    fs, path,
+};
 
-// Used because actual numbers such as file size are stored as strings in serialized objects and then
-// reverted back
-const ENCODING_RADIX: u32 = 10;
+// Modes are stored as octal strings in serialized tree objects
+const ENCODING_RADIX: u32 = 8;
+const TREE_MODE: u32 = 0o40000;
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Change of a file between two trees, holding its old and new version as index entries, a
+/// missing version means the file was added or deleted
+pub type TreeChange = (Option<index::Entry>, Option<index::Entry>);
+
+#[derive(Debug, Clone)]
 enum EntryType {
     Tree(Tree),
     Blob(Entry),
 }
 
+impl EntryType {
+    /// Name used to order entries in a serialized tree, git sorts trees as if they had a trailing slash
+    fn sort_key(&self) -> Vec<u8> {
+        match self {
+            EntryType::Tree(tree) => format!("{}/", tree.name).into_bytes(),
+            EntryType::Blob(blob) => blob.file_name.to_owned().into_bytes(),
+        }
+    }
+
+    /// Flattens the entry into index entries with paths prefixed by the entry's directory
+    fn to_index_entries(&self, prefix: &str) -> Vec<index::Entry> {
+        match self {
+            EntryType::Tree(tree) => tree
+                .to_index_entries()
+                .into_iter()
+                .map(|mut i_entry| {
+                    i_entry.path_mut().insert_str(0, prefix);
+                    i_entry
+                })
+                .collect(),
+            EntryType::Blob(blob) => vec![blob.to_index_entry(prefix)],
+        }
+    }
+}
+
 trait TreeEntry {
     /// Serializes the entry as an entry of a tree object, not as the object itself
     fn serialize_as_entry(&self) -> Vec<u8>;
     /// Creates the corrisponding EntryType from an entry of a serialized tree object
-    fn deserialize_as_entry(buf: Vec<u8>) -> EntryType;
+    fn deserialize_as_entry(
+        db: &dyn ObjectDatabase,
+        mode: u32,
+        name: String,
+        sha: String,
+    ) -> EntryType;
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Serializes a tree entry in git's `mode name\0<binary sha>` format
+fn serialize_entry(mode: u32, name: &str, sha: &str) -> Vec<u8> {
+    let mut buf = format!("{:o} {}\0", mode, name).into_bytes();
+    buf.extend(hex::decode(sha));
+    buf
+}
+
+#[derive(Debug, Clone)]
 pub struct Tree {
-    entries: Vec<EntryType>,
+    // Trees read from the database only hold the shas of their subtrees, whose entries are
+    // loaded on first access
+    entries: OnceCell<Vec<EntryType>>,
     name: String,
+    // Set to the sha a tree was read with, trees created from an index are hashed on first access
+    sha: OnceCell<String>,
 }
 
 impl Tree {
     /// Creates a new tree from index entries, converting them to tree entries
     pub fn new(name: &str, entries: Vec<index::Entry>) -> Tree {
         let mut tree = Tree {
-            entries: Vec::new(),
+            entries: OnceCell::from(Vec::new()),
             name: String::from(name),
+            sha: OnceCell::new(),
         };
 
         tree.add_index_entries(entries);
@@ -43,6 +92,41 @@ impl Tree {
         tree
     }
 
+    /// Creates a tree of which only the sha is known, its entries are read when first accessed
+    fn unloaded(name: String, sha: String) -> Tree {
+        Tree {
+            entries: OnceCell::new(),
+            name,
+            sha: OnceCell::from(sha),
+        }
+    }
+
+    /// Returns the entries of the tree, loading them from the .rgit/objects directory if necessary
+    fn entries(&self) -> &[EntryType] {
+        self.entries
+            .get_or_init(|| Tree::load_entries(&FsDatabase, self.sha.get().unwrap()))
+    }
+
+    fn entries_mut(&mut self) -> &mut Vec<EntryType> {
+        self.entries
+            .get_mut()
+            .expect("Only trees created from an index are filled")
+    }
+
+    /// Reads the entries of a tree object, subtrees are only read when they are accessed
+    fn load_entries(db: &dyn ObjectDatabase, sha: &str) -> Vec<EntryType> {
+        Tree::read_entries(db, sha)
+            .into_iter()
+            .map(|(mode, name, sha)| {
+                if mode == TREE_MODE {
+                    Tree::deserialize_as_entry(db, mode, name, sha)
+                } else {
+                    Entry::deserialize_as_entry(db, mode, name, sha)
+                }
+            })
+            .collect()
+    }
+
     /// Recursively fills a tree object with a list of index entries
     fn add_index_entries(&mut self, mut entries: Vec<index::Entry>) {
         // To avoid cloning and having multiple instances of entries which
@@ -60,13 +144,13 @@ impl Tree {
             })
             .iter()
             .map(|i| entries.remove(*i))
-            .map(|i_entry| Entry::from(i_entry))
-            .map(|t_entry| EntryType::Blob(t_entry))
-            .for_each(|blob| self.entries.push(blob));
+            .map(Entry::from)
+            .map(EntryType::Blob)
+            .for_each(|blob| self.entries_mut().push(blob));
 
-        while entries.len() > 0 {
+        while !entries.is_empty() {
             let path = entries[0].path().to_owned();
-            let dir = &path[0..path.find("/").unwrap()];
+            let dir = &path[0..=path.find('/').unwrap()];
 
             let entries_with_dir = entries
                 .iter()
@@ -80,20 +164,86 @@ impl Tree {
                 .iter()
                 .map(|i| entries.remove(*i))
                 .map(|mut i_entry| {
-                    let slash_offset = i_entry.path().find("/").unwrap();
+                    let slash_offset = i_entry.path().find('/').unwrap();
                     i_entry.path_mut().replace_range(..=slash_offset, "");
                     i_entry
                 })
                 .collect();
 
-            let tree = Tree::new(dir, entries_with_dir);
-            self.entries.push(EntryType::Tree(tree));
+            let tree = Tree::new(dir.trim_end_matches('/'), entries_with_dir);
+            self.entries_mut().push(EntryType::Tree(tree));
+        }
+    }
+
+    /// Resolves a revision to the tree of its commit, or a full or abbreviated tree sha to its tree
+    pub fn from_rev(rev: &str) -> Option<Tree> {
+        if let Some(commit) = Commit::from_rev(rev) {
+            return Some(commit.tree().to_owned());
+        }
+
+        FsDatabase
+            .find_prefix(rev, identifiers::TREE)
+            .map(|sha| Tree::new_from_object_file(&sha, Some(String::from("ROOT"))))
+    }
+
+    /// Collects the files which differ between this tree and another one, sorted by path.
+    /// Subtrees with the same sha on both sides hold the same files and aren't even loaded
+    pub fn changes(&self, other: &Tree) -> Vec<TreeChange> {
+        let mut changes = Vec::new();
+        Tree::collect_changes(self.entries(), other.entries(), "", &mut changes);
+        changes.sort_by(|a, b| {
+            let path = |x: &TreeChange| x.0.as_ref().or(x.1.as_ref()).unwrap().path().to_owned();
+            path(a).cmp(&path(b))
+        });
+
+        changes
+    }
+
+    fn collect_changes(
+        old: &[EntryType],
+        new: &[EntryType],
+        prefix: &str,
+        changes: &mut Vec<TreeChange>,
+    ) {
+        // Files and directories of the same name are different entries, like in git's sort order
+        let mut entries: BTreeMap<Vec<u8>, (Option<&EntryType>, Option<&EntryType>)> =
+            BTreeMap::new();
+        old.iter()
+            .for_each(|x| entries.entry(x.sort_key()).or_default().0 = Some(x));
+        new.iter()
+            .for_each(|x| entries.entry(x.sort_key()).or_default().1 = Some(x));
+
+        for sides in entries.into_values() {
+            match sides {
+                (Some(EntryType::Tree(old)), Some(EntryType::Tree(new))) => {
+                    if old.sha() != new.sha() {
+                        let prefix = format!("{}{}/", prefix, old.name);
+                        Tree::collect_changes(old.entries(), new.entries(), &prefix, changes);
+                    }
+                }
+                (Some(EntryType::Blob(old)), Some(EntryType::Blob(new))) => {
+                    if old != new {
+                        changes.push((
+                            Some(old.to_index_entry(prefix)),
+                            Some(new.to_index_entry(prefix)),
+                        ));
+                    }
+                }
+                (old, new) => {
+                    old.into_iter()
+                        .flat_map(|x| x.to_index_entries(prefix))
+                        .for_each(|x| changes.push((Some(x), None)));
+                    new.into_iter()
+                        .flat_map(|x| x.to_index_entries(prefix))
+                        .for_each(|x| changes.push((None, Some(x))));
+                }
+            }
         }
     }
 
     /// Returns the trees entries, filtered to be only trees
     fn get_trees(&self) -> impl Iterator<Item = (usize, &Tree)> {
-        self.entries
+        self.entries()
             .iter()
             .enumerate()
             .filter(|(_, entry_type)| match entry_type {
@@ -122,22 +272,12 @@ impl Tree {
     //         .rev()
     // }
 
-    pub fn sha(&self) -> String {
-        let buf = self.serialize();
-
-        let mut hasher = Sha1::new();
-        hasher.update(&buf);
-        let sha = hasher.digest().to_string();
-
-        sha
-    }
-
     // util function
     /// Recursively prints all the shas the tree holds
     pub fn print_shas(&self) {
         println!("{}: {}", self.name, self.sha());
 
-        for entry in &self.entries {
+        for entry in self.entries() {
             match entry {
                 EntryType::Tree(tree) => tree.print_shas(),
                 EntryType::Blob(blob) => println!("{}: {}", blob.file_name, blob.sha),
@@ -145,6 +285,92 @@ impl Tree {
         }
     }
 
+    /// Collects the shas of a tree, its subtrees and its blobs together with their paths relative
+    /// to the root tree. Trees in seen were collected before together with everything they hold,
+    /// so they are skipped without reading them again
+    pub fn collect_objects(
+        db: &dyn ObjectDatabase,
+        sha: &str,
+        path: &str,
+        seen: &mut HashSet<String>,
+        objects: &mut Vec<(String, String)>,
+    ) {
+        if !seen.insert(sha.to_owned()) {
+            return;
+        }
+        objects.push((sha.to_owned(), path.to_owned()));
+
+        let prefix = if path.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", path)
+        };
+
+        for (mode, name, sha) in Tree::read_entries(db, sha) {
+            if mode == TREE_MODE {
+                Tree::collect_objects(db, &sha, &(prefix.clone() + &name), seen, objects);
+            } else if mode == index::GITLINK_MODE {
+                // Submodule commits are in the submodule's repository, not in this one
+                continue;
+            } else if seen.insert(sha.to_owned()) {
+                objects.push((sha, prefix.clone() + &name));
+            }
+        }
+    }
+
+    /// Parses the mode, name and sha of every entry of a tree object without reading any subtrees
+    fn read_entries(db: &dyn ObjectDatabase, sha: &str) -> Vec<(u32, String, String)> {
+        let (kind, payload) = db.read_existing(sha);
+        let mut payload = payload.as_slice();
+        assert_eq!(kind, identifiers::TREE, "Object {} is not a tree", sha);
+
+        let mut entries = Vec::new();
+
+        while !payload.is_empty() {
+            let space_i = payload.iter().position(|x| *x == b' ').unwrap();
+            let null_i = payload.iter().position(|x| *x == b'\0').unwrap();
+
+            let mode = std::str::from_utf8(&payload[..space_i]).unwrap();
+            let mode = u32::from_str_radix(mode, ENCODING_RADIX).unwrap();
+            let name = String::from_utf8(payload[space_i + 1..null_i].into()).unwrap();
+            let sha = hex::encode(&payload[null_i + 1..null_i + 21]);
+            entries.push((mode, name, sha));
+
+            payload = &payload[null_i + 21..];
+        }
+
+        entries
+    }
+
+    /// Flattens the tree into index entries for all blobs it recursively holds, the inverse of
+    /// creating a tree from an index
+    pub fn to_index_entries(&self) -> Vec<index::Entry> {
+        let mut entries = Vec::new();
+        let prefix = if self.name == "ROOT" {
+            String::new()
+        } else {
+            format!("{}/", self.name)
+        };
+
+        for entry in self.entries() {
+            match entry {
+                EntryType::Tree(tree) => {
+                    entries.extend(tree.to_index_entries().into_iter().map(|mut i_entry| {
+                        i_entry.path_mut().insert_str(0, &prefix);
+                        i_entry
+                    }))
+                }
+                EntryType::Blob(blob) => entries.push(index::Entry::new(
+                    blob.mode,
+                    prefix.to_owned() + &blob.file_name,
+                    blob.sha.to_owned(),
+                )),
+            }
+        }
+
+        entries
+    }
+
     /// Restores the actual directory system the tree represents into a given path
     pub fn restore(&self, path: String) {
         let is_root = self.name.eq("ROOT");
@@ -161,7 +387,7 @@ impl Tree {
 
         fs::DirBuilder::new().recursive(true).create(&path).unwrap();
 
-        for entry in &self.entries {
+        for entry in self.entries() {
             match entry {
                 EntryType::Tree(tree) => tree.restore(path.to_owned()),
                 EntryType::Blob(blob) => blob.restore(path.to_owned()),
@@ -173,114 +399,77 @@ impl Tree {
 // Creates a tree from an index
 impl From<Index> for Tree {
     fn from(index: Index) -> Self {
-        let entries = index.entries().to_owned();
-        let tree = Tree::new("ROOT", entries);
+        // Like git, paths only intended to be added aren't committed yet
+        let entries = index
+            .entries()
+            .iter()
+            .filter(|x| !x.is_intent_to_add())
+            .cloned()
+            .collect();
 
-        tree
+        Tree::new("ROOT", entries)
     }
 }
 
 impl Storable for Tree {
     fn store(&self) {
-        self.get_trees().for_each(|(_, tree)| tree.store());
-
-        let buf = self.serialize();
-
-        let mut file = fs::File::create(Paths::objects() + "/" + &self.sha())
-            .expect("Failed to create file to store tree");
-
-        file.write_all(&buf).unwrap();
+        self.write_to(&FsDatabase);
     }
 }
 
 impl Object for Tree {
-    fn new_from_object_file(sha: &str, name: Option<String>) -> Self {
-        let buf = fs::read(Paths::objects() + "/" + sha).unwrap();
-
-        let null_i = buf.iter().position(|x| *x == b'\0').unwrap();
-        let buf = Vec::from(&buf[null_i + 1..]);
-
-        let bufs = buf.split(|x| *x == b'\0').filter(|x| x.len() != 0);
-
-        let entries = bufs
-            .map(|buf| {
-                if buf.starts_with(identifiers::TREE.as_bytes()) {
-                    Tree::deserialize_as_entry(buf.into())
-                } else if buf.starts_with(identifiers::BLOB.as_bytes()) {
-                    Entry::deserialize_as_entry(buf.into())
-                } else {
-                    panic!();
-                }
-            })
-            .collect();
-
+    fn read_from(db: &dyn ObjectDatabase, sha: &str, name: Option<String>) -> Self {
         Tree {
-            entries,
+            entries: OnceCell::from(Tree::load_entries(db, sha)),
             name: name.unwrap(),
+            sha: OnceCell::from(sha.to_owned()),
         }
     }
 
-    fn serialize(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
+    fn write_to(&self, db: &dyn ObjectDatabase) {
+        // Trees in the database already are stored together with everything they hold
+        if db.exists(&self.sha()) {
+            return;
+        }
+        self.get_trees().for_each(|(_, tree)| tree.write_to(db));
+
+        db.write(&self.serialize());
+    }
 
-        buf.extend(identifiers::TREE.bytes());
-        buf.push(b' ');
+    fn serialize(&self) -> Vec<u8> {
+        let mut entries = self.entries().iter().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.sort_key());
 
-        let mut entries = self.entries.to_owned();
-        entries.sort();
-        let entries = entries
+        let payload = entries
             .iter()
-            .map(|entry| match entry {
+            .flat_map(|entry| match entry {
                 EntryType::Tree(tree) => tree.serialize_as_entry(),
                 EntryType::Blob(blob) => blob.serialize_as_entry(),
             })
-            .fold(&mut Vec::new(), |buf, entry| {
-                buf.extend(entry);
-                buf.push(b'\0');
-                buf
-            })
-            .to_owned();
+            .collect::<Vec<u8>>();
 
-        buf.extend(entries.len().to_string().bytes());
-        buf.push(b'\0');
-        buf.extend(entries);
+        storing::add_header(identifiers::TREE, &payload)
+    }
 
-        buf
+    fn sha(&self) -> String {
+        self.sha
+            .get_or_init(|| Sha1::from(self.serialize()).digest().to_string())
+            .to_owned()
     }
 }
 
 impl TreeEntry for Tree {
     fn serialize_as_entry(&self) -> Vec<u8> {
-        format!(
-            "{} {} {} {}",
-            identifiers::TREE,
-            0o755,
-            self.name,
-            self.sha()
-        )
-        .as_bytes()
-        .to_owned()
+        serialize_entry(TREE_MODE, &self.name, &self.sha())
     }
 
-    fn deserialize_as_entry(buf: Vec<u8>) -> EntryType {
-        if !buf.starts_with(identifiers::TREE.as_bytes()) {
-            panic!()
-        }
-
-        let mut parts = buf.split(|x| *x == b' ');
-
-        parts.next();
-        parts.next();
-        let name = parts.next().unwrap();
-        let sha = parts.next().unwrap();
-
-        let name = String::from_utf8(name.into()).unwrap();
-
-        let sha = String::from_utf8(sha.into()).unwrap();
-
-        let tree = Tree::new_from_object_file(&sha, Some(name));
-
-        EntryType::Tree(tree)
+    fn deserialize_as_entry(
+        _: &dyn ObjectDatabase,
+        _: u32,
+        name: String,
+        sha: String,
+    ) -> EntryType {
+        EntryType::Tree(Tree::unloaded(name, sha))
     }
 }
 
@@ -292,29 +481,38 @@ struct Entry {
 }
 
 impl Entry {
+    fn to_index_entry(&self, prefix: &str) -> index::Entry {
+        index::Entry::new(
+            self.mode,
+            format!("{}{}", prefix, self.file_name),
+            self.sha.to_owned(),
+        )
+    }
+
     /// Restores the actual file the entry is representing
     fn restore(&self, path: String) {
         let path = format!("{}/{}", path, self.file_name);
-        let mut file = fs::File::create(path).unwrap();
 
-        file.set_permissions(fs::Permissions::from_mode(self.mode))
-            .unwrap();
+        // The commit of a submodule isn't in the database, only its directory is created
+        if self.mode == index::GITLINK_MODE {
+            return write_wd_file(&path, self.mode, &[]);
+        }
 
         let blob = Blob::new_from_object_file(&self.sha, None);
-        let buf = blob.bytes();
-
-        file.write(buf).unwrap();
+        write_wd_file(&path, self.mode, blob.bytes());
     }
 }
 
 impl From<index::Entry> for Entry {
     fn from(entry: index::Entry) -> Self {
-        if entry.path().contains("/") {
+        if entry.path().contains('/') {
             panic!("Index entry contains path");
         }
 
+        // Git only records the type of an entry and whether a file is executable, not its full
+        // permissions
         Entry {
-            mode: entry.mode(),
+            mode: entry.git_mode(),
             file_name: String::from(entry.path()),
             sha: String::from(entry.sha()),
         }
@@ -323,46 +521,23 @@ impl From<index::Entry> for Entry {
 
 impl Storable for Entry {
     fn store(&self) {
-        let path = Paths::objects() + "/" + &self.sha;
-        let path = path::Path::new(&path);
-        if !path.exists() {
-            eprintln!("{} was not found while checking", path.to_string_lossy());
+        if self.mode != index::GITLINK_MODE && !FsDatabase.exists(&self.sha) {
+            eprintln!("{} was not found while checking", self.sha);
         }
     }
 }
 
 impl TreeEntry for Entry {
     fn serialize_as_entry(&self) -> Vec<u8> {
-        format!(
-            "{} {} {} {}",
-            identifiers::BLOB,
-            self.mode,
-            self.file_name,
-            self.sha
-        )
-        .as_bytes()
-        .to_owned()
+        serialize_entry(self.mode, &self.file_name, &self.sha)
     }
 
-    fn deserialize_as_entry(buf: Vec<u8>) -> EntryType {
-        if !buf.starts_with(identifiers::BLOB.as_bytes()) {
-            panic!()
-        }
-
-        let mut parts = buf.split(|x| *x == b' ');
-
-        parts.next();
-        let mode = parts.next().unwrap();
-        let file_name = parts.next().unwrap();
-        let sha = parts.next().unwrap();
-
-        let mode = String::from_utf8(mode.into()).unwrap();
-        let mode = u32::from_str_radix(&mode, ENCODING_RADIX).unwrap();
-
-        let file_name = String::from_utf8(file_name.into()).unwrap();
-
-        let sha = String::from_utf8(sha.into()).unwrap();
-
+    fn deserialize_as_entry(
+        _: &dyn ObjectDatabase,
+        mode: u32,
+        file_name: String,
+        sha: String,
+    ) -> EntryType {
         let entry = Entry {
             mode,
             file_name,
@@ -1,10 +1,18 @@
 const ROOT: &str = ".rgit";
 const OBJECTS: &str = "/objects";
+const PACKS: &str = "/pack";
 const REFS: &str = "/refs";
 const HEADS: &str = "/heads";
+const TAGS: &str = "/tags";
 const HEAD: &str = "/HEAD";
-const INDEX: &str = "/index.json";
+const INDEX: &str = "/index";
+const INDEX_JSON: &str = "/index.json";
+const MERGE_HEAD: &str = "/MERGE_HEAD";
+const CONFIG: &str = "/config";
+const GLOBAL_CONFIG: &str = "/.rgitconfig";
 const IGNORE: &str = ".gitignore";
+const INFO_EXCLUDE: &str = "/info/exclude";
+const GLOBAL_EXCLUDES: &str = "/rgit/ignore";
 
 /// Convinience Struct to centralize the directory paths
 pub struct Paths;
@@ -18,6 +26,10 @@ impl Paths {
         Paths::root() + OBJECTS
     }
 
+    pub fn packs() -> String {
+        Paths::objects() + PACKS
+    }
+
     pub fn refs() -> String {
         Paths::root() + REFS
     }
@@ -26,20 +38,63 @@ impl Paths {
         Paths::root() + INDEX
     }
 
+    /// Index of repositories created before rgit used git's binary index format
+    pub fn index_json() -> String {
+        Paths::root() + INDEX_JSON
+    }
+
+    pub fn config() -> String {
+        Paths::root() + CONFIG
+    }
+
+    /// Config file in the home directory shared by all repositories
+    pub fn global_config() -> Option<String> {
+        std::env::var("HOME").ok().map(|home| home + GLOBAL_CONFIG)
+    }
+
     pub fn dirs() -> Vec<String> {
-        vec![Paths::objects(), Paths::refs(), Paths::heads()]
+        vec![
+            Paths::objects(),
+            Paths::packs(),
+            Paths::refs(),
+            Paths::heads(),
+            Paths::tags(),
+        ]
     }
 
     pub fn ignore() -> String {
         String::from(IGNORE)
     }
 
+    /// Ignore patterns of the repository which aren't shared through a .gitignore file
+    pub fn info_exclude() -> String {
+        Paths::root() + INFO_EXCLUDE
+    }
+
+    /// Default ignore file shared by all repositories, used unless core.excludesFile is set
+    pub fn global_excludes() -> Option<String> {
+        std::env::var("XDG_CONFIG_HOME")
+            .ok()
+            .filter(|x| !x.is_empty())
+            .or_else(|| std::env::var("HOME").ok().map(|home| home + "/.config"))
+            .map(|config| config + GLOBAL_EXCLUDES)
+    }
+
     pub fn heads() -> String {
         Paths::refs() + HEADS
     }
 
+    pub fn tags() -> String {
+        Paths::refs() + TAGS
+    }
+
     #[allow(non_snake_case)]
     pub fn HEAD() -> String {
         Paths::root() + HEAD
     }
+
+    /// Holds the commit being merged while a merge with conflicts is in progress
+    pub fn merge_head() -> String {
+        Paths::root() + MERGE_HEAD
+    }
 }
@@ -0,0 +1,464 @@
+use crate::{diff, Config};
+use std::{cmp::Reverse, collections::HashMap};
+
+// Similarity scores are fractions of this, like in git, so that rounding matches git's
+const MAX_SCORE: usize = 60000;
+const DEFAULT_SCORE: usize = MAX_SCORE / 2;
+// Contents are compared in chunks of up to this many bytes, each ending at a newline if possible
+const MAX_CHUNK_LEN: usize = 64;
+const HASH_BASE: u32 = 107927;
+const CANDIDATES_PER_FILE: usize = 4;
+
+/// Where a file found by rename or copy detection comes from
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub from: String,
+    // Similarity of the two versions in percent
+    pub score: usize,
+    // Copies leave their source in place
+    pub copy: bool,
+}
+
+/// A version of a file taking part in rename detection
+pub struct Candidate<'a> {
+    pub path: &'a str,
+    pub sha: &'a str,
+    pub bytes: &'a [u8],
+}
+
+/// Decides how similar files have to be to count as renamed, and whether copies are detected
+#[derive(Debug, Clone, Copy)]
+pub struct RenameOptions {
+    // Minimum score of a rename, as a fraction of MAX_SCORE
+    threshold: usize,
+    copies: bool,
+}
+
+impl RenameOptions {
+    pub fn new(threshold: Option<usize>, copies: bool) -> RenameOptions {
+        RenameOptions {
+            threshold: threshold.unwrap_or(DEFAULT_SCORE),
+            copies,
+        }
+    }
+
+    /// Parses a similarity threshold like git does: "50%", or digits forming a fraction, so that
+    /// both "5" and "50" mean 50%
+    pub fn parse_threshold(value: &str) -> Result<usize, String> {
+        let (mut number, mut scale, mut dot) = (0, 1, false);
+
+        for (i, char) in value.char_indices() {
+            match char {
+                '.' if !dot => (scale, dot) = (1, true),
+                '%' if i == value.len() - 1 => scale = if dot { scale * 100 } else { 100 },
+                // Digits beyond the precision of scores don't matter
+                '0'..='9' if scale < 100000 => {
+                    scale *= 10;
+                    number = number * 10 + char.to_digit(10).unwrap() as usize;
+                }
+                '0'..='9' => (),
+                _ => return Err(format!("invalid similarity threshold {}", value)),
+            }
+        }
+
+        Ok(match number >= scale {
+            true => MAX_SCORE,
+            false => MAX_SCORE * number / scale,
+        })
+    }
+
+    /// Reads the first of the given config keys which is set, like diff.renames. Renames are
+    /// detected unless it is false, copies too if it is "copies"
+    pub fn configured(keys: &[&str]) -> Option<RenameOptions> {
+        let config = Config::load();
+        let value = keys.iter().find_map(|key| config.get(key));
+
+        match value.map(|x| x.to_lowercase()).as_deref() {
+            Some("false" | "no" | "off" | "0") => None,
+            Some("copies" | "copy") => Some(RenameOptions::new(None, true)),
+            _ => Some(RenameOptions::new(None, false)),
+        }
+    }
+
+    /// Pairs added files with the deleted files they were renamed from, and if copies are
+    /// detected with the modified files they were copied from. Files with the same content are
+    /// paired first, then those whose contents are the most similar, in the same order as git
+    /// so that equally good candidates are chosen alike. Returns the index of each added file
+    /// which was paired, together with where it comes from
+    pub fn detect(
+        &self,
+        deleted: &[Candidate],
+        modified: &[Candidate],
+        added: &[Candidate],
+    ) -> Vec<(usize, Rename)> {
+        // Sources sorted by path, each telling whether it was deleted
+        let mut sources: Vec<(&Candidate, bool)> = deleted.iter().map(|x| (x, true)).collect();
+        if self.copies {
+            sources.extend(modified.iter().map(|x| (x, false)));
+        }
+        sources.sort_by_key(|(x, _)| x.path);
+
+        // Source of each added file with its score, and how often each source is used
+        let mut pairs: Vec<Option<(usize, usize)>> = vec![None; added.len()];
+        let mut used = vec![0; sources.len()];
+        // Renames use up their source, copies can be made any number of times
+        let available = |used: &[usize], source: usize| self.copies || used[source] == 0;
+
+        for (target, file) in added.iter().enumerate() {
+            let source = sources
+                .iter()
+                .enumerate()
+                .filter(|(i, (x, _))| x.sha == file.sha && available(&used, *i))
+                .max_by_key(|(i, (x, _))| (same_name(x.path, file.path), Reverse(*i)))
+                .map(|(i, _)| i);
+            if let Some(source) = source {
+                pairs[target] = Some((source, MAX_SCORE));
+                used[source] += 1;
+            }
+        }
+
+        // Empty files are too common to tell anything about where a file comes from by its content
+        let fingerprints: Vec<Option<Fingerprint>> = sources
+            .iter()
+            .map(|(x, _)| (!x.bytes.is_empty()).then(|| Fingerprint::new(x.bytes)))
+            .collect();
+        let similarity = |source: usize, target: &Fingerprint, threshold: usize| {
+            fingerprints[source]
+                .as_ref()
+                .map_or(0, |x| x.similarity(target, threshold))
+        };
+
+        // Files moved to another directory usually keep their name, so files whose name is
+        // unique among the remaining ones are paired if they are clearly similar
+        if !self.copies {
+            let threshold = self.threshold + (MAX_SCORE - self.threshold) / 2;
+            let source_names = unique_names(
+                (0..sources.len())
+                    .filter(|i| available(&used, *i))
+                    .map(|i| (i, sources[i].0.path)),
+            );
+            let target_names = unique_names(
+                (0..added.len())
+                    .filter(|i| pairs[*i].is_none())
+                    .map(|i| (i, added[i].path)),
+            );
+
+            for (name, source) in source_names {
+                let (Some(source), Some(Some(target))) = (source, target_names.get(name)) else {
+                    continue;
+                };
+                let score = similarity(source, &Fingerprint::new(added[*target].bytes), threshold);
+                if score >= threshold && !added[*target].bytes.is_empty() {
+                    pairs[*target] = Some((source, score));
+                    used[source] += 1;
+                }
+            }
+        }
+
+        let mut candidates = Vec::new();
+        for (target, file) in added.iter().enumerate() {
+            if pairs[target].is_some() || file.bytes.is_empty() {
+                continue;
+            }
+
+            // Like git, only the best few sources of each file are kept, which decides the order
+            // of equally good candidates
+            let fingerprint = Fingerprint::new(file.bytes);
+            let mut best = [None; CANDIDATES_PER_FILE];
+            for (source, (other, _)) in sources.iter().enumerate() {
+                if !available(&used, source) {
+                    continue;
+                }
+                let score = similarity(source, &fingerprint, self.threshold);
+                let candidate = Some((score, same_name(other.path, file.path), source));
+
+                let worst =
+                    (0..best.len()).fold(0, |worst, i| match is_worse(best[i], best[worst]) {
+                        true => i,
+                        false => worst,
+                    });
+                if is_worse(best[worst], candidate) {
+                    best[worst] = candidate;
+                }
+            }
+
+            candidates.extend(
+                best.into_iter()
+                    .flatten()
+                    .map(|(score, name, source)| (score, name, target, source)),
+            );
+        }
+
+        // The most similar pairs win, files with the same name are preferred among equals. Unused
+        // deleted files are taken first, so that copies don't take away the source of a rename
+        candidates.sort_by_key(|(score, name, ..)| Reverse((*score, *name)));
+        for reuse in [false, self.copies] {
+            for (score, _, target, source) in candidates.iter().copied() {
+                if score >= self.threshold
+                    && pairs[target].is_none()
+                    && (reuse || (used[source] == 0 && sources[source].1))
+                {
+                    pairs[target] = Some((source, score));
+                    used[source] += 1;
+                }
+            }
+        }
+
+        // The last file made of a deleted file is its rename, the others are copies of it
+        pairs
+            .into_iter()
+            .enumerate()
+            .filter_map(|(target, pair)| {
+                let (source, score) = pair?;
+                let (file, deleted) = sources[source];
+                used[source] -= 1;
+
+                Some((
+                    target,
+                    Rename {
+                        from: file.path.to_owned(),
+                        score: score * 100 / MAX_SCORE,
+                        copy: !deleted || used[source] > 0,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Whether a candidate of score, same name and source is worse than another, no candidate at
+/// all is the worst
+fn is_worse(a: Option<(usize, bool, usize)>, b: Option<(usize, bool, usize)>) -> bool {
+    match (a, b) {
+        (None, b) => b.is_some(),
+        (Some(_), None) => false,
+        (Some(a), Some(b)) => (a.0, a.1) < (b.0, b.1),
+    }
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap()
+}
+
+/// Maps the file names of paths to the index of the only path with that name, or Option::None if
+/// more than one path has it
+fn unique_names<'a>(
+    paths: impl Iterator<Item = (usize, &'a str)>,
+) -> HashMap<&'a str, Option<usize>> {
+    let mut names: HashMap<&str, Option<usize>> = HashMap::new();
+    for (i, path) in paths {
+        names
+            .entry(basename(path))
+            .and_modify(|x| *x = None)
+            .or_insert(Some(i));
+    }
+
+    names
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    basename(a) == basename(b)
+}
+
+fn chunk_hash(accumulator: u32, carry: u32) -> u32 {
+    accumulator.wrapping_add(carry.wrapping_mul(0x61)) % HASH_BASE
+}
+
+/// Number of bytes of each chunk of a file's content by the chunk's hash, files are as similar
+/// as the amount of chunks they share, like git's diffcore-delta
+struct Fingerprint {
+    chunks: HashMap<u32, usize>,
+    size: usize,
+}
+
+impl Fingerprint {
+    fn new(bytes: &[u8]) -> Fingerprint {
+        let is_text = !diff::is_binary(bytes);
+        let mut chunks = HashMap::new();
+        let (mut accumulator, mut carry) = (0u32, 0u32);
+        let mut len = 0;
+
+        for (i, byte) in bytes.iter().enumerate() {
+            // Line endings don't make a difference in text files
+            if is_text && *byte == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+                continue;
+            }
+
+            let previous = accumulator;
+            accumulator = (accumulator << 7) ^ (carry >> 25);
+            carry = (carry << 7) ^ (previous >> 25);
+            accumulator = accumulator.wrapping_add(*byte as u32);
+            len += 1;
+
+            if len < MAX_CHUNK_LEN && *byte != b'\n' {
+                continue;
+            }
+            *chunks.entry(chunk_hash(accumulator, carry)).or_insert(0) += len;
+            (accumulator, carry, len) = (0, 0, 0);
+        }
+
+        // Content after the last newline is a chunk too
+        if len > 0 {
+            *chunks.entry(chunk_hash(accumulator, carry)).or_insert(0) += len;
+        }
+
+        Fingerprint {
+            chunks,
+            size: bytes.len(),
+        }
+    }
+
+    /// Score of how much of the other file's content is made of this file's, scores which
+    /// can't reach the threshold because the sizes differ too much are 0
+    fn similarity(&self, other: &Fingerprint, threshold: usize) -> usize {
+        let max_size = self.size.max(other.size);
+        let delta_size = max_size - self.size.min(other.size);
+        if max_size * (MAX_SCORE - threshold) < delta_size * MAX_SCORE || other.size == 0 {
+            return 0;
+        }
+
+        let copied: usize = self
+            .chunks
+            .iter()
+            .filter_map(|(hash, len)| other.chunks.get(hash).map(|x| *x.min(len)))
+            .sum();
+
+        copied * MAX_SCORE / max_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate<'a>(path: &'a str, sha: &'a str, bytes: &'a str) -> Candidate<'a> {
+        Candidate {
+            path,
+            sha,
+            bytes: bytes.as_bytes(),
+        }
+    }
+
+    fn renames(pairs: Vec<(usize, Rename)>) -> Vec<(usize, String, usize, bool)> {
+        pairs
+            .into_iter()
+            .map(|(i, x)| (i, x.from, x.score, x.copy))
+            .collect()
+    }
+
+    fn lines(edited: Option<usize>) -> String {
+        (0..10)
+            .map(|x| match Some(x) == edited {
+                true => format!("line {}!\n", x),
+                false => format!("line {}\n", x),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_thresholds_like_git() {
+        let percent =
+            |value: &str| RenameOptions::parse_threshold(value).map(|x| x * 100 / MAX_SCORE);
+
+        assert_eq!(percent("50%"), Ok(50));
+        assert_eq!(percent("5"), Ok(50));
+        assert_eq!(percent("50"), Ok(50));
+        assert_eq!(percent("75"), Ok(75));
+        assert_eq!(percent("0.5"), Ok(50));
+        assert_eq!(percent("100%"), Ok(100));
+        assert_eq!(percent("150%"), Ok(100));
+        assert_eq!(
+            RenameOptions::parse_threshold("1.5%"),
+            Ok(MAX_SCORE * 15 / 1000)
+        );
+        assert!(RenameOptions::parse_threshold("5%0").is_err());
+        assert!(RenameOptions::parse_threshold("half").is_err());
+    }
+
+    #[test]
+    fn pairs_identical_files() {
+        let deleted = [candidate("a.txt", "1111", "same\n")];
+        let added = [
+            candidate("b.txt", "2222", "other\n"),
+            candidate("c.txt", "1111", "same\n"),
+        ];
+
+        let pairs = RenameOptions::new(None, false).detect(&deleted, &[], &added);
+        assert_eq!(renames(pairs), [(1, "a.txt".to_owned(), 100, false)]);
+    }
+
+    #[test]
+    fn scores_similar_files_like_git() {
+        let (old, new) = (lines(None), lines(Some(5)));
+        let deleted = [candidate("a.txt", "1111", &old)];
+        let added = [candidate("b.txt", "2222", &new)];
+
+        let pairs = RenameOptions::new(None, false).detect(&deleted, &[], &added);
+        assert_eq!(renames(pairs), [(0, "a.txt".to_owned(), 88, false)]);
+
+        let threshold = RenameOptions::parse_threshold("90%").unwrap();
+        let pairs = RenameOptions::new(Some(threshold), false).detect(&deleted, &[], &added);
+        assert!(pairs.is_empty());
+    }
+
+    #[test]
+    fn detects_copies_of_modified_files() {
+        let (old, new) = (lines(None), lines(Some(5)));
+        let modified = [candidate("a.txt", "1111", &old)];
+        let added = [candidate("b.txt", "2222", &new)];
+
+        let pairs = RenameOptions::new(None, false).detect(&[], &modified, &added);
+        assert!(pairs.is_empty());
+
+        let pairs = RenameOptions::new(None, true).detect(&[], &modified, &added);
+        assert_eq!(renames(pairs), [(0, "a.txt".to_owned(), 88, true)]);
+    }
+
+    #[test]
+    fn renames_to_the_last_of_several_copies() {
+        let deleted = [candidate("a.txt", "1111", "same\n")];
+        let added = [
+            candidate("b.txt", "1111", "same\n"),
+            candidate("c.txt", "1111", "same\n"),
+        ];
+
+        let pairs = RenameOptions::new(None, false).detect(&deleted, &[], &added);
+        assert_eq!(renames(pairs), [(0, "a.txt".to_owned(), 100, false)]);
+
+        let pairs = RenameOptions::new(None, true).detect(&deleted, &[], &added);
+        assert_eq!(
+            renames(pairs),
+            [
+                (0, "a.txt".to_owned(), 100, true),
+                (1, "a.txt".to_owned(), 100, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_files_with_the_same_name() {
+        let (old, new) = (lines(None), lines(Some(5)));
+        let deleted = [
+            candidate("old/a.txt", "1111", &old),
+            candidate("old/b.txt", "2222", &old),
+        ];
+        let added = [candidate("new/b.txt", "3333", &new)];
+
+        let pairs = RenameOptions::new(None, false).detect(&deleted, &[], &added);
+        assert_eq!(renames(pairs), [(0, "old/b.txt".to_owned(), 88, false)]);
+    }
+
+    #[test]
+    fn compares_content_after_the_last_newline() {
+        let score = |a: &str, b: &str| {
+            Fingerprint::new(a.as_bytes()).similarity(&Fingerprint::new(b.as_bytes()), 0)
+        };
+
+        assert_eq!(score("no newline", "no newline"), MAX_SCORE);
+        assert!(score("line\nfoo", "line\nbar") < MAX_SCORE);
+        assert_eq!(
+            score("line\r\n", "line\n"),
+            score("line\n", "line\n") * 5 / 6
+        );
+    }
+}
@@ -1,13 +1,61 @@
+use crate::objects::{FsDatabase, ObjectDatabase};
+use sha1_smol::Sha1;
+
 /// Trait for all Structs which have to do fs operations
 pub trait Storable {
     fn store(&self);
 }
 
-/// Trait used for all Structs which represent actual objects in the .rgit/objects directory
-pub trait Object {
-    /// Retrieves the object from its serialized representation as a file in the objects directory
-    fn new_from_object_file(sha: &str, name: Option<String>) -> Self;
+/// Trait used for all Structs which represent actual objects in an object database
+pub trait Object: Sized {
+    /// Retrieves the object from its serialized representation in the .rgit/objects directory
+    fn new_from_object_file(sha: &str, name: Option<String>) -> Self {
+        Self::read_from(&FsDatabase, sha, name)
+    }
+
+    /// Retrieves the object from its serialized representation in a database
+    fn read_from(db: &dyn ObjectDatabase, sha: &str, name: Option<String>) -> Self;
+
+    /// Writes the object, and all objects it holds which aren't stored yet, to a database
+    fn write_to(&self, db: &dyn ObjectDatabase);
 
-    /// Serializes the object to bytes to be able to be stored
+    /// Serializes the object to bytes to be able to be stored, including the `type len\0` header
     fn serialize(&self) -> Vec<u8>;
+
+    /// Hashes the serialized object, header included, the same way git does
+    fn sha(&self) -> String {
+        Sha1::from(self.serialize()).digest().to_string()
+    }
+}
+
+/// Prepends the `type len\0` header git puts in front of every object's payload
+pub fn add_header(kind: &str, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(payload.len() + kind.len() + 12);
+
+    buf.extend(kind.bytes());
+    buf.push(b' ');
+    buf.extend(payload.len().to_string().bytes());
+    buf.push(b'\0');
+    buf.extend_from_slice(payload);
+
+    buf
+}
+
+/// Splits a serialized object into its type and payload, checking the length stored in the header
+pub fn split_header(buf: &[u8]) -> (&str, &[u8]) {
+    let null_i = buf
+        .iter()
+        .position(|x| *x == b'\0')
+        .expect("Object is missing its header");
+    let header = std::str::from_utf8(&buf[..null_i]).expect("Object header is not valid utf-8");
+    let (kind, len) = header.split_once(' ').expect("Malformed object header");
+
+    let payload = &buf[null_i + 1..];
+    assert_eq!(
+        len.parse::<usize>().expect("Malformed object length"),
+        payload.len(),
+        "Object length does not match its header"
+    );
+
+    (kind, payload)
 }